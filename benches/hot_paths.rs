@@ -0,0 +1,80 @@
+//! Benchmarks of the paths every credentials request goes through. See the
+//! README for the performance budgets these are held to.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use id_contact_comm_common::{
+    credentials::{collect_credentials, render_credentials, RenderType},
+    test_helpers::{encrypt_auth_result, sign_guest_token, test_config, test_guest_token},
+    types::{FromPlatformJwt, GuestAuthResult, GuestToken},
+};
+use std::collections::HashMap;
+
+/// Auth results of `guests` guests with `attributes` attributes each
+fn auth_results(guests: usize, attributes: usize) -> Vec<GuestAuthResult> {
+    (0..guests)
+        .map(|guest| {
+            let attributes: HashMap<String, String> = (0..attributes)
+                .map(|i| {
+                    (
+                        format!("attribute_{}", i),
+                        format!("value {} of {}", i, guest),
+                    )
+                })
+                .collect();
+            GuestAuthResult::new(encrypt_auth_result(attributes))
+                .with_purpose("test_purpose")
+                .with_name(format!("Guest {}", guest))
+        })
+        .collect()
+}
+
+fn bench_collect_credentials(c: &mut Criterion) {
+    let config = test_config();
+    let mut group = c.benchmark_group("collect_credentials");
+    for &(guests, attributes) in [(1, 5), (10, 5), (10, 20), (50, 20)].iter() {
+        let results = auth_results(guests, attributes);
+        group.bench_with_input(
+            BenchmarkId::new(format!("{} attributes", attributes), guests),
+            &results,
+            |b, results| b.iter(|| collect_credentials(black_box(results), &config).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn bench_render_credentials(c: &mut Criterion) {
+    let config = test_config();
+    let mut group = c.benchmark_group("render_credentials");
+    for &guests in [1, 10, 50].iter() {
+        let results = auth_results(guests, 20);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(guests),
+            &results,
+            |b, results| {
+                b.iter_batched(
+                    || collect_credentials(results, &config).unwrap(),
+                    |credentials| render_credentials(credentials, RenderType::Html).unwrap(),
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_validate_guest_token(c: &mut Criterion) {
+    let config = test_config();
+    let validator = config.auth_during_comm_config().guest_validator();
+    let jwt = sign_guest_token(&test_guest_token("room"));
+    c.bench_function("validate_guest_token", |b| {
+        b.iter(|| GuestToken::from_platform_jwt(black_box(&jwt), validator).unwrap())
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_collect_credentials,
+    bench_render_credentials,
+    bench_validate_guest_token
+);
+criterion_main!(benches);
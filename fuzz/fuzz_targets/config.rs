@@ -0,0 +1,7 @@
+#![no_main]
+use id_contact_comm_common::config::Config;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    let _ = Config::from_yaml(source);
+});
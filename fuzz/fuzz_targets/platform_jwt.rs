@@ -0,0 +1,16 @@
+#![no_main]
+use id_contact_comm_common::{
+    test_helpers::test_config,
+    types::{FromPlatformJwt, GuestToken, HostToken},
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|jwt: &str| {
+    let config = test_config();
+    let config = config.auth_during_comm_config();
+    let _ = GuestToken::from_platform_jwt(jwt, config.guest_validator());
+    let _ = HostToken::from_platform_jwt(jwt, config.host_validator());
+    let _ = GuestToken::from_instance_platform_jwt(jwt, |instance| {
+        config.guest_validator_for(instance)
+    });
+});
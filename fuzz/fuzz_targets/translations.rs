@@ -0,0 +1,10 @@
+#![no_main]
+use id_contact_comm_common::translations::{interpolate, Translations};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|source: &str| {
+    if let Ok(translations) = Translations::parse(source) {
+        let _ = translations.format("room", &[("name", source)]);
+    }
+    let _ = interpolate(source, &[("name", "value")]);
+});
@@ -0,0 +1,276 @@
+use crate::{error::Error, util::constant_time_eq};
+use id_contact_jwt::SignKeyConfig;
+use josekit::jws::JwsVerifier;
+use serde::Deserialize;
+use std::{convert::TryFrom, fmt::Debug, time::SystemTime};
+use zeroize::Zeroizing;
+
+/// Static API key, hidden from logs and wiped from memory when dropped
+#[derive(Deserialize)]
+#[serde(from = "String")]
+struct ApiKey(Zeroizing<String>);
+
+impl From<String> for ApiKey {
+    fn from(value: String) -> Self {
+        ApiKey(Zeroizing::new(value))
+    }
+}
+
+impl Debug for ApiKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiKey").finish()
+    }
+}
+
+/// Configuration of bearer token authentication for server-to-server calls
+#[derive(Deserialize, Debug, Default)]
+pub struct RawApiAuthConfig {
+    /// Public key with which platform backends sign their bearer JWTs
+    token_pubkey: Option<SignKeyConfig>,
+    /// Static API keys accepted as bearer tokens
+    #[serde(default)]
+    api_keys: Vec<ApiKey>,
+}
+
+#[derive(Debug, Default)]
+pub struct ApiAuthConfig {
+    token_verifier: Option<Box<dyn JwsVerifier>>,
+    api_keys: Vec<ApiKey>,
+}
+
+impl TryFrom<RawApiAuthConfig> for ApiAuthConfig {
+    type Error = Error;
+    fn try_from(raw_config: RawApiAuthConfig) -> Result<ApiAuthConfig, Error> {
+        Ok(ApiAuthConfig {
+            token_verifier: raw_config
+                .token_pubkey
+                .map(Box::<dyn JwsVerifier>::try_from)
+                .transpose()?,
+            api_keys: raw_config.api_keys,
+        })
+    }
+}
+
+/// A validated bearer token of a platform backend. Missing or invalid tokens
+/// are rejected with [`Error::Unauthorized`]. Rocket drops the errors of
+/// failing guards, so handlers take `Result<ApiToken, Error>` and return the
+/// error to send its `WWW-Authenticate` header along.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiToken {
+    /// One of the configured static API keys
+    ApiKey,
+    /// A JWT signed with the configured key, with its subject if any
+    Jwt { subject: Option<String> },
+}
+
+impl ApiAuthConfig {
+    /// Whether any means of API authentication is configured
+    pub fn is_enabled(&self) -> bool {
+        self.token_verifier.is_some() || !self.api_keys.is_empty()
+    }
+
+    /// Validate the value of an `Authorization` header
+    pub fn validate(&self, authorization: &str) -> Result<ApiToken, Error> {
+        let token = authorization
+            .strip_prefix("Bearer ")
+            .ok_or(Error::Unauthorized("Missing bearer token"))?
+            .trim();
+
+        if self
+            .api_keys
+            .iter()
+            .any(|key| constant_time_eq(&key.0, token))
+        {
+            return Ok(ApiToken::ApiKey);
+        }
+
+        let verifier = self
+            .token_verifier
+            .as_deref()
+            .ok_or(Error::Unauthorized("Invalid bearer token"))?;
+        let (payload, _) = josekit::jwt::decode_with_verifier(token, verifier)
+            .map_err(|_| Error::Unauthorized("Invalid bearer token"))?;
+        match payload.expires_at() {
+            Some(expires_at) if expires_at > SystemTime::now() => Ok(ApiToken::Jwt {
+                subject: payload.subject().map(|subject| subject.to_owned()),
+            }),
+            Some(_) => Err(Error::Unauthorized("Expired bearer token")),
+            None => Err(Error::Unauthorized("Bearer token without expiry")),
+        }
+    }
+}
+
+#[cfg(feature = "rocket")]
+mod guard {
+    use super::ApiToken;
+    use crate::{config::Config, error::Error};
+    use rocket::{
+        http::Status,
+        request::{FromRequest, Outcome},
+        Request,
+    };
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for ApiToken {
+        type Error = Error;
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let config = match request.rocket().state::<Config>() {
+                Some(config) if config.api_auth().is_enabled() => config.api_auth(),
+                _ => {
+                    return Outcome::Failure((
+                        Status::Forbidden,
+                        Error::Forbidden("API authentication not configured"),
+                    ))
+                }
+            };
+
+            match config.validate(request.headers().get_one("Authorization").unwrap_or("")) {
+                Ok(token) => Outcome::Success(token),
+                Err(e) => Outcome::Failure((
+                    Status::from_code(e.status_code()).unwrap_or(Status::Unauthorized),
+                    e,
+                )),
+            }
+        }
+    }
+}
+
+/// Extracts a validated bearer token in axum handlers, using the
+/// [`Config`](crate::config::Config) added to the router as `Extension<Arc<Config>>`
+#[cfg(feature = "axum")]
+mod extractor {
+    use super::ApiToken;
+    use crate::{config::Config, error::Error};
+    use axum::extract::{FromRequest, RequestParts};
+    use std::sync::Arc;
+
+    #[axum::async_trait]
+    impl<B: Send> FromRequest<B> for ApiToken {
+        type Rejection = Error;
+
+        async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+            let config = match request.extensions().get::<Arc<Config>>() {
+                Some(config) if config.api_auth().is_enabled() => config.api_auth(),
+                _ => return Err(Error::Forbidden("API authentication not configured")),
+            };
+
+            let authorization = request
+                .headers()
+                .get(axum::http::header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("");
+            config.validate(authorization)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "rocket", feature = "auth_during_comm"))]
+mod tests {
+    use super::*;
+    use crate::{config::Config, test_helpers::test_raw_config};
+    use rocket::{get, http::Header, http::Status, local::blocking::Client};
+
+    #[get("/")]
+    fn protected(token: Result<ApiToken, Error>) -> Result<String, Error> {
+        Ok(format!("{:?}", token?))
+    }
+
+    #[test]
+    fn test_api_token_guard() {
+        let mut raw_config = test_raw_config();
+        let api_auth: serde_yaml::Value = serde_yaml::from_str("api_keys: [key1, key2]").unwrap();
+        raw_config.insert("api_auth".into(), api_auth);
+        let config: Config =
+            serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+        assert_eq!(
+            format!("{:?}", config.api_auth().api_keys),
+            "[ApiKey, ApiKey]"
+        );
+
+        let rocket = rocket::build()
+            .manage(config)
+            .mount("/", rocket::routes![protected]);
+        let client = Client::tracked(rocket).unwrap();
+
+        let response = client
+            .get("/")
+            .header(Header::new("Authorization", "Bearer key2"))
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), "ApiKey");
+
+        let response = client
+            .get("/")
+            .header(Header::new("Authorization", "Bearer key3"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(
+            response.headers().get_one("WWW-Authenticate"),
+            Some("Bearer")
+        );
+
+        let response = client.get("/").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(
+            response.headers().get_one("WWW-Authenticate"),
+            Some("Bearer")
+        );
+
+        // A valid token is only of use where API authentication is configured
+        let rocket = rocket::build()
+            .manage(crate::test_helpers::test_config())
+            .mount("/", rocket::routes![protected]);
+        let client = Client::tracked(rocket).unwrap();
+        let response = client
+            .get("/")
+            .header(Header::new("Authorization", "Bearer key2"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Forbidden);
+    }
+}
+
+#[cfg(all(test, feature = "axum", feature = "auth_during_comm"))]
+mod axum_tests {
+    use super::*;
+    use crate::{
+        config::Config,
+        test_helpers::{serve_router, test_raw_config},
+    };
+    use axum::{routing::get, Extension, Router};
+    use std::sync::Arc;
+
+    async fn protected(token: ApiToken) -> String {
+        format!("{:?}", token)
+    }
+
+    #[test]
+    fn test_api_token_extractor() {
+        let mut raw_config = test_raw_config();
+        let api_auth: serde_yaml::Value = serde_yaml::from_str("api_keys: [key1, key2]").unwrap();
+        raw_config.insert("api_auth".into(), api_auth);
+        let config: Config =
+            serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+        let router = Router::new()
+            .route("/", get(protected))
+            .layer(Extension(Arc::new(config)));
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let url = serve_router(router);
+            let client = reqwest::Client::new();
+
+            let response = client.get(&url).bearer_auth("key2").send().await.unwrap();
+            assert_eq!(response.text().await.unwrap(), "ApiKey");
+
+            let response = client.get(&url).bearer_auth("key3").send().await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()["www-authenticate"], "Bearer");
+
+            let response = client.get(&url).send().await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+        });
+    }
+}
@@ -0,0 +1,303 @@
+use crate::{
+    aws::{hex, AwsCredentials},
+    error::Error,
+    jwt::JwtError,
+    util::{encode_path_segment, random_string},
+};
+use id_contact_jwt::EncryptionKeyConfig;
+use josekit::jwe::{JweDecrypter, JweEncrypter, JweHeader};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    convert::TryFrom,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where archives are written
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ArchiveTarget {
+    /// A directory on the local filesystem, such as a mounted volume
+    Filesystem { directory: PathBuf },
+    /// An S3 bucket, accessed with the region and credentials in the
+    /// standard AWS environment variables
+    S3 {
+        bucket: String,
+        /// Prefix of the object keys, such as `sessions/`
+        #[serde(default)]
+        prefix: String,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RawArchiveConfig {
+    /// Public key archives are encrypted for
+    encryption_pubkey: EncryptionKeyConfig,
+    target: ArchiveTarget,
+    /// Keep the encrypted authentication results in the archive. Off by
+    /// default, as statistics do not need them.
+    #[serde(default)]
+    include_auth_results: bool,
+}
+
+/// Summary of a removed session, for statistics on usage per purpose
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionSummary {
+    pub purpose: String,
+    pub instance: String,
+    pub domain: String,
+    pub authenticated: bool,
+    /// Whether the guest left the room before the session was removed
+    pub left: bool,
+    /// Unix time of the last activity in the session's room
+    pub last_activity: i64,
+    /// The still encrypted authentication result, only if configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_result: Option<String>,
+}
+
+/// Summaries of the sessions removed in a single database clean-up
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionArchive {
+    /// Unix time at which the archive was created
+    pub archived_at: u64,
+    pub sessions: Vec<SessionSummary>,
+}
+
+impl SessionArchive {
+    /// Serialize the archive and encrypt it as a JWE for the given key
+    pub fn encrypt(&self, encrypter: &dyn JweEncrypter) -> Result<String, Error> {
+        let mut header = JweHeader::new();
+        header.set_content_type("application/json");
+        header.set_content_encryption("A256GCM");
+
+        let payload = serde_json::to_vec(self)?;
+        Ok(
+            josekit::jwe::serialize_compact(&payload, &header, encrypter)
+                .map_err(JwtError::from)?,
+        )
+    }
+
+    /// Decrypt an archive created with [`SessionArchive::encrypt`]
+    pub fn decrypt(archive: &str, decrypter: &dyn JweDecrypter) -> Result<Self, Error> {
+        let (payload, _) =
+            josekit::jwe::deserialize_compact(archive, decrypter).map_err(JwtError::from)?;
+        Ok(serde_json::from_slice(&payload)?)
+    }
+}
+
+/// Writes encrypted summaries of sessions to cold storage before they are
+/// removed from the session database
+pub struct Archiver {
+    encrypter: Box<dyn JweEncrypter>,
+    target: ArchiveTarget,
+    include_auth_results: bool,
+    credentials: Option<AwsCredentials>,
+    client: reqwest::Client,
+}
+
+impl TryFrom<RawArchiveConfig> for Archiver {
+    type Error = Error;
+    fn try_from(config: RawArchiveConfig) -> Result<Archiver, Error> {
+        let credentials = match config.target {
+            ArchiveTarget::S3 { .. } => Some(AwsCredentials::from_env()?),
+            ArchiveTarget::Filesystem { .. } => None,
+        };
+        Ok(Archiver {
+            encrypter: Box::<dyn JweEncrypter>::try_from(config.encryption_pubkey)?,
+            target: config.target,
+            include_auth_results: config.include_auth_results,
+            credentials,
+            client: reqwest::Client::new(),
+        })
+    }
+}
+
+impl std::fmt::Debug for Archiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Archiver")
+            .field("target", &self.target)
+            .field("include_auth_results", &self.include_auth_results)
+            .finish()
+    }
+}
+
+impl Archiver {
+    /// Encrypt the summaries and write them to the target as a new archive,
+    /// leaving out the authentication results unless configured otherwise
+    pub async fn write(&self, mut sessions: Vec<SessionSummary>) -> Result<(), Error> {
+        if !self.include_auth_results {
+            for session in sessions.iter_mut() {
+                session.auth_result = None;
+            }
+        }
+        let archived_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let count = sessions.len();
+        let archive = SessionArchive {
+            archived_at,
+            sessions,
+        }
+        .encrypt(self.encrypter.as_ref())?;
+        let name = format!("sessions-{}-{}.jwe", archived_at, random_string(8));
+
+        match &self.target {
+            ArchiveTarget::Filesystem { directory } => {
+                let path = directory.join(&name);
+                tokio::fs::write(&path, archive).await.map_err(|e| {
+                    log::error!("Could not write archive {}: {}", path.display(), e);
+                    Error::from(e)
+                })?;
+            }
+            ArchiveTarget::S3 { bucket, prefix } => {
+                self.upload(bucket, &format!("{}{}", prefix, name), archive)
+                    .await?;
+            }
+        }
+        log::info!("Archived {} sessions as {}", count, name);
+        Ok(())
+    }
+
+    async fn upload(&self, bucket: &str, key: &str, archive: String) -> Result<(), Error> {
+        let credentials = self
+            .credentials
+            .as_ref()
+            .ok_or(Error::Config("AWS credentials not configured"))?;
+        let host = format!("{}.s3.{}.amazonaws.com", bucket, credentials.region);
+        let path: String = key
+            .split('/')
+            .map(encode_path_segment)
+            .collect::<Vec<_>>()
+            .join("/");
+        let path = format!("/{}", path);
+
+        let headers = credentials.sign(
+            "PUT",
+            "s3",
+            &host,
+            &path,
+            vec![
+                ("content-type", "application/jose".to_string()),
+                (
+                    "x-amz-content-sha256",
+                    hex(&Sha256::digest(archive.as_bytes())),
+                ),
+            ],
+            archive.as_bytes(),
+        );
+        let mut request = self
+            .client
+            .put(format!("https://{}{}", host, path))
+            .timeout(UPLOAD_TIMEOUT)
+            .body(archive);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    pub fn include_auth_results(&self) -> bool {
+        self.include_auth_results
+    }
+}
+
+lazy_static! {
+    static ref ARCHIVER: RwLock<Option<Arc<Archiver>>> = RwLock::new(None);
+}
+
+/// Replace the archiver used when cleaning the session database. `None`
+/// disables archiving.
+pub fn configure_archiver(archiver: Option<Archiver>) {
+    set_archiver(archiver.map(Arc::new));
+}
+
+pub(crate) fn set_archiver(archiver: Option<Arc<Archiver>>) {
+    *ARCHIVER.write().expect("Archiver lock poisoned") = archiver;
+}
+
+/// The configured archiver, if any
+pub fn archiver() -> Option<Arc<Archiver>> {
+    ARCHIVER.read().expect("Archiver lock poisoned").clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{test_config, EC_PUBKEY};
+
+    #[test]
+    fn test_filesystem_archive() {
+        let directory =
+            std::env::temp_dir().join(format!("comm-common-archive-{}", random_string(8)));
+        std::fs::create_dir(&directory).unwrap();
+        let archiver = Archiver::try_from(RawArchiveConfig {
+            encryption_pubkey: serde_yaml::from_str(EC_PUBKEY).unwrap(),
+            target: ArchiveTarget::Filesystem {
+                directory: directory.clone(),
+            },
+            include_auth_results: false,
+        })
+        .unwrap();
+
+        let summary = SessionSummary {
+            purpose: "report_move".to_string(),
+            instance: "test".to_string(),
+            domain: "guest".to_string(),
+            authenticated: true,
+            left: false,
+            last_activity: 1_600_000_000,
+            auth_result: Some("encrypted".to_string()),
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime
+            .block_on(archiver.write(vec![summary.clone()]))
+            .unwrap();
+
+        let entries: Vec<_> = std::fs::read_dir(&directory).unwrap().flatten().collect();
+        assert_eq!(entries.len(), 1);
+        let archive = std::fs::read_to_string(entries[0].path()).unwrap();
+        let config = test_config();
+        let archive =
+            SessionArchive::decrypt(&archive, config.keys().current().decrypter()).unwrap();
+        assert_eq!(
+            archive.sessions,
+            vec![SessionSummary {
+                auth_result: None,
+                ..summary
+            }]
+        );
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_filesystem_archive_failure() {
+        let archiver = Archiver::try_from(RawArchiveConfig {
+            encryption_pubkey: serde_yaml::from_str(EC_PUBKEY).unwrap(),
+            target: ArchiveTarget::Filesystem {
+                directory: std::env::temp_dir()
+                    .join(format!("comm-common-missing-{}", random_string(8))),
+            },
+            include_auth_results: false,
+        })
+        .unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let error = runtime.block_on(archiver.write(vec![])).unwrap_err();
+        assert!(matches!(error, Error::Io(_)));
+        assert_eq!(error.status_code(), 500);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{error::Error, util::random_token};
+use hmac::{Hmac, Mac, NewMac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::{convert::TryFrom, fmt::Debug};
+use zeroize::Zeroizing;
+
+/// Minimum length of an attr_id: 22 URL-safe base64 characters carry 128
+/// bits when random
+pub const MIN_ATTR_ID_LENGTH: usize = 22;
+
+/// Generates the attr_ids matching delivered attributes to sessions. Anyone
+/// knowing an attr_id can deliver attributes for its session, so they must
+/// not be guessable.
+pub trait AttrIdGenerator: Debug + Send + Sync {
+    /// attr_id for the session with the given id
+    fn generate(&self, session_id: &str) -> String;
+}
+
+/// Generates 256 bit random attr_ids. The default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomAttrIds;
+
+impl AttrIdGenerator for RandomAttrIds {
+    fn generate(&self, _session_id: &str) -> String {
+        random_token(32)
+    }
+}
+
+/// Derives attr_ids from the session id with HMAC-SHA256, so every plugin
+/// instance arrives at the same attr_id for a session without sharing state
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "String")]
+pub struct HmacAttrIds {
+    secret: Zeroizing<Vec<u8>>,
+}
+
+impl TryFrom<String> for HmacAttrIds {
+    type Error = String;
+    fn try_from(secret: String) -> Result<Self, String> {
+        if secret.len() < 32 {
+            return Err("attr_id secret must be at least 32 bytes".to_string());
+        }
+        Ok(HmacAttrIds {
+            secret: Zeroizing::new(secret.into_bytes()),
+        })
+    }
+}
+
+impl Debug for HmacAttrIds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HmacAttrIds").finish()
+    }
+}
+
+impl AttrIdGenerator for HmacAttrIds {
+    fn generate(&self, session_id: &str) -> String {
+        // HMAC accepts keys of any length
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+        mac.update(b"attr_id\n");
+        mac.update(session_id.as_bytes());
+        base64::encode_config(mac.finalize().into_bytes(), base64::URL_SAFE_NO_PAD)
+    }
+}
+
+/// Check that an attr_id is long enough and URL-safe, as those generated by
+/// [`RandomAttrIds`] and [`HmacAttrIds`] are
+pub fn check_attr_id(attr_id: &str) -> Result<(), Error> {
+    if attr_id.len() < MIN_ATTR_ID_LENGTH {
+        return Err(Error::BadRequest("attr_id is too short"));
+    }
+    if !attr_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(Error::BadRequest("attr_id contains invalid characters"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attr_id_generators() {
+        let random = RandomAttrIds.generate("session");
+        assert!(check_attr_id(&random).is_ok());
+        assert_ne!(random, RandomAttrIds.generate("session"));
+
+        assert!(HmacAttrIds::try_from("short".to_string()).is_err());
+        let secret = "0123456789abcdef0123456789abcdef".to_string();
+        let hmac = HmacAttrIds::try_from(secret.clone()).unwrap();
+        assert!(!format!("{:?}", hmac).contains(&secret));
+        let derived = hmac.generate("session");
+        assert!(check_attr_id(&derived).is_ok());
+        assert_eq!(derived, hmac.generate("session"));
+        assert_ne!(derived, hmac.generate("other"));
+
+        assert!(check_attr_id("attr").is_err());
+        assert!(check_attr_id("0123456789abcdef01234/../..").is_err());
+        assert!(check_attr_id(&crate::util::random_string(16)).is_err());
+    }
+}
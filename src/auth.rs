@@ -0,0 +1,236 @@
+use crate::{
+    credentials::{RenderType, RenderedContent},
+    error::Error,
+    retry::send_with_retry,
+    retry::RetryConfig,
+    templates,
+    translations::Translations,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Server-side store of logged in hosts and their tokens
+pub mod host_session;
+/// Identity of the logged in host
+pub mod host_user;
+/// Host login by disclosing attributes with IRMA or Yivi
+pub mod irma;
+/// OAuth state carrying the page to return to after login
+pub mod login_state;
+/// Validation of OpenID Connect ID tokens
+pub mod oidc;
+/// Proof Key for Code Exchange (RFC 7636) for the authorization code flow
+pub mod pkce;
+/// Configuration of the providers hosts can log in with
+pub mod provider;
+#[cfg(feature = "saml")]
+/// Host login with SAML 2.0 identity providers
+pub mod saml;
+
+#[cfg(feature = "saml")]
+pub use self::saml::SamlProvider;
+pub use self::{
+    host_session::{configure_host_sessions, HostSessionConfig},
+    host_user::HostUser,
+    irma::IrmaProvider,
+    login_state::LoginState,
+    oidc::{IdTokenClaims, OidcProvider},
+    pkce::PkceVerifier,
+    provider::{AuthProvider, OAuthProvider},
+};
+
+/// Response of an OAuth2 token endpoint
+#[derive(Deserialize, Debug, Clone)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub token_type: String,
+    pub expires_in: Option<u64>,
+    pub refresh_token: Option<String>,
+    pub id_token: Option<String>,
+}
+
+/// Client credentials of the plugin at an OAuth2 provider
+#[derive(Debug, Clone)]
+pub struct OAuthClient<'a> {
+    pub client_id: &'a str,
+    pub client_secret: &'a str,
+    pub token_url: &'a str,
+    pub redirect_uri: &'a str,
+}
+
+/// Exchange an authorization code for tokens, proving with the PKCE verifier
+/// that this plugin started the authorization request
+pub async fn exchange_code(
+    client: &OAuthClient<'_>,
+    code: &str,
+    verifier: &PkceVerifier,
+    retry: &RetryConfig,
+) -> Result<TokenResponse, Error> {
+    let request = reqwest::Client::new().post(client.token_url).form(&[
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", client.redirect_uri),
+        ("client_id", client.client_id),
+        ("client_secret", client.client_secret),
+        ("code_verifier", verifier.as_str()),
+    ]);
+
+    let response = send_with_retry(request, retry).await?;
+    if !response.status().is_success() {
+        log::warn!("Token exchange failed with status {}", response.status());
+        return Err(Error::Forbidden("Token exchange failed"));
+    }
+    Ok(response.json().await?)
+}
+
+/// Log the host out, revoking the tokens stored at login at the provider if
+/// it supports that. Returns the URL to redirect the host to if their session
+/// at the provider has to be ended as well. The host is logged out here even
+/// if revocation fails, which is only logged.
+pub async fn logout(
+    cookies: &rocket::http::CookieJar<'_>,
+    provider: &OAuthProvider,
+    post_logout_redirect_uri: &str,
+    retry: &RetryConfig,
+) -> Result<Option<String>, Error> {
+    let tokens = match host_user::HostUser::logout(cookies) {
+        Some(tokens) => tokens,
+        None => {
+            return Ok(provider
+                .oidc
+                .end_session_url(None, post_logout_redirect_uri))
+        }
+    };
+    if let Some(refresh_token) = &tokens.refresh_token {
+        // Revoking the refresh token also revokes its access tokens at most providers
+        if let Err(e) = provider.revoke_token(refresh_token, retry).await {
+            log::warn!("Could not revoke refresh token at logout: {}", e);
+        }
+    }
+    if let Err(e) = provider.revoke_token(&tokens.access_token, retry).await {
+        log::warn!("Could not revoke access token at logout: {}", e);
+    }
+
+    Ok(provider
+        .oidc
+        .end_session_url(tokens.id_token.as_deref(), post_logout_redirect_uri))
+}
+
+/// Where a host who is not logged in can do so, as sent to JSON clients
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LoginUrl {
+    pub login_url: String,
+}
+
+/// Ask a host who is not logged in to do so, with a 401 response. JSON
+/// clients, such as single page apps embedded in a platform, get the login
+/// URL to redirect to themselves, browsers a page linking to it.
+pub fn render_login(
+    login_url: &str,
+    render_type: RenderType,
+    translations: &Translations,
+) -> Result<RenderedContent, Error> {
+    let content = if render_type == RenderType::Json {
+        serde_json::to_string(&LoginUrl {
+            login_url: login_url.to_string(),
+        })?
+    } else {
+        let mut context = templates::localized_context(translations);
+        context.insert("login_url", login_url);
+        templates::render("login_required.html", context)?
+    };
+    Ok(RenderedContent::new(content, render_type).with_status(401))
+}
+
+/// Render the page shown to the host after logging in, or its JSON
+/// equivalent, linking to the page to continue to if any
+pub fn render_login_result(
+    host_user: &HostUser,
+    next: Option<&str>,
+    render_type: RenderType,
+    translations: &Translations,
+) -> Result<RenderedContent, Error> {
+    if render_type == RenderType::Json {
+        let content = json!({ "host": host_user, "next": next }).to_string();
+        return Ok(RenderedContent::new(content, render_type));
+    }
+
+    let mut context = templates::localized_context(translations);
+    context.insert("host", host_user);
+    context.insert("next", &next);
+    let content = templates::render("login_result.html", context)?;
+    Ok(RenderedContent::new(content, render_type))
+}
+
+/// Render the page shown to the host after logging out, or its JSON
+/// equivalent, linking to the provider's end session URL if any
+pub fn render_logout_result(
+    end_session_url: Option<&str>,
+    render_type: RenderType,
+    translations: &Translations,
+) -> Result<RenderedContent, Error> {
+    if render_type == RenderType::Json {
+        let content = json!({ "end_session_url": end_session_url }).to_string();
+        return Ok(RenderedContent::new(content, render_type));
+    }
+
+    let mut context = templates::localized_context(translations);
+    context.insert("end_session_url", &end_session_url);
+    let content = templates::render("logout_result.html", context)?;
+    Ok(RenderedContent::new(content, render_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_login() {
+        let translations = Translations::for_locale("en");
+        let login_url = "https://plugin.example.com/login?next=%2Froom";
+
+        let json = render_login(login_url, RenderType::Json, &translations).unwrap();
+        assert_eq!(json.status(), 401);
+        assert_eq!(
+            serde_json::from_str::<LoginUrl>(json.content()).unwrap(),
+            LoginUrl {
+                login_url: login_url.to_string()
+            }
+        );
+
+        let page = render_login(login_url, RenderType::HtmlPage, &translations).unwrap();
+        assert_eq!(page.status(), 401);
+        assert!(page.content().contains(">Log in</a>"));
+    }
+
+    #[test]
+    fn test_render_login_result() {
+        let host_user = HostUser {
+            subject: "1234".to_string(),
+            name: Some("<Host>".to_string()),
+            email: None,
+            provider: "google".to_string(),
+        };
+        let translations = Translations::for_locale("en");
+
+        let page = render_login_result(
+            &host_user,
+            Some("https://plugin.example.com/room"),
+            RenderType::HtmlPage,
+            &translations,
+        )
+        .unwrap();
+        assert!(page.content().contains("Logged in as &lt;Host&gt;"));
+        assert!(page.content().contains("plugin.example.com"));
+        assert!(page.content().contains(">Continue</a>"));
+
+        let json = render_login_result(&host_user, None, RenderType::Json, &translations).unwrap();
+        let json: serde_json::Value = serde_json::from_str(json.content()).unwrap();
+        assert_eq!(json["host"]["subject"], "1234");
+        assert!(json["next"].is_null());
+
+        let page = render_logout_result(None, RenderType::HtmlPage, &translations).unwrap();
+        assert!(page.content().contains("You are logged out"));
+        assert!(!page.content().contains("<a "));
+    }
+}
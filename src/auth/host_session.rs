@@ -0,0 +1,128 @@
+use super::{HostUser, TokenResponse};
+use crate::util::random_token;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Lifetime of host sessions
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct HostSessionConfig {
+    /// Seconds after login after which the host has to log in again
+    pub ttl_secs: u64,
+}
+
+impl Default for HostSessionConfig {
+    fn default() -> Self {
+        HostSessionConfig { ttl_secs: 8 * 3600 }
+    }
+}
+
+/// A logged in host and the tokens their provider issued, kept server-side
+/// so that only an opaque id ends up in the host's browser
+#[derive(Debug, Clone)]
+pub struct HostSession {
+    pub host_user: HostUser,
+    pub tokens: Option<TokenResponse>,
+    expires: Instant,
+}
+
+struct HostSessions {
+    config: HostSessionConfig,
+    sessions: HashMap<String, HostSession>,
+}
+
+/// Number of sessions above which expired ones are pruned
+const PRUNE_THRESHOLD: usize = 10_000;
+
+lazy_static! {
+    static ref HOST_SESSIONS: Mutex<HostSessions> = Mutex::new(HostSessions {
+        config: HostSessionConfig::default(),
+        sessions: HashMap::new(),
+    });
+}
+
+/// Replace the host session configuration, for sessions created from now on
+pub fn configure_host_sessions(config: HostSessionConfig) {
+    HOST_SESSIONS
+        .lock()
+        .expect("Host session lock poisoned")
+        .config = config;
+}
+
+/// Store a new session, returning its id
+pub(crate) fn create(host_user: HostUser, tokens: Option<TokenResponse>) -> String {
+    let mut store = HOST_SESSIONS.lock().expect("Host session lock poisoned");
+    let now = Instant::now();
+    if store.sessions.len() >= PRUNE_THRESHOLD {
+        store.sessions.retain(|_, session| session.expires > now);
+    }
+
+    let id = random_token(32);
+    let expires = now + Duration::from_secs(store.config.ttl_secs);
+    store.sessions.insert(
+        id.clone(),
+        HostSession {
+            host_user,
+            tokens,
+            expires,
+        },
+    );
+    id
+}
+
+/// The session with the given id, if it exists and has not expired
+pub(crate) fn get(id: &str) -> Option<HostSession> {
+    let mut store = HOST_SESSIONS.lock().expect("Host session lock poisoned");
+    match store.sessions.get(id) {
+        Some(session) if session.expires > Instant::now() => Some(session.clone()),
+        Some(_) => {
+            store.sessions.remove(id);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Remove a session, returning it if it had not expired yet
+pub(crate) fn remove(id: &str) -> Option<HostSession> {
+    HOST_SESSIONS
+        .lock()
+        .expect("Host session lock poisoned")
+        .sessions
+        .remove(id)
+        .filter(|session| session.expires > Instant::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_sessions() {
+        let host_user = HostUser {
+            subject: "5678".to_string(),
+            name: None,
+            email: None,
+            provider: "microsoft".to_string(),
+        };
+        let tokens: TokenResponse = serde_json::from_value(serde_json::json!({
+            "access_token": "access",
+            "token_type": "Bearer",
+        }))
+        .unwrap();
+
+        let id = create(host_user.clone(), Some(tokens));
+        assert!(!id.contains("5678"));
+        let session = get(&id).unwrap();
+        assert_eq!(session.host_user, host_user);
+        assert_eq!(session.tokens.unwrap().access_token, "access");
+
+        assert!(remove(&id).is_some());
+        assert!(get(&id).is_none());
+        assert!(get("unknown").is_none());
+    }
+}
@@ -0,0 +1,142 @@
+use super::{host_session, oidc::IdTokenClaims, TokenResponse};
+use crate::error::Error;
+use rocket::{
+    http::{Cookie, CookieJar, SameSite, Status},
+    request::{FromRequest, Outcome},
+    Request,
+};
+use serde::{Deserialize, Serialize};
+
+/// Private cookie holding the id of the logged in host's session
+pub const HOST_USER_COOKIE: &str = "host_user";
+
+/// Identity of a host, as established at login. Available to handlers as a
+/// request guard, which fails with 401 if the host is not logged in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostUser {
+    pub subject: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    /// Provider the host logged in with
+    pub provider: String,
+}
+
+impl HostUser {
+    pub fn from_id_token(provider: &str, claims: &IdTokenClaims) -> Self {
+        HostUser {
+            subject: claims.sub.clone(),
+            name: claims.name.clone(),
+            email: claims.email.clone(),
+            provider: provider.to_string(),
+        }
+    }
+
+    /// Remember the host for subsequent requests
+    pub fn login(&self, cookies: &CookieJar<'_>) -> Result<(), Error> {
+        self.login_with_tokens(None, cookies)
+    }
+
+    /// Remember the host for subsequent requests, along with the tokens their
+    /// provider issued. Both are kept server-side, the browser only gets the
+    /// session id.
+    pub fn login_with_tokens(
+        &self,
+        tokens: Option<TokenResponse>,
+        cookies: &CookieJar<'_>,
+    ) -> Result<(), Error> {
+        if let Some(cookie) = cookies.get_private(HOST_USER_COOKIE) {
+            host_session::remove(cookie.value());
+        }
+        let session_id = host_session::create(self.clone(), tokens);
+        cookies.add_private(
+            Cookie::build(HOST_USER_COOKIE, session_id)
+                .path("/")
+                .http_only(true)
+                .secure(true)
+                // Host pages are usually embedded in the communication platform
+                .same_site(SameSite::None)
+                .finish(),
+        );
+        Ok(())
+    }
+
+    /// Tokens stored at login for the host's session, if any
+    pub fn tokens(cookies: &CookieJar<'_>) -> Option<TokenResponse> {
+        let cookie = cookies.get_private(HOST_USER_COOKIE)?;
+        host_session::get(cookie.value())?.tokens
+    }
+
+    /// End the host's session, returning the tokens stored at login for
+    /// revoking them
+    pub fn logout(cookies: &CookieJar<'_>) -> Option<TokenResponse> {
+        let cookie = cookies.get_private(HOST_USER_COOKIE)?;
+        cookies.remove_private(Cookie::named(HOST_USER_COOKIE));
+        host_session::remove(cookie.value())?.tokens
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for HostUser {
+    type Error = Error;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let host_user = request
+            .cookies()
+            .get_private(HOST_USER_COOKIE)
+            .and_then(|cookie| host_session::get(cookie.value()))
+            .map(|session| session.host_user);
+
+        match host_user {
+            Some(host_user) => Outcome::Success(host_user),
+            None => Outcome::Failure((Status::Unauthorized, Error::Unauthorized("Not logged in"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::{get, local::blocking::Client};
+
+    #[get("/login")]
+    fn login(cookies: &CookieJar<'_>) -> Result<(), Error> {
+        HostUser {
+            subject: "1234".to_string(),
+            name: Some("Host".to_string()),
+            email: None,
+            provider: "google".to_string(),
+        }
+        .login(cookies)
+    }
+
+    #[get("/whoami")]
+    fn whoami(host_user: HostUser) -> String {
+        format!("{} via {}", host_user.subject, host_user.provider)
+    }
+
+    #[get("/whoami_or_error")]
+    fn whoami_or_error(host_user: Result<HostUser, Error>) -> Result<String, Error> {
+        Ok(host_user?.subject)
+    }
+
+    #[test]
+    fn test_host_user_guard() {
+        let rocket = rocket::build().mount("/", rocket::routes![login, whoami, whoami_or_error]);
+        let client = Client::tracked(rocket).unwrap();
+
+        assert_eq!(
+            client.get("/whoami").dispatch().status(),
+            Status::Unauthorized
+        );
+        let response = client.get("/whoami_or_error").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(
+            response.headers().get_one("WWW-Authenticate"),
+            Some("Bearer")
+        );
+
+        client.get("/login").dispatch();
+        let response = client.get("/whoami").dispatch();
+        assert_eq!(response.into_string().unwrap(), "1234 via google");
+    }
+}
@@ -0,0 +1,345 @@
+use super::HostUser;
+use crate::{
+    error::Error,
+    retry::{send_with_retry, RetryConfig},
+    util::join_url,
+};
+use reqwest::Url;
+use rocket::http::{Cookie, CookieJar, SameSite};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{convert::TryFrom, fmt::Debug};
+use zeroize::Zeroizing;
+
+/// Private cookie holding the requestor token of the disclosure session the
+/// host is logging in with
+pub const IRMA_SESSION_COOKIE: &str = "irma_session";
+
+#[derive(Deserialize)]
+#[serde(from = "String")]
+struct RequestorToken(Zeroizing<String>);
+
+impl From<String> for RequestorToken {
+    fn from(value: String) -> Self {
+        RequestorToken(Zeroizing::new(value))
+    }
+}
+
+impl Debug for RequestorToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestorToken").finish()
+    }
+}
+
+/// Configuration of an IRMA or Yivi server hosts can log in with, as found
+/// in the plugin configuration
+#[derive(Deserialize, Debug)]
+pub struct RawIrmaProviderConfig {
+    /// URL of the IRMA server, such as `https://irma.example.com`
+    server_url: String,
+    /// Token of this plugin at the IRMA server, if it requires requestor
+    /// authentication. May refer to a secret store, like OAuth client secrets.
+    #[serde(default)]
+    requestor_token: Option<RequestorToken>,
+    /// Attributes hosts must disclose to log in, such as
+    /// `pbdf.sidn-pbdf.email.email`
+    required_attributes: Vec<String>,
+    /// Attribute identifying the host. The first required attribute if not set.
+    #[serde(default)]
+    subject_attribute: Option<String>,
+    /// Attributes holding the display name and e-mail address of the host,
+    /// disclosed along with the required attributes
+    #[serde(default)]
+    name_attribute: Option<String>,
+    #[serde(default)]
+    email_attribute: Option<String>,
+}
+
+/// An IRMA or Yivi server hosts can log in with, by disclosing attributes
+/// from their app
+#[derive(Debug)]
+pub struct IrmaProvider {
+    server_url: String,
+    requestor_token: Option<RequestorToken>,
+    /// All attributes disclosed at login, starting with the required ones
+    pub attributes: Vec<String>,
+    pub subject_attribute: String,
+    pub name_attribute: Option<String>,
+    pub email_attribute: Option<String>,
+}
+
+impl TryFrom<RawIrmaProviderConfig> for IrmaProvider {
+    type Error = Error;
+    fn try_from(config: RawIrmaProviderConfig) -> Result<IrmaProvider, Error> {
+        match Url::parse(&config.server_url) {
+            Ok(url) if url.scheme() == "https" => {}
+            _ => return Err(Error::Config("IRMA server URL must be an https URL")),
+        }
+        let subject_attribute = config
+            .subject_attribute
+            .clone()
+            .or_else(|| config.required_attributes.first().cloned())
+            .ok_or(Error::Config(
+                "IRMA providers need at least one required attribute",
+            ))?;
+
+        let mut attributes = config.required_attributes;
+        for attribute in [
+            Some(&subject_attribute),
+            config.name_attribute.as_ref(),
+            config.email_attribute.as_ref(),
+        ]
+        .iter()
+        .flatten()
+        {
+            if !attributes.contains(attribute) {
+                attributes.push(attribute.to_string());
+            }
+        }
+
+        Ok(IrmaProvider {
+            server_url: config.server_url,
+            requestor_token: config.requestor_token,
+            attributes,
+            subject_attribute,
+            name_attribute: config.name_attribute,
+            email_attribute: config.email_attribute,
+        })
+    }
+}
+
+/// A started disclosure session, to be handed to `irma-frontend` or
+/// `yivi-frontend` in the host's browser for showing the QR code
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IrmaSessionStart {
+    pub session_ptr: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frontend_request: Option<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionPackage {
+    token: String,
+    session_ptr: Value,
+    #[serde(default)]
+    frontend_request: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DisclosedAttribute {
+    id: String,
+    #[serde(default)]
+    rawvalue: Option<String>,
+    status: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SessionResult {
+    status: String,
+    #[serde(default)]
+    proof_status: Option<String>,
+    #[serde(default)]
+    disclosed: Vec<Vec<DisclosedAttribute>>,
+}
+
+fn rejected(reason: &'static str) -> Error {
+    log::warn!("Rejected host login: {}", reason);
+    Error::Forbidden("Host is not allowed to log in")
+}
+
+impl IrmaProvider {
+    /// Session request disclosing all configured attributes, each in a
+    /// separate conjunction so all of them are required
+    fn session_request(&self) -> Value {
+        let disclose: Vec<Value> = self
+            .attributes
+            .iter()
+            .map(|attribute| json!([[attribute]]))
+            .collect();
+        json!({
+            "@context": "https://irma.app/ld/request/disclosure/v2",
+            "disclose": disclose,
+        })
+    }
+
+    fn request(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.requestor_token {
+            Some(token) => request.header("Authorization", token.0.as_str()),
+            None => request,
+        }
+    }
+
+    /// Start a disclosure session for logging in, remembering it in the
+    /// host's browser until [`IrmaProvider::finish_login`]
+    pub async fn start_login(
+        &self,
+        cookies: &CookieJar<'_>,
+        retry: &RetryConfig,
+    ) -> Result<IrmaSessionStart, Error> {
+        let url = join_url(&self.server_url, "/session")?;
+        let request = self.request(
+            reqwest::Client::new()
+                .post(&url)
+                .json(&self.session_request()),
+        );
+        // A failure of the IRMA server is reported as an upstream error
+        let package: SessionPackage = send_with_retry(request, retry)
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        cookies.add_private(
+            Cookie::build(IRMA_SESSION_COOKIE, package.token)
+                .path("/")
+                .http_only(true)
+                .secure(true)
+                // Host pages are usually embedded in the communication platform
+                .same_site(SameSite::None)
+                .finish(),
+        );
+        Ok(IrmaSessionStart {
+            session_ptr: package.session_ptr,
+            frontend_request: package.frontend_request,
+        })
+    }
+
+    /// Fetch the result of the disclosure session started in this browser
+    /// and log the host in if they disclosed all required attributes
+    pub async fn finish_login(
+        &self,
+        provider: &str,
+        cookies: &CookieJar<'_>,
+        retry: &RetryConfig,
+    ) -> Result<HostUser, Error> {
+        let token = cookies
+            .get_private(IRMA_SESSION_COOKIE)
+            .ok_or(Error::Forbidden("Login was not started here"))?;
+        cookies.remove_private(Cookie::named(IRMA_SESSION_COOKIE));
+
+        let url = join_url(
+            &self.server_url,
+            &format!(
+                "/session/{}/result",
+                crate::util::encode_path_segment(token.value())
+            ),
+        )?;
+        let response =
+            send_with_retry(self.request(reqwest::Client::new().get(&url)), retry).await?;
+        if !response.status().is_success() {
+            log::warn!(
+                "Fetching IRMA session result failed with status {}",
+                response.status()
+            );
+            return Err(Error::Forbidden("Could not fetch IRMA session result"));
+        }
+
+        let host_user = self.host_user(provider, &response.json().await?)?;
+        host_user.login(cookies)?;
+        Ok(host_user)
+    }
+
+    fn host_user(&self, provider: &str, result: &SessionResult) -> Result<HostUser, Error> {
+        if result.status != "DONE" {
+            return Err(rejected("disclosure session not completed"));
+        }
+        if result.proof_status.as_deref() != Some("VALID") {
+            return Err(rejected("invalid disclosure proof"));
+        }
+
+        let value = |attribute: &str| {
+            result
+                .disclosed
+                .iter()
+                .flatten()
+                .find(|disclosed| disclosed.id == attribute && disclosed.status == "PRESENT")
+                .and_then(|disclosed| disclosed.rawvalue.clone())
+        };
+        if !self
+            .attributes
+            .iter()
+            .all(|attribute| value(attribute).is_some())
+        {
+            return Err(rejected("required attribute not disclosed"));
+        }
+
+        Ok(HostUser {
+            subject: value(&self.subject_attribute)
+                .ok_or_else(|| rejected("required attribute not disclosed"))?,
+            name: self.name_attribute.as_deref().and_then(value),
+            email: self.email_attribute.as_deref().and_then(value),
+            provider: provider.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_irma_login() {
+        let raw: RawIrmaProviderConfig = serde_yaml::from_str(
+            r"
+            server_url: https://irma.example.com
+            requestor_token: very-secret
+            required_attributes:
+                - pbdf.gemeente.personalData.bsn
+            email_attribute: pbdf.sidn-pbdf.email.email
+            ",
+        )
+        .unwrap();
+        assert!(!format!("{:?}", raw).contains("very-secret"));
+        let provider = IrmaProvider::try_from(raw).unwrap();
+        assert_eq!(
+            provider.session_request()["disclose"],
+            json!([
+                [["pbdf.gemeente.personalData.bsn"]],
+                [["pbdf.sidn-pbdf.email.email"]]
+            ])
+        );
+
+        let result: SessionResult = serde_json::from_value(json!({
+            "token": "abc",
+            "status": "DONE",
+            "type": "disclosing",
+            "proofStatus": "VALID",
+            "disclosed": [
+                [{"id": "pbdf.gemeente.personalData.bsn", "rawvalue": "999999990", "status": "PRESENT"}],
+                [{"id": "pbdf.sidn-pbdf.email.email", "rawvalue": "host@gemeente.nl", "status": "PRESENT"}],
+            ],
+        }))
+        .unwrap();
+        let host_user = provider.host_user("yivi", &result).unwrap();
+        assert_eq!(host_user.subject, "999999990");
+        assert_eq!(host_user.email.as_deref(), Some("host@gemeente.nl"));
+
+        let result: SessionResult = serde_json::from_value(json!({
+            "status": "DONE",
+            "proofStatus": "VALID",
+            "disclosed": [
+                [{"id": "pbdf.gemeente.personalData.bsn", "rawvalue": "999999990", "status": "PRESENT"}],
+            ],
+        }))
+        .unwrap();
+        assert!(provider.host_user("yivi", &result).is_err());
+
+        let result: SessionResult = serde_json::from_value(json!({
+            "status": "CANCELLED",
+        }))
+        .unwrap();
+        assert!(provider.host_user("yivi", &result).is_err());
+
+        let raw: RawIrmaProviderConfig = serde_yaml::from_str(
+            r"
+            server_url: http://irma.example.com
+            required_attributes: [pbdf.sidn-pbdf.email.email]
+            ",
+        )
+        .unwrap();
+        assert!(IrmaProvider::try_from(raw).is_err());
+    }
+}
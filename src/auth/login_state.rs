@@ -0,0 +1,179 @@
+use crate::{error::Error, util::random_token};
+use reqwest::Url;
+use rocket::http::{Cookie, CookieJar, SameSite};
+use serde::{Deserialize, Serialize};
+
+/// Private cookie binding the OAuth `state` parameter and the OpenID Connect
+/// `nonce` to the browser that started the login
+pub const LOGIN_STATE_COOKIE: &str = "login_state";
+
+/// Contents of the OAuth `state` parameter, carrying the page to return to after login
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LoginState {
+    csrf: String,
+    /// Only kept in the cookie, so the `state` cannot be altered to accept
+    /// an ID token issued for another login
+    #[serde(skip)]
+    nonce: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+impl LoginState {
+    /// Start a login, returning the values to pass as `state` and `nonce` to
+    /// the provider
+    pub fn start(next: Option<String>, cookies: &CookieJar<'_>) -> Result<(String, String), Error> {
+        let state = LoginState {
+            csrf: random_token(16),
+            nonce: random_token(16),
+            next,
+        };
+        cookies.add_private(
+            Cookie::build(
+                LOGIN_STATE_COOKIE,
+                format!("{}.{}", state.csrf, state.nonce),
+            )
+            .path("/")
+            .http_only(true)
+            .secure(true)
+            // The IdP redirects back with a top-level navigation
+            .same_site(SameSite::Lax)
+            .finish(),
+        );
+        let encoded = base64::encode_config(serde_json::to_vec(&state)?, base64::URL_SAFE_NO_PAD);
+        Ok((encoded, state.nonce))
+    }
+
+    /// Check the `state` the provider redirected back with against the cookie
+    /// set when the login started
+    pub fn finish(state: &str, cookies: &CookieJar<'_>) -> Result<LoginState, Error> {
+        let expected = cookies
+            .get_private(LOGIN_STATE_COOKIE)
+            .ok_or(Error::Forbidden("Login was not started here"))?;
+        cookies.remove_private(Cookie::named(LOGIN_STATE_COOKIE));
+        let (csrf, nonce) = expected
+            .value()
+            .split_once('.')
+            .ok_or(Error::Forbidden("Login was not started here"))?;
+
+        let mut state: LoginState = base64::decode_config(state, base64::URL_SAFE_NO_PAD)
+            .ok()
+            .and_then(|state| serde_json::from_slice(&state).ok())
+            .ok_or(Error::BadRequest("Invalid login state"))?;
+        if !crate::util::constant_time_eq(&state.csrf, csrf) {
+            return Err(Error::Forbidden("Login was not started here"));
+        }
+        state.nonce = nonce.to_string();
+        Ok(state)
+    }
+
+    /// The nonce the ID token of this login must carry
+    pub fn nonce(&self) -> &str {
+        &self.nonce
+    }
+
+    /// Where to redirect the host after login, if they came from a page of this plugin
+    pub fn redirect_url(&self, external_url: &str) -> Option<String> {
+        validate_next_url(self.next.as_deref()?, external_url)
+    }
+}
+
+/// Accept `next` only if it points to a page under the external url, either
+/// as a path relative to it or as an absolute URL. Anything else could be
+/// abused to redirect freshly logged in hosts to a phishing site.
+pub fn validate_next_url(next: &str, external_url: &str) -> Option<String> {
+    if next.contains('\\') {
+        return None;
+    }
+    if next.starts_with('/') && !next.starts_with("//") {
+        return crate::util::join_url(external_url, next).ok();
+    }
+
+    let next = Url::parse(next).ok()?;
+    let base = Url::parse(&format!("{}/", external_url.trim_end_matches('/'))).ok()?;
+    if next.origin() == base.origin() && next.path().starts_with(base.path()) {
+        Some(next.to_string())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::{get, local::blocking::Client};
+
+    #[test]
+    fn test_validate_next_url() {
+        let external_url = "https://comm.example.com/plugin";
+        assert_eq!(
+            validate_next_url("/credentials/room?x=1", external_url),
+            Some("https://comm.example.com/plugin/credentials/room?x=1".to_string())
+        );
+        assert_eq!(
+            validate_next_url("https://comm.example.com/plugin/credentials", external_url),
+            Some("https://comm.example.com/plugin/credentials".to_string())
+        );
+        assert_eq!(validate_next_url("//evil.example.com/", external_url), None);
+        assert_eq!(
+            validate_next_url("/\\evil.example.com/", external_url),
+            None
+        );
+        assert_eq!(validate_next_url("/../other", external_url), None);
+        assert_eq!(
+            validate_next_url("https://evil.example.com/plugin/", external_url),
+            None
+        );
+        assert_eq!(
+            validate_next_url("https://comm.example.com/other", external_url),
+            None
+        );
+        assert_eq!(validate_next_url("javascript:alert(1)", external_url), None);
+    }
+
+    #[get("/start?<next>")]
+    fn start(next: Option<String>, cookies: &CookieJar<'_>) -> Result<String, Error> {
+        let (state, nonce) = LoginState::start(next, cookies)?;
+        Ok(format!("{} {}", state, nonce))
+    }
+
+    #[get("/callback?<state>")]
+    fn callback(state: String, cookies: &CookieJar<'_>) -> Result<String, Error> {
+        let state = LoginState::finish(&state, cookies)?;
+        Ok(format!(
+            "{} {}",
+            state
+                .redirect_url("https://comm.example.com")
+                .unwrap_or_default(),
+            state.nonce()
+        ))
+    }
+
+    #[test]
+    fn test_login_state() {
+        let rocket = rocket::build().mount("/", rocket::routes![start, callback]);
+        let client = Client::tracked(rocket).unwrap();
+
+        let started = client
+            .get("/start?next=/credentials")
+            .dispatch()
+            .into_string()
+            .unwrap();
+        let (state, nonce) = started.split_once(' ').unwrap();
+        // The nonce is only kept in the cookie
+        assert!(
+            !String::from_utf8(base64::decode_config(state, base64::URL_SAFE_NO_PAD).unwrap())
+                .unwrap()
+                .contains(nonce)
+        );
+        let response = client.get(format!("/callback?state={}", state)).dispatch();
+        assert_eq!(
+            response.into_string().unwrap(),
+            format!("https://comm.example.com/credentials {}", nonce)
+        );
+
+        // The state can only be used once
+        let response = client.get(format!("/callback?state={}", state)).dispatch();
+        assert_ne!(response.status(), rocket::http::Status::Ok);
+    }
+}
@@ -0,0 +1,469 @@
+use crate::{
+    error::Error,
+    retry::{send_with_retry, RetryConfig},
+};
+use josekit::{
+    jwk::JwkSet,
+    jws::{JwsVerifier, ES256, RS256},
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Time a fetched key set is used before fetching it anew
+const JWKS_TTL: Duration = Duration::from_secs(60 * 60);
+/// Minimum time between fetches of a key set, so tokens with unknown key
+/// ids cannot make us hammer the provider
+const JWKS_MIN_REFETCH: Duration = Duration::from_secs(60);
+/// Allowed clock difference between us and the provider
+const CLOCK_SKEW: u64 = 60;
+/// Placeholder in the issuer of multi-tenant providers, filled in with the
+/// `tid` claim of each token
+const TENANT_ID_PLACEHOLDER: &str = "{tenantid}";
+
+lazy_static! {
+    static ref JWKS_CACHE: RwLock<HashMap<String, (Instant, Arc<JwkSet>)>> =
+        RwLock::new(HashMap::new());
+}
+
+/// OpenID Connect provider whose ID tokens are accepted for host login
+#[derive(Deserialize, Debug, Clone)]
+pub struct OidcProvider {
+    /// Expected `iss` claim. A `{tenantid}` placeholder is filled in with
+    /// the `tid` claim, as for Microsoft's multi-tenant endpoints.
+    pub issuer: String,
+    /// Client id of this plugin at the provider, the expected audience
+    pub client_id: String,
+    /// URL of the provider's JSON Web Key Set
+    pub jwks_uri: String,
+    /// Token revocation endpoint (RFC 7009), if the provider has one
+    #[serde(default)]
+    pub revocation_endpoint: Option<String>,
+    /// Endpoint to end the host's session at the provider, if it has one
+    #[serde(default)]
+    pub end_session_endpoint: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    pub fn contains(&self, audience: &str) -> bool {
+        match self {
+            Audience::One(one) => one == audience,
+            Audience::Many(many) => many.iter().any(|a| a == audience),
+        }
+    }
+}
+
+/// Validated claims of an ID token
+#[derive(Deserialize, Debug, Clone)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: Audience,
+    pub exp: u64,
+    pub iat: Option<u64>,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub name: Option<String>,
+    /// Provider specific claims, such as Google's `hd` or Microsoft's `tid`
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct IdTokenHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn invalid(reason: &'static str) -> Error {
+    log::warn!("Rejected ID token: {}", reason);
+    Error::Forbidden("Invalid ID token")
+}
+
+async fn fetch_jwks(jwks_uri: &str, retry: &RetryConfig) -> Result<Arc<JwkSet>, Error> {
+    let response = send_with_retry(reqwest::Client::new().get(jwks_uri), retry)
+        .await?
+        .error_for_status()?;
+    let jwks =
+        Arc::new(JwkSet::from_bytes(response.bytes().await?).map_err(crate::jwt::JwtError::from)?);
+    JWKS_CACHE
+        .write()
+        .unwrap()
+        .insert(jwks_uri.to_string(), (Instant::now(), jwks.clone()));
+    Ok(jwks)
+}
+
+/// Whether a key set fetched at `fetched` may be fetched again, for a key id
+/// it does not contain
+fn may_refetch(fetched: Instant) -> bool {
+    fetched.elapsed() >= JWKS_MIN_REFETCH
+}
+
+/// Key set of the provider fetched anew for an unknown key id, unless it was
+/// fetched too recently for the provider to have rotated its keys again
+async fn refetch_jwks(jwks_uri: &str, retry: &RetryConfig) -> Result<Option<Arc<JwkSet>>, Error> {
+    let recent = matches!(
+        JWKS_CACHE.read().unwrap().get(jwks_uri),
+        Some((fetched, _)) if !may_refetch(*fetched)
+    );
+    if recent {
+        return Ok(None);
+    }
+    fetch_jwks(jwks_uri, retry).await.map(Some)
+}
+
+/// Key set of the provider, fetched anew if the cached one is stale
+async fn jwks(jwks_uri: &str, retry: &RetryConfig) -> Result<Arc<JwkSet>, Error> {
+    let cached = JWKS_CACHE
+        .read()
+        .unwrap()
+        .get(jwks_uri)
+        .filter(|(fetched, _)| fetched.elapsed() < JWKS_TTL)
+        .map(|(_, jwks)| jwks.clone());
+    match cached {
+        Some(jwks) => Ok(jwks),
+        None => fetch_jwks(jwks_uri, retry).await,
+    }
+}
+
+fn verifier_for(jwks: &JwkSet, header: &IdTokenHeader) -> Option<Box<dyn JwsVerifier>> {
+    let kid = header.kid.as_deref()?;
+    jwks.get(kid).into_iter().find_map(|jwk| {
+        let verifier: Box<dyn JwsVerifier> = match header.alg.as_str() {
+            "RS256" => Box::new(RS256.verifier_from_jwk(jwk).ok()?),
+            "ES256" => Box::new(ES256.verifier_from_jwk(jwk).ok()?),
+            _ => return None,
+        };
+        Some(verifier)
+    })
+}
+
+/// Whether a tenant is given by its id, a GUID, rather than by a name
+fn is_tenant_id(tenant: &str) -> bool {
+    tenant.len() == 36
+        && tenant.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+impl OidcProvider {
+    /// Google accounts, which support token revocation
+    pub fn google(client_id: &str) -> Self {
+        OidcProvider {
+            issuer: "https://accounts.google.com".to_string(),
+            client_id: client_id.to_string(),
+            jwks_uri: "https://www.googleapis.com/oauth2/v3/certs".to_string(),
+            revocation_endpoint: Some("https://oauth2.googleapis.com/revoke".to_string()),
+            end_session_endpoint: None,
+        }
+    }
+
+    /// Microsoft accounts of the given tenant. Microsoft does not support
+    /// revoking tokens, only ending the session.
+    pub fn microsoft(tenant: &str, client_id: &str) -> Self {
+        let base = format!("https://login.microsoftonline.com/{}", tenant);
+        // Tokens name the tenant they were issued for by its id, also when
+        // logging in at `common`, `organizations`, `consumers` or a domain
+        let issuer_tenant = if is_tenant_id(tenant) {
+            tenant
+        } else {
+            TENANT_ID_PLACEHOLDER
+        };
+        OidcProvider {
+            issuer: format!("https://login.microsoftonline.com/{}/v2.0", issuer_tenant),
+            client_id: client_id.to_string(),
+            jwks_uri: format!("{}/discovery/v2.0/keys", base),
+            revocation_endpoint: None,
+            end_session_endpoint: Some(format!("{}/oauth2/v2.0/logout", base)),
+        }
+    }
+
+    /// Revoke an access or refresh token at the provider, if it supports
+    /// revocation. Returns whether the token was revoked.
+    pub async fn revoke_token(
+        &self,
+        token: &str,
+        client_secret: Option<&str>,
+        retry: &RetryConfig,
+    ) -> Result<bool, Error> {
+        let revocation_endpoint = match &self.revocation_endpoint {
+            Some(revocation_endpoint) => revocation_endpoint,
+            None => return Ok(false),
+        };
+
+        let mut form = vec![("token", token), ("client_id", self.client_id.as_str())];
+        if let Some(client_secret) = client_secret {
+            form.push(("client_secret", client_secret));
+        }
+        let request = reqwest::Client::new().post(revocation_endpoint).form(&form);
+        let response = send_with_retry(request, retry).await?;
+
+        // Revoking an already invalid token is not an error (RFC 7009, section 2.2)
+        if !response.status().is_success() && response.status() != reqwest::StatusCode::BAD_REQUEST
+        {
+            log::warn!("Token revocation failed with status {}", response.status());
+            return Err(Error::Forbidden("Token revocation failed"));
+        }
+        Ok(true)
+    }
+
+    /// URL to redirect the host to, to also end their session at the provider
+    pub fn end_session_url(
+        &self,
+        id_token_hint: Option<&str>,
+        post_logout_redirect_uri: &str,
+    ) -> Option<String> {
+        let mut url = reqwest::Url::parse(self.end_session_endpoint.as_deref()?).ok()?;
+        {
+            let mut query = url.query_pairs_mut();
+            if let Some(id_token_hint) = id_token_hint {
+                query.append_pair("id_token_hint", id_token_hint);
+            }
+            query.append_pair("post_logout_redirect_uri", post_logout_redirect_uri);
+        }
+        Some(url.to_string())
+    }
+
+    /// Validate the signature, issuer, audience, expiry and nonce of an ID
+    /// token, returning its claims. The nonce is the one sent along with the
+    /// authorization request, see [`LoginState::nonce`](super::LoginState::nonce).
+    pub async fn validate_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+        retry: &RetryConfig,
+    ) -> Result<IdTokenClaims, Error> {
+        let header = id_token
+            .split('.')
+            .next()
+            .and_then(|header| base64::decode_config(header, base64::URL_SAFE_NO_PAD).ok())
+            .and_then(|header| serde_json::from_slice::<IdTokenHeader>(&header).ok())
+            .ok_or_else(|| invalid("malformed header"))?;
+
+        // An unknown key id may mean the provider rotated its keys
+        let verifier = match verifier_for(&*jwks(&self.jwks_uri, retry).await?, &header) {
+            Some(verifier) => verifier,
+            None => refetch_jwks(&self.jwks_uri, retry)
+                .await?
+                .and_then(|jwks| verifier_for(&jwks, &header))
+                .ok_or_else(|| invalid("unknown signing key"))?,
+        };
+
+        let (payload, _) = josekit::jwt::decode_with_verifier(id_token, verifier.as_ref())
+            .map_err(|_| invalid("bad signature"))?;
+        let claims: IdTokenClaims =
+            serde_json::from_value(Value::Object(payload.claims_set().clone()))
+                .map_err(|_| invalid("malformed claims"))?;
+
+        self.validate_claims(&claims, nonce, unix_time())?;
+        Ok(claims)
+    }
+
+    fn validate_claims(&self, claims: &IdTokenClaims, nonce: &str, now: u64) -> Result<(), Error> {
+        let issuer = if self.issuer.contains(TENANT_ID_PLACEHOLDER) {
+            let tenant_id = claims
+                .extra
+                .get("tid")
+                .and_then(Value::as_str)
+                .filter(|tenant_id| is_tenant_id(tenant_id))
+                .ok_or_else(|| invalid("missing tenant id"))?;
+            self.issuer.replace(TENANT_ID_PLACEHOLDER, tenant_id)
+        } else {
+            self.issuer.clone()
+        };
+        if claims.iss != issuer {
+            return Err(invalid("wrong issuer"));
+        }
+        if !claims.aud.contains(&self.client_id) {
+            return Err(invalid("wrong audience"));
+        }
+        if claims.exp + CLOCK_SKEW < now {
+            return Err(invalid("expired"));
+        }
+        if matches!(claims.iat, Some(iat) if iat > now + CLOCK_SKEW) {
+            return Err(invalid("issued in the future"));
+        }
+        match &claims.nonce {
+            Some(claimed) if crate::util::constant_time_eq(claimed, nonce) => {}
+            _ => return Err(invalid("wrong nonce")),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_end_session_url() {
+        assert_eq!(
+            OidcProvider::google("comm-plugin").end_session_url(None, "https://example.com"),
+            None
+        );
+        assert_eq!(
+            OidcProvider::microsoft("contoso", "comm-plugin")
+                .end_session_url(Some("token"), "https://example.com/logged_out"),
+            Some("https://login.microsoftonline.com/contoso/oauth2/v2.0/logout?id_token_hint=token&post_logout_redirect_uri=https%3A%2F%2Fexample.com%2Flogged_out".to_string())
+        );
+    }
+
+    #[test]
+    fn test_validate_claims() {
+        let provider = OidcProvider::google("comm-plugin");
+        let claims: IdTokenClaims = serde_json::from_value(serde_json::json!({
+            "iss": "https://accounts.google.com",
+            "sub": "1234",
+            "aud": ["comm-plugin", "other"],
+            "exp": 2000,
+            "iat": 1000,
+            "nonce": "n-0S6_WzA2Mj",
+            "email": "host@example.com",
+            "hd": "example.com",
+        }))
+        .unwrap();
+        assert_eq!(claims.extra.get("hd"), Some(&Value::from("example.com")));
+
+        let nonce = "n-0S6_WzA2Mj";
+        assert!(provider.validate_claims(&claims, nonce, 1500).is_ok());
+        assert!(provider.validate_claims(&claims, "other", 1500).is_err());
+        assert!(provider.validate_claims(&claims, nonce, 3000).is_err());
+        assert!(provider.validate_claims(&claims, nonce, 500).is_err());
+        // A token without nonce is not bound to any login
+        let without_nonce = IdTokenClaims {
+            nonce: None,
+            ..claims.clone()
+        };
+        assert!(provider
+            .validate_claims(&without_nonce, nonce, 1500)
+            .is_err());
+
+        let other_audience = OidcProvider {
+            client_id: "other-plugin".to_string(),
+            ..provider.clone()
+        };
+        assert!(other_audience
+            .validate_claims(&claims, nonce, 1500)
+            .is_err());
+        let other_issuer = OidcProvider {
+            issuer: "https://login.microsoftonline.com".to_string(),
+            ..provider
+        };
+        assert!(other_issuer.validate_claims(&claims, nonce, 1500).is_err());
+    }
+
+    #[test]
+    fn test_validate_multi_tenant_claims() {
+        let tenant_id = "72f988bf-86f1-41af-91ab-2d7cd011db47";
+        let claims = |iss: &str, tid: Option<&str>| -> IdTokenClaims {
+            let mut claims = serde_json::json!({
+                "aud": "6731de76-14a6-49ae-97bc-6eba6914391e",
+                "iss": iss,
+                "iat": 1000,
+                "nbf": 1000,
+                "exp": 2000,
+                "name": "Abe Lincoln",
+                "oid": "00000000-0000-0000-66f3-3332eca7ea81",
+                "preferred_username": "AbeLi@microsoft.com",
+                "sub": "AAAAAAAAAAAAAAAAAAAAAIkzqFVrSaSaFHy782bbtaQ",
+                "ver": "2.0",
+                "nonce": "nonce",
+            });
+            if let Some(tid) = tid {
+                claims["tid"] = tid.into();
+            }
+            serde_json::from_value(claims).unwrap()
+        };
+        let issuer = format!("https://login.microsoftonline.com/{}/v2.0", tenant_id);
+
+        for tenant in ["common", "organizations", "contoso.onmicrosoft.com"].iter() {
+            let provider = OidcProvider::microsoft(tenant, "6731de76-14a6-49ae-97bc-6eba6914391e");
+            assert!(provider
+                .validate_claims(&claims(&issuer, Some(tenant_id)), "nonce", 1500)
+                .is_ok());
+            // The issuer must match the tenant the token claims to be for
+            let other_tenant = "9188040d-6c67-4c5b-b112-36a304b66dad";
+            assert!(provider
+                .validate_claims(&claims(&issuer, Some(other_tenant)), "nonce", 1500)
+                .is_err());
+            assert!(provider
+                .validate_claims(&claims(&issuer, None), "nonce", 1500)
+                .is_err());
+            assert!(provider
+                .validate_claims(
+                    &claims(
+                        "https://login.microsoftonline.com/common/v2.0",
+                        Some("common")
+                    ),
+                    "nonce",
+                    1500
+                )
+                .is_err());
+        }
+
+        let provider = OidcProvider::microsoft(tenant_id, "6731de76-14a6-49ae-97bc-6eba6914391e");
+        assert_eq!(provider.issuer, issuer);
+        assert!(provider
+            .validate_claims(&claims(&issuer, Some(tenant_id)), "nonce", 1500)
+            .is_ok());
+    }
+
+    #[cfg(feature = "auth_during_comm")]
+    #[test]
+    fn test_unknown_key_refetch() {
+        let server = crate::test_helpers::MockServer::start(vec![
+            (200, r#"{"keys":[]}"#.to_string()),
+            (200, r#"{"keys":[]}"#.to_string()),
+        ]);
+        let provider = OidcProvider {
+            jwks_uri: format!("{}/jwks", server.url()),
+            ..OidcProvider::google("comm-plugin")
+        };
+        let header = base64::encode_config(
+            r#"{"alg":"RS256","kid":"rotated"}"#,
+            base64::URL_SAFE_NO_PAD,
+        );
+        let id_token = format!("{}.e30.c2ln", header);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        for _ in 0..3 {
+            assert!(matches!(
+                runtime.block_on(provider.validate_id_token(
+                    &id_token,
+                    "nonce",
+                    &RetryConfig::default()
+                )),
+                Err(Error::Forbidden(_))
+            ));
+        }
+        // The key set was just fetched, so unknown key ids do not fetch it again
+        assert_eq!(server.requests().len(), 1);
+
+        let fetched = Instant::now();
+        assert!(!may_refetch(fetched));
+        assert!(may_refetch(fetched - JWKS_MIN_REFETCH));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::util::random_token;
+use reqwest::Url;
+use rocket::http::{Cookie, CookieJar, SameSite};
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Cookie holding the code verifier between the authorization redirect and
+/// the token exchange
+pub const PKCE_COOKIE: &str = "pkce_verifier";
+/// The only challenge method we use, plain challenges offer no protection
+pub const PKCE_METHOD: &str = "S256";
+/// Time a stored verifier remains usable
+const PKCE_VALIDITY: Duration = Duration::from_secs(10 * 60);
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Code verifier of a single authorization request. The challenge derived from
+/// it is sent along with the authorization redirect, the verifier itself with
+/// the token exchange, so an intercepted authorization code is useless.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PkceVerifier(String);
+
+impl PkceVerifier {
+    /// Generate a new verifier of 43 URL-safe characters
+    pub fn generate() -> Self {
+        PkceVerifier(random_token(32))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Challenge derived from this verifier, for the `S256` method
+    pub fn challenge(&self) -> String {
+        base64::encode_config(Sha256::digest(self.0.as_bytes()), base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Add the challenge parameters to an authorization URL
+    pub fn add_challenge(&self, url: &mut Url) {
+        url.query_pairs_mut()
+            .append_pair("code_challenge", &self.challenge())
+            .append_pair("code_challenge_method", PKCE_METHOD);
+    }
+
+    /// Keep the verifier in an encrypted session cookie until the token
+    /// exchange. The cookie records when it was created, so it is only
+    /// accepted for a short while.
+    pub fn store(&self, cookies: &CookieJar<'_>) {
+        cookies.add_private(
+            Cookie::build(PKCE_COOKIE, format!("{}.{}", self.0, unix_time()))
+                .path("/")
+                .http_only(true)
+                .secure(true)
+                // The IdP redirects back with a top-level navigation
+                .same_site(SameSite::Lax)
+                .finish(),
+        );
+    }
+
+    /// Take the verifier stored for the current authorization request, if it
+    /// has not expired yet. A verifier can only be used once.
+    pub fn take(cookies: &CookieJar<'_>) -> Option<Self> {
+        let value = cookies.get_private(PKCE_COOKIE)?.value().to_string();
+        cookies.remove_private(Cookie::named(PKCE_COOKIE));
+
+        let (verifier, created) = value.rsplit_once('.')?;
+        let created: u64 = created.parse().ok()?;
+        if is_expired(created, unix_time()) {
+            return None;
+        }
+        Some(PkceVerifier(verifier.to_string()))
+    }
+}
+
+/// Whether a verifier created at `created` is no longer valid at `now`.
+/// Creation times so far in the future that the expiry overflows are rejected.
+fn is_expired(created: u64, now: u64) -> bool {
+    match created.checked_add(PKCE_VALIDITY.as_secs()) {
+        Some(expires) => expires < now,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::{get, local::blocking::Client};
+
+    #[get("/store")]
+    fn store(cookies: &CookieJar<'_>) -> String {
+        let verifier = PkceVerifier::generate();
+        verifier.store(cookies);
+        verifier.as_str().to_string()
+    }
+
+    #[get("/take")]
+    fn take(cookies: &CookieJar<'_>) -> String {
+        PkceVerifier::take(cookies)
+            .map(|verifier| verifier.as_str().to_string())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_pkce_cookie() {
+        let rocket = rocket::build().mount("/", rocket::routes![store, take]);
+        let client = Client::tracked(rocket).unwrap();
+
+        let response = client.get("/store").dispatch();
+        let cookie = response.cookies().get(PKCE_COOKIE).unwrap().clone();
+        let verifier = response.into_string().unwrap();
+        // The verifier is not readable from the cookie
+        assert!(!cookie.value().contains(&verifier));
+
+        assert_eq!(
+            client.get("/take").dispatch().into_string().unwrap(),
+            verifier
+        );
+        // Verifiers can only be used once
+        assert_eq!(client.get("/take").dispatch().into_string().unwrap(), "");
+
+        // Plaintext cookies, as set by someone other than this plugin, are ignored
+        let response = client
+            .get("/take")
+            .cookie(Cookie::new(PKCE_COOKIE, format!("forged.{}", unix_time())))
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), "");
+
+        let response = client
+            .get("/take")
+            .private_cookie(Cookie::new(PKCE_COOKIE, format!("v.{}", u64::MAX)))
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), "");
+    }
+
+    #[test]
+    fn test_pkce_expiry() {
+        let validity = PKCE_VALIDITY.as_secs();
+        assert!(!is_expired(1_000, 1_000));
+        assert!(!is_expired(1_000, 1_000 + validity));
+        assert!(is_expired(1_000, 1_001 + validity));
+        assert!(is_expired(u64::MAX, 1_000));
+        assert!(is_expired(u64::MAX - validity + 1, 1_000));
+    }
+
+    #[test]
+    fn test_pkce_challenge() {
+        // Example from RFC 7636, appendix B
+        let verifier = PkceVerifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".to_string());
+        assert_eq!(
+            verifier.challenge(),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+
+        let generated = PkceVerifier::generate();
+        assert_eq!(generated.as_str().len(), 43);
+        assert_ne!(generated, PkceVerifier::generate());
+
+        let mut url = Url::parse("https://idp.example.com/authorize?client_id=comm").unwrap();
+        verifier.add_challenge(&mut url);
+        assert_eq!(
+            url.query(),
+            Some("client_id=comm&code_challenge=E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM&code_challenge_method=S256")
+        );
+    }
+}
@@ -0,0 +1,483 @@
+use super::{
+    oidc::{IdTokenClaims, OidcProvider},
+    OAuthClient, PkceVerifier, TokenResponse,
+};
+use crate::{
+    error::Error,
+    retry::{send_with_retry, RetryConfig},
+};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, convert::TryFrom, fmt::Debug};
+
+/// Groups of the logged in user, in the Microsoft Graph API
+const GRAPH_MEMBER_OF_URL: &str =
+    "https://graph.microsoft.com/v1.0/me/memberOf?$select=id&$top=999";
+
+fn default_tenant() -> String {
+    "common".to_string()
+}
+
+fn default_scopes() -> Vec<String> {
+    vec![
+        "openid".to_string(),
+        "email".to_string(),
+        "profile".to_string(),
+    ]
+}
+
+/// Identity provider hosts log in with, with its provider specific settings
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuthProvider {
+    Google {
+        /// Google Workspace domains hosts must belong to, checked against the
+        /// `hd` claim. Consumer accounts, such as Gmail addresses, have no
+        /// hosted domain and are rejected when set. Any account is accepted
+        /// if empty.
+        #[serde(default)]
+        allowed_domains: Vec<String>,
+    },
+    Microsoft {
+        /// Azure AD tenant, by id or domain, or `common` for any organization
+        /// or personal account. Tokens are checked to be issued by the tenant
+        /// they name in their `tid` claim.
+        #[serde(default = "default_tenant")]
+        tenant: String,
+        /// Id of the tenant hosts must belong to, checked against the `tid`
+        /// claim. Hosts of any organization and personal accounts are
+        /// accepted if not set.
+        #[serde(default)]
+        tenant_id: Option<String>,
+        /// Object ids of Azure AD groups hosts must be a member of at least one
+        /// of. Requires a scope allowing the Graph `memberOf` endpoint, such
+        /// as `GroupMember.Read.All`.
+        #[serde(default)]
+        allowed_groups: Vec<String>,
+    },
+    /// Any other OpenID Connect provider
+    Oidc {
+        issuer: String,
+        jwks_uri: String,
+        authorization_endpoint: String,
+        token_endpoint: String,
+        #[serde(default)]
+        revocation_endpoint: Option<String>,
+        #[serde(default)]
+        end_session_endpoint: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(from = "String")]
+struct ClientSecret(String);
+
+impl From<String> for ClientSecret {
+    fn from(value: String) -> Self {
+        ClientSecret(value)
+    }
+}
+
+impl Debug for ClientSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientSecret").finish()
+    }
+}
+
+/// Configuration of a provider hosts can log in with, as found in the
+/// plugin configuration. The client secret may refer to a secret store, such
+/// as `vault:oauth#google`, when the configuration is read with
+/// [`load_config`](crate::secrets::load_config).
+#[derive(Deserialize, Debug)]
+pub struct RawOAuthProviderConfig {
+    #[serde(flatten)]
+    provider: AuthProvider,
+    client_id: String,
+    client_secret: ClientSecret,
+    #[serde(default = "default_scopes")]
+    scopes: Vec<String>,
+    /// Additional parameters of the authorization request, such as `prompt`
+    #[serde(default)]
+    extra_auth_params: BTreeMap<String, String>,
+}
+
+/// A provider hosts can log in with, and the credentials of this plugin there
+#[derive(Debug)]
+pub struct OAuthProvider {
+    pub provider: AuthProvider,
+    pub oidc: OidcProvider,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    client_secret: ClientSecret,
+    pub scopes: Vec<String>,
+    pub extra_auth_params: BTreeMap<String, String>,
+}
+
+fn https_url(url: &str) -> Result<(), Error> {
+    match Url::parse(url) {
+        Ok(url) if url.scheme() == "https" => Ok(()),
+        _ => Err(Error::Config("OAuth provider endpoints must be https URLs")),
+    }
+}
+
+impl TryFrom<RawOAuthProviderConfig> for OAuthProvider {
+    type Error = Error;
+    fn try_from(config: RawOAuthProviderConfig) -> Result<OAuthProvider, Error> {
+        if !config.scopes.iter().any(|scope| scope == "openid") {
+            return Err(Error::Config("OAuth provider scopes must include openid"));
+        }
+
+        let client_id = &config.client_id;
+        let (oidc, authorization_endpoint, token_endpoint) = match &config.provider {
+            AuthProvider::Google { .. } => (
+                OidcProvider::google(client_id),
+                "https://accounts.google.com/o/oauth2/v2/auth".to_string(),
+                "https://oauth2.googleapis.com/token".to_string(),
+            ),
+            AuthProvider::Microsoft { tenant, .. } => {
+                let base = format!("https://login.microsoftonline.com/{}", tenant);
+                (
+                    OidcProvider::microsoft(tenant, client_id),
+                    format!("{}/oauth2/v2.0/authorize", base),
+                    format!("{}/oauth2/v2.0/token", base),
+                )
+            }
+            AuthProvider::Oidc {
+                issuer,
+                jwks_uri,
+                authorization_endpoint,
+                token_endpoint,
+                revocation_endpoint,
+                end_session_endpoint,
+            } => (
+                OidcProvider {
+                    issuer: issuer.clone(),
+                    client_id: client_id.clone(),
+                    jwks_uri: jwks_uri.clone(),
+                    revocation_endpoint: revocation_endpoint.clone(),
+                    end_session_endpoint: end_session_endpoint.clone(),
+                },
+                authorization_endpoint.clone(),
+                token_endpoint.clone(),
+            ),
+        };
+        https_url(&authorization_endpoint)?;
+        https_url(&token_endpoint)?;
+        https_url(&oidc.jwks_uri)?;
+
+        Ok(OAuthProvider {
+            provider: config.provider,
+            oidc,
+            authorization_endpoint,
+            token_endpoint,
+            client_secret: config.client_secret,
+            scopes: config.scopes,
+            extra_auth_params: config.extra_auth_params,
+        })
+    }
+}
+
+impl OAuthProvider {
+    pub fn client_id(&self) -> &str {
+        &self.oidc.client_id
+    }
+
+    /// Client credentials for exchanging codes at this provider
+    pub fn client<'a>(&'a self, redirect_uri: &'a str) -> OAuthClient<'a> {
+        OAuthClient {
+            client_id: &self.oidc.client_id,
+            client_secret: &self.client_secret.0,
+            token_url: &self.token_endpoint,
+            redirect_uri,
+        }
+    }
+
+    /// URL to send the host to for logging in, for the authorization code flow
+    /// with PKCE. The state and nonce are usually obtained from
+    /// [`LoginState::start`](super::LoginState::start).
+    pub fn authorization_url(
+        &self,
+        redirect_uri: &str,
+        state: &str,
+        verifier: &PkceVerifier,
+        nonce: &str,
+    ) -> Result<String, Error> {
+        let mut url = Url::parse(&self.authorization_endpoint)
+            .map_err(|_| Error::BadRequest("Invalid authorization endpoint"))?;
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("response_type", "code")
+                .append_pair("client_id", &self.oidc.client_id)
+                .append_pair("redirect_uri", redirect_uri)
+                .append_pair("scope", &self.scopes.join(" "))
+                .append_pair("state", state)
+                .append_pair("nonce", nonce);
+            for (name, value) in &self.extra_auth_params {
+                query.append_pair(name, value);
+            }
+        }
+        verifier.add_challenge(&mut url);
+        Ok(url.to_string())
+    }
+
+    /// Revoke a token at the provider, authenticating with the client secret
+    pub async fn revoke_token(&self, token: &str, retry: &RetryConfig) -> Result<bool, Error> {
+        self.oidc
+            .revoke_token(token, Some(&self.client_secret.0), retry)
+            .await
+    }
+
+    /// Validate the ID token of a completed login and check the host against
+    /// the restrictions configured for the provider, returning the ID token's claims
+    pub async fn check_token(
+        &self,
+        tokens: &TokenResponse,
+        nonce: &str,
+        retry: &RetryConfig,
+    ) -> Result<IdTokenClaims, Error> {
+        let id_token = tokens
+            .id_token
+            .as_deref()
+            .ok_or(Error::Forbidden("No ID token received"))?;
+        let claims = self.oidc.validate_id_token(id_token, nonce, retry).await?;
+
+        match &self.provider {
+            AuthProvider::Google { allowed_domains } => {
+                check_token_google(&claims, allowed_domains)?;
+            }
+            AuthProvider::Microsoft {
+                tenant_id,
+                allowed_groups,
+                ..
+            } => {
+                check_token_microsoft(
+                    &claims,
+                    &tokens.access_token,
+                    tenant_id.as_deref(),
+                    allowed_groups,
+                    retry,
+                )
+                .await?;
+            }
+            AuthProvider::Oidc { .. } => {}
+        }
+        Ok(claims)
+    }
+}
+
+fn rejected(reason: &'static str) -> Error {
+    log::warn!("Rejected host login: {}", reason);
+    Error::Forbidden("Host is not allowed to log in")
+}
+
+/// Only accept hosts of one of the allowed Google Workspace domains, with a
+/// verified e-mail address in that domain if the token has one
+fn check_token_google(claims: &IdTokenClaims, allowed_domains: &[String]) -> Result<(), Error> {
+    if allowed_domains.is_empty() {
+        return Ok(());
+    }
+    let allowed = |domain: &str| {
+        allowed_domains
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+    };
+
+    let hosted_domain = claims
+        .extra
+        .get("hd")
+        .and_then(Value::as_str)
+        .ok_or_else(|| rejected("not a Google Workspace account"))?;
+    if !allowed(hosted_domain) {
+        return Err(rejected("wrong hosted domain"));
+    }
+    if let Some(email) = &claims.email {
+        let email_domain = email.rsplit('@').next().unwrap_or_default();
+        if claims.email_verified != Some(true) || !allowed(email_domain) {
+            return Err(rejected("unverified or foreign e-mail address"));
+        }
+    }
+    Ok(())
+}
+
+/// Only accept hosts of the configured tenant and, if configured, in one of
+/// the allowed groups
+async fn check_token_microsoft(
+    claims: &IdTokenClaims,
+    access_token: &str,
+    tenant_id: Option<&str>,
+    allowed_groups: &[String],
+    retry: &RetryConfig,
+) -> Result<(), Error> {
+    if let Some(tenant_id) = tenant_id {
+        if claims.extra.get("tid").and_then(Value::as_str) != Some(tenant_id) {
+            return Err(rejected("wrong tenant"));
+        }
+    }
+    if allowed_groups.is_empty() {
+        return Ok(());
+    }
+
+    let mut next = Some(GRAPH_MEMBER_OF_URL.to_string());
+    while let Some(url) = next {
+        let request = reqwest::Client::new().get(&url).bearer_auth(access_token);
+        let response = send_with_retry(request, retry).await?;
+        if !response.status().is_success() {
+            log::warn!("Group lookup failed with status {}", response.status());
+            return Err(rejected("group membership unknown"));
+        }
+        let page: Value = response.json().await?;
+        let member = page["value"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|group| group["id"].as_str())
+            .any(|id| allowed_groups.iter().any(|allowed| allowed == id));
+        if member {
+            return Ok(());
+        }
+        next = page["@odata.nextLink"].as_str().map(String::from);
+    }
+    Err(rejected("not in an allowed group"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_config() {
+        let raw: RawOAuthProviderConfig = serde_yaml::from_str(
+            r"
+            type: microsoft
+            tenant: contoso
+            client_id: comm-plugin
+            client_secret: very-secret
+            extra_auth_params:
+                prompt: select_account
+            ",
+        )
+        .unwrap();
+        assert!(!format!("{:?}", raw).contains("very-secret"));
+        let provider = OAuthProvider::try_from(raw).unwrap();
+        // Tokens carry the tenant's GUID, not its name, in the issuer
+        assert_eq!(
+            provider.oidc.issuer,
+            "https://login.microsoftonline.com/{tenantid}/v2.0"
+        );
+        assert_eq!(
+            provider
+                .client("https://plugin.example.com/login")
+                .token_url,
+            "https://login.microsoftonline.com/contoso/oauth2/v2.0/token"
+        );
+
+        let url = provider
+            .authorization_url(
+                "https://plugin.example.com/login",
+                "state",
+                &PkceVerifier::generate(),
+                "nonce",
+            )
+            .unwrap();
+        assert!(url.starts_with(
+            "https://login.microsoftonline.com/contoso/oauth2/v2.0/authorize?response_type=code&client_id=comm-plugin"
+        ));
+        assert!(url.contains("&scope=openid+email+profile&state=state&nonce=nonce&prompt=select_account&code_challenge="));
+
+        assert_eq!(
+            provider.provider,
+            AuthProvider::Microsoft {
+                tenant: "contoso".to_string(),
+                tenant_id: None,
+                allowed_groups: vec![],
+            }
+        );
+
+        let raw: RawOAuthProviderConfig = serde_yaml::from_str(
+            r"
+            type: google
+            client_id: comm-plugin
+            client_secret: very-secret
+            scopes: [email]
+            ",
+        )
+        .unwrap();
+        assert!(OAuthProvider::try_from(raw).is_err());
+    }
+
+    #[test]
+    fn test_check_token_microsoft() {
+        let claims: IdTokenClaims = serde_json::from_value(serde_json::json!({
+            "iss": "https://login.microsoftonline.com/contoso/v2.0",
+            "sub": "1234",
+            "aud": "comm-plugin",
+            "exp": 2000,
+            "tid": "72f988bf-86f1-41af-91ab-2d7cd011db47",
+        }))
+        .unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let check = |tenant_id| {
+            runtime.block_on(check_token_microsoft(
+                &claims,
+                "token",
+                tenant_id,
+                &[],
+                &RetryConfig::default(),
+            ))
+        };
+
+        assert!(check(None).is_ok());
+        assert!(check(Some("72f988bf-86f1-41af-91ab-2d7cd011db47")).is_ok());
+        assert!(matches!(
+            check(Some("9188040d-6c67-4c5b-b112-36a304b66dad")),
+            Err(Error::Forbidden(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_token_google() {
+        let claims = |extra: serde_json::Value| -> IdTokenClaims {
+            let mut claims = serde_json::json!({
+                "iss": "https://accounts.google.com",
+                "sub": "1234",
+                "aud": "comm-plugin",
+                "exp": 2000,
+            });
+            claims
+                .as_object_mut()
+                .unwrap()
+                .extend(extra.as_object().unwrap().clone());
+            serde_json::from_value(claims).unwrap()
+        };
+        let allowed = vec!["gemeente.nl".to_string()];
+
+        let workspace = claims(serde_json::json!({
+            "hd": "gemeente.nl",
+            "email": "host@Gemeente.nl",
+            "email_verified": true,
+        }));
+        assert!(check_token_google(&workspace, &allowed).is_ok());
+        assert!(check_token_google(&workspace, &[]).is_ok());
+
+        let consumer = claims(serde_json::json!({
+            "email": "host@gmail.com",
+            "email_verified": true,
+        }));
+        assert!(check_token_google(&consumer, &allowed).is_err());
+        assert!(check_token_google(&consumer, &[]).is_ok());
+
+        let other_domain = claims(serde_json::json!({ "hd": "example.com" }));
+        assert!(check_token_google(&other_domain, &allowed).is_err());
+        let unverified = claims(serde_json::json!({
+            "hd": "gemeente.nl",
+            "email": "host@gemeente.nl",
+            "email_verified": false,
+        }));
+        assert!(check_token_google(&unverified, &allowed).is_err());
+    }
+}
@@ -0,0 +1,190 @@
+use crate::error::Error;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::{Digest, Sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+use zeroize::Zeroizing;
+
+/// Region and credentials for calling AWS APIs
+pub struct AwsCredentials {
+    pub region: String,
+    access_key_id: String,
+    secret_access_key: Zeroizing<String>,
+    session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    pub fn new(
+        region: String,
+        access_key_id: String,
+        secret_access_key: String,
+        session_token: Option<String>,
+    ) -> Self {
+        AwsCredentials {
+            region,
+            access_key_id,
+            secret_access_key: Zeroizing::new(secret_access_key),
+            session_token,
+        }
+    }
+
+    /// Region and credentials from the standard `AWS_REGION`,
+    /// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`
+    /// environment variables
+    pub fn from_env() -> Result<Self, Error> {
+        Ok(AwsCredentials::new(
+            env("AWS_REGION").or_else(|_| env("AWS_DEFAULT_REGION"))?,
+            env("AWS_ACCESS_KEY_ID")?,
+            env("AWS_SECRET_ACCESS_KEY")?,
+            std::env::var("AWS_SESSION_TOKEN").ok(),
+        ))
+    }
+
+    /// Sign a request without query string with signature version 4. Returns
+    /// the given headers extended with the date, session token and
+    /// `Authorization` headers, to be sent along with the request.
+    pub fn sign(
+        &self,
+        method: &str,
+        service: &str,
+        host: &str,
+        path: &str,
+        mut headers: Vec<(&'static str, String)>,
+        body: &[u8],
+    ) -> Vec<(&'static str, String)> {
+        let amz_date = amz_date(SystemTime::now());
+        let date = &amz_date[..8];
+
+        headers.push(("host", host.to_string()));
+        headers.push(("x-amz-date", amz_date.clone()));
+        if let Some(session_token) = &self.session_token {
+            headers.push(("x-amz-security-token", session_token.clone()));
+        }
+        headers.sort_by_key(|(name, _)| *name);
+
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method,
+            path,
+            canonical_headers,
+            signed_headers,
+            hex(&Sha256::digest(body))
+        );
+
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let signing_key = signing_key(&self.secret_access_key, date, &self.region, service);
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key_id,
+            scope,
+            signed_headers,
+            hex(&hmac(&signing_key, &string_to_sign))
+        );
+
+        headers.retain(|(name, _)| *name != "host");
+        headers.push(("authorization", authorization));
+        headers
+    }
+}
+
+fn env(name: &'static str) -> Result<String, Error> {
+    std::env::var(name).map_err(|_| {
+        log::error!("Environment variable {} not set", name);
+        Error::Config("AWS credentials not configured")
+    })
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Lowercase hexadecimal encoding, as used in signatures and payload hashes
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn signing_key(secret_access_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let secret = Zeroizing::new(format!("AWS4{}", secret_access_key));
+    let key = hmac(secret.as_bytes(), date);
+    let key = hmac(&key, region);
+    let key = hmac(&key, service);
+    hmac(&key, "aws4_request")
+}
+
+/// Timestamp in the `YYYYMMDDTHHMMSSZ` format used by signature version 4
+fn amz_date(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, seconds) = ((seconds / 86400) as i64, seconds % 86400);
+
+    // Civil date from days since the epoch, after Howard Hinnant
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_signing() {
+        assert_eq!(amz_date(UNIX_EPOCH), "19700101T000000Z");
+        assert_eq!(
+            amz_date(UNIX_EPOCH + Duration::from_secs(1329264000 + 3723)),
+            "20120215T010203Z"
+        );
+        assert_eq!(
+            amz_date(UNIX_EPOCH + Duration::from_secs(951782400)),
+            "20000229T000000Z"
+        );
+
+        // Example from the AWS signature version 4 documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            hex(&key),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Attributes of a decrypted auth result
+pub type Attributes = Option<HashMap<String, String>>;
+
+/// Configuration of the in-memory cache of decrypted auth results
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct CredentialCacheConfig {
+    /// Maximum number of cached auth results
+    pub capacity: usize,
+    /// Seconds after which a cached auth result is decrypted again
+    pub ttl_secs: u64,
+}
+
+impl Default for CredentialCacheConfig {
+    fn default() -> Self {
+        CredentialCacheConfig {
+            capacity: 1000,
+            ttl_secs: 60,
+        }
+    }
+}
+
+struct Entry {
+    attributes: Attributes,
+    inserted: Instant,
+    last_used: u64,
+}
+
+/// Least recently used cache keyed on session id and auth result hash.
+/// Disabled (zero capacity) until configured.
+struct CredentialCache {
+    config: CredentialCacheConfig,
+    entries: HashMap<(Option<String>, [u8; 32]), Entry>,
+    clock: u64,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<CredentialCache> = Mutex::new(CredentialCache {
+        config: CredentialCacheConfig {
+            capacity: 0,
+            ttl_secs: 0,
+        },
+        entries: HashMap::new(),
+        clock: 0,
+    });
+}
+
+fn key(session_id: Option<&str>, auth_result: &str) -> (Option<String>, [u8; 32]) {
+    (
+        session_id.map(str::to_string),
+        Sha256::digest(auth_result.as_bytes()).into(),
+    )
+}
+
+/// Enable the cache with the given configuration, dropping anything cached so far
+pub fn configure_credential_cache(config: CredentialCacheConfig) {
+    let mut cache = CACHE.lock().expect("Credential cache lock poisoned");
+    cache.config = config;
+    cache.entries.clear();
+}
+
+/// Cached attributes of an auth result, if present and not expired
+pub(crate) fn get(session_id: Option<&str>, auth_result: &str) -> Option<Attributes> {
+    let mut cache = CACHE.lock().expect("Credential cache lock poisoned");
+    if cache.config.capacity == 0 {
+        return None;
+    }
+    let ttl = Duration::from_secs(cache.config.ttl_secs);
+    let key = key(session_id, auth_result);
+
+    cache.clock += 1;
+    let clock = cache.clock;
+    match cache.entries.get_mut(&key) {
+        Some(entry) if entry.inserted.elapsed() < ttl => {
+            entry.last_used = clock;
+            Some(entry.attributes.clone())
+        }
+        Some(_) => {
+            cache.entries.remove(&key);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Cache the attributes of an auth result, evicting the least recently used entry if full
+pub(crate) fn insert(session_id: Option<&str>, auth_result: &str, attributes: Attributes) {
+    let mut cache = CACHE.lock().expect("Credential cache lock poisoned");
+    if cache.config.capacity == 0 {
+        return;
+    }
+
+    if cache.entries.len() >= cache.config.capacity {
+        if let Some(oldest) = cache
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone())
+        {
+            cache.entries.remove(&oldest);
+        }
+    }
+
+    cache.clock += 1;
+    let last_used = cache.clock;
+    cache.entries.insert(
+        key(session_id, auth_result),
+        Entry {
+            attributes,
+            inserted: Instant::now(),
+            last_used,
+        },
+    );
+}
+
+/// Remove everything cached for a session
+pub fn invalidate_session(session_id: &str) {
+    CACHE
+        .lock()
+        .expect("Credential cache lock poisoned")
+        .entries
+        .retain(|(id, _), _| id.as_deref() != Some(session_id));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credential_cache() {
+        configure_credential_cache(CredentialCacheConfig {
+            capacity: 2,
+            ttl_secs: 60,
+        });
+        let attributes: Attributes = Some(HashMap::new());
+
+        insert(Some("a"), "jwe-a", attributes.clone());
+        insert(Some("b"), "jwe-b", attributes.clone());
+        assert_eq!(get(Some("a"), "jwe-a"), Some(attributes.clone()));
+        assert_eq!(get(Some("a"), "jwe-other"), None);
+
+        // b is least recently used, so it is evicted
+        insert(Some("c"), "jwe-c", attributes.clone());
+        assert_eq!(get(Some("b"), "jwe-b"), None);
+        assert!(get(Some("c"), "jwe-c").is_some());
+
+        invalidate_session("a");
+        assert_eq!(get(Some("a"), "jwe-a"), None);
+    }
+}
@@ -0,0 +1,270 @@
+use crate::{error::Error, util::random_token};
+use hmac::{Hmac, Mac, NewMac};
+use reqwest::Url;
+use serde::Deserialize;
+use sha2::Sha256;
+use std::{
+    convert::TryFrom,
+    fmt::Debug,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use zeroize::Zeroizing;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signs and verifies callback URLs, such as the attribute delivery URL the
+/// core calls back with an `attr_id`. Signatures cover the path, an expiry
+/// time and a nonce, so a leaked `attr_id` alone is not enough to call back.
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "String")]
+pub struct CallbackSigner {
+    secret: Zeroizing<Vec<u8>>,
+}
+
+/// Minimum length of the callback secret, the output size of SHA-256
+const MIN_SECRET_LENGTH: usize = 32;
+
+impl TryFrom<String> for CallbackSigner {
+    type Error = String;
+    fn try_from(secret: String) -> Result<Self, String> {
+        if secret.len() < MIN_SECRET_LENGTH {
+            return Err(format!(
+                "Callback secret must be at least {} bytes",
+                MIN_SECRET_LENGTH
+            ));
+        }
+        Ok(CallbackSigner {
+            secret: Zeroizing::new(secret.into_bytes()),
+        })
+    }
+}
+
+impl Debug for CallbackSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallbackSigner").finish()
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl CallbackSigner {
+    fn mac(&self, path: &str, expires: u64, nonce: &str) -> HmacSha256 {
+        // HMAC accepts keys of any length
+        let mut mac = HmacSha256::new_from_slice(&self.secret).unwrap();
+        mac.update(format!("{}\n{}\n{}", path, expires, nonce).as_bytes());
+        mac
+    }
+
+    /// Create a URL for `path` relative to `base_url`, valid for the given duration.
+    /// The signed path is the one the plugin receives requests on, so any
+    /// path prefix of `base_url` stripped by a proxy is not included.
+    pub fn sign_url(
+        &self,
+        base_url: &str,
+        path: &str,
+        valid_for: Duration,
+    ) -> Result<String, Error> {
+        let path = format!("/{}", path.trim_start_matches('/'));
+        let expires = unix_time(SystemTime::now() + valid_for);
+        let nonce = random_token(16);
+        let signature = base64::encode_config(
+            self.mac(&path, expires, &nonce).finalize().into_bytes(),
+            base64::URL_SAFE_NO_PAD,
+        );
+
+        let mut url = Url::parse(&crate::util::join_url(base_url, &path)?)
+            .map_err(|_| Error::BadRequest("Invalid callback URL"))?;
+        url.query_pairs_mut()
+            .append_pair("expires", &expires.to_string())
+            .append_pair("nonce", &nonce)
+            .append_pair("signature", &signature);
+        Ok(url.to_string())
+    }
+
+    /// Check the signature and expiry of a callback to `path`
+    pub fn verify(
+        &self,
+        path: &str,
+        expires: u64,
+        nonce: &str,
+        signature: &str,
+    ) -> Result<(), Error> {
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD)
+            .map_err(|_| Error::Forbidden("Invalid callback signature"))?;
+        self.mac(path, expires, nonce)
+            .verify(&signature)
+            .map_err(|_| Error::Forbidden("Invalid callback signature"))?;
+
+        if expires < unix_time(SystemTime::now()) {
+            return Err(Error::Forbidden("Callback URL expired"));
+        }
+        Ok(())
+    }
+}
+
+/// Request guard, or axum extractor, accepting only requests to URLs signed
+/// with the configured callback secret
+#[cfg(any(feature = "rocket", feature = "axum"))]
+pub struct SignedCallback;
+
+#[cfg(feature = "rocket")]
+mod guard {
+    use super::{CallbackSigner, SignedCallback};
+    use crate::{config::Config, error::Error};
+    use rocket::{
+        http::Status,
+        request::{FromRequest, Outcome},
+        Request,
+    };
+
+    fn verify(request: &Request<'_>, signer: &CallbackSigner) -> Result<(), Error> {
+        let param = |name| {
+            request
+                .query_value::<&str>(name)
+                .and_then(Result::ok)
+                .ok_or(Error::Forbidden("Unsigned callback"))
+        };
+        let expires = param("expires")?
+            .parse()
+            .map_err(|_| Error::Forbidden("Unsigned callback"))?;
+
+        signer.verify(
+            request.uri().path().as_str(),
+            expires,
+            param("nonce")?,
+            param("signature")?,
+        )
+    }
+
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for SignedCallback {
+        type Error = Error;
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let signer = request
+                .rocket()
+                .state::<Config>()
+                .and_then(Config::callback_signer);
+            let result = match signer {
+                Some(signer) => verify(request, signer),
+                None => Err(Error::Forbidden("Callback signing not configured")),
+            };
+
+            match result {
+                Ok(()) => Outcome::Success(SignedCallback),
+                Err(e) => Outcome::Failure((Status::Forbidden, e)),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "axum")]
+mod extractor {
+    use super::SignedCallback;
+    use crate::{config::Config, error::Error, util::query_pairs};
+    use axum::extract::{FromRequest, OriginalUri, RequestParts};
+    use std::sync::Arc;
+
+    #[axum::async_trait]
+    impl<B: Send> FromRequest<B> for SignedCallback {
+        type Rejection = Error;
+
+        async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+            let config = request
+                .extensions()
+                .get::<Arc<Config>>()
+                .cloned()
+                .ok_or(Error::Forbidden("Callback signing not configured"))?;
+            let signer = config
+                .callback_signer()
+                .ok_or(Error::Forbidden("Callback signing not configured"))?;
+            // Nested routers see the path without their prefix
+            let path = match request.extensions().get::<OriginalUri>() {
+                Some(OriginalUri(uri)) => uri.path().to_owned(),
+                None => request.uri().path().to_owned(),
+            };
+
+            let query = query_pairs(request).await;
+            let param = |name| {
+                query
+                    .iter()
+                    .find(|(key, _)| key == name)
+                    .map(|(_, value)| value.as_str())
+                    .ok_or(Error::Forbidden("Unsigned callback"))
+            };
+            let expires = param("expires")?
+                .parse()
+                .map_err(|_| Error::Forbidden("Unsigned callback"))?;
+
+            signer.verify(&path, expires, param("nonce")?, param("signature")?)?;
+            Ok(SignedCallback)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_url() {
+        let signer =
+            CallbackSigner::try_from("a callback secret of at least 32 bytes".to_string()).unwrap();
+        let url = signer
+            .sign_url(
+                "https://comm.example.com/base",
+                "/auth_result/attr",
+                Duration::from_secs(60),
+            )
+            .unwrap();
+        let url = Url::parse(&url).unwrap();
+        assert_eq!(url.path(), "/base/auth_result/attr");
+
+        let param = |name| {
+            url.query_pairs()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.into_owned())
+                .unwrap()
+        };
+        let expires: u64 = param("expires").parse().unwrap();
+        let nonce = param("nonce");
+        let signature = param("signature");
+
+        assert!(signer
+            .verify("/auth_result/attr", expires, &nonce, &signature)
+            .is_ok());
+        assert!(signer
+            .verify("/auth_result/other", expires, &nonce, &signature)
+            .is_err());
+        assert!(signer
+            .verify("/auth_result/attr", expires + 1, &nonce, &signature)
+            .is_err());
+        assert!(
+            CallbackSigner::try_from("another secret of at least 32 bytes".to_string())
+                .unwrap()
+                .verify("/auth_result/attr", expires, &nonce, &signature)
+                .is_err()
+        );
+
+        let expired = signer.mac("/auth_result/attr", 1, &nonce).finalize();
+        let expired = base64::encode_config(expired.into_bytes(), base64::URL_SAFE_NO_PAD);
+        assert!(signer
+            .verify("/auth_result/attr", 1, &nonce, &expired)
+            .is_err());
+    }
+
+    #[test]
+    fn test_callback_secret_length() {
+        assert!(CallbackSigner::try_from(String::new()).is_err());
+        assert!(CallbackSigner::try_from("secret".to_string()).is_err());
+        assert!(serde_yaml::from_str::<CallbackSigner>("secret").is_err());
+        assert!(
+            serde_yaml::from_str::<CallbackSigner>("a callback secret of at least 32 bytes")
+                .is_ok()
+        );
+    }
+}
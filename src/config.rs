@@ -1,11 +1,64 @@
-use crate::error::Error;
+#[cfg(feature = "archive")]
+use crate::archive::{set_archiver, Archiver, RawArchiveConfig};
+#[cfg(feature = "saml")]
+use crate::auth::saml::{RawSamlProviderConfig, SamlProvider};
+#[cfg(feature = "oauth")]
+use crate::auth::{
+    host_session::{configure_host_sessions, HostSessionConfig},
+    irma::{IrmaProvider, RawIrmaProviderConfig},
+    provider::{OAuthProvider, RawOAuthProviderConfig},
+};
+#[cfg(feature = "platform_token")]
+use crate::cache::{configure_credential_cache, CredentialCacheConfig};
+#[cfg(feature = "session_db")]
+use crate::credentials::RenderType;
+#[cfg(feature = "amqp")]
+use crate::events::AmqpConfig;
+#[cfg(feature = "session_db")]
+use crate::lockout::{configure_lockout, LockoutConfig};
+#[cfg(feature = "json_logging")]
+use crate::logging::LoggingConfig;
+#[cfg(feature = "notify")]
+use crate::notify::NotifyConfig;
+#[cfg(feature = "rocket")]
+use crate::proxy::TrustedProxies;
+#[cfg(feature = "session_db")]
+use crate::session::{configure_session_limits, RetentionConfig, SessionLimits};
+#[cfg(feature = "session_db")]
+use crate::share::ShareSigner;
+#[cfg(feature = "auth_during_comm")]
+use crate::util::fill_url_pattern;
+use crate::{
+    api_token::{ApiAuthConfig, RawApiAuthConfig},
+    attr_id::{AttrIdGenerator, HmacAttrIds, RandomAttrIds},
+    callback::CallbackSigner,
+    error::Error,
+    keys::{KeyStore, Keys, RawKeys},
+    retry::RetryConfig,
+    templates::{
+        select_template, set_inline_css, set_template_dir, set_template_settings, TemplateSettings,
+    },
+    transform::TransformerConfig,
+    translations::{
+        set_default_locale, set_strict_translations, set_timezone, set_translations_dir,
+    },
+    util::validate_redirect_url,
+};
 
+use chrono_tz::Tz;
 use id_contact_jwt::{EncryptionKeyConfig, SignKeyConfig};
-use josekit::{jwe::JweDecrypter, jws::JwsVerifier};
+use josekit::{
+    jwe::{JweDecrypter, JweEncrypter},
+    jws::JwsVerifier,
+};
 use serde::Deserialize;
 
-use std::convert::TryFrom;
+#[cfg(feature = "archive")]
+use std::sync::Arc;
+use std::{collections::HashMap, convert::TryFrom, path::PathBuf};
 
+#[cfg(feature = "auth_during_comm")]
+pub use self::auth_during_comm::InstanceConfig;
 #[cfg(feature = "auth_during_comm")]
 pub(crate) use self::auth_during_comm::{AuthDuringCommConfig, RawAuthDuringCommConfig};
 
@@ -19,10 +72,137 @@ pub struct RawConfig {
     /// Sentry DSN
     sentry_dsn: Option<String>,
 
-    /// Private key used to decrypt ID Contact JWEs
-    decryption_privkey: EncryptionKeyConfig,
-    /// Public key used to sign ID Contact JWSs
-    signature_pubkey: SignKeyConfig,
+    /// Private key used to decrypt ID Contact JWEs. Not needed if a key file is configured
+    decryption_privkey: Option<EncryptionKeyConfig>,
+    /// Public key used to sign ID Contact JWSs. Not needed if a key file is configured
+    signature_pubkey: Option<SignKeyConfig>,
+    /// File with the `decryption_privkey` and `signature_pubkey`, which can
+    /// be reloaded while running to rotate keys
+    key_file: Option<PathBuf>,
+    /// Public key of the host, for which credentials can be encrypted
+    host_encryption_pubkey: Option<EncryptionKeyConfig>,
+
+    /// Secret of at least 32 bytes for signing callback URLs, such as the
+    /// attribute delivery URL
+    callback_secret: Option<CallbackSigner>,
+    /// Secret of at least 32 bytes for deriving attr_ids from session ids.
+    /// attr_ids are random if not set
+    attr_id_secret: Option<HmacAttrIds>,
+    /// Bearer token authentication for server-to-server calls
+    #[serde(default)]
+    api_auth: RawApiAuthConfig,
+
+    /// Retry policy for outbound calls
+    #[serde(default)]
+    retry: RetryConfig,
+
+    /// Purposes sessions may be started for. All purposes are allowed if empty
+    #[serde(default)]
+    purposes: HashMap<String, PurposeConfig>,
+    /// Hosts guests may be redirected to, such as `meet.example.com` or
+    /// `*.example.com`. All hosts are allowed if empty
+    #[serde(default)]
+    allowed_redirect_hosts: Vec<String>,
+
+    #[cfg(feature = "platform_token")]
+    /// Cache decrypted auth results in memory. Disabled if not set
+    credential_cache: Option<CredentialCacheConfig>,
+    #[cfg(feature = "session_db")]
+    /// Lockout of clients after repeated invalid host tokens
+    #[serde(default)]
+    lockout: LockoutConfig,
+    #[cfg(feature = "session_db")]
+    /// Maximum numbers of sessions per room and per guest
+    #[serde(default)]
+    session_limits: SessionLimits,
+
+    /// Transformations applied to received attributes before rendering, in order
+    #[serde(default)]
+    transformers: Vec<TransformerConfig>,
+
+    #[cfg(feature = "oauth")]
+    /// Lifetime of the sessions of logged in hosts
+    #[serde(default)]
+    host_sessions: HostSessionConfig,
+    #[cfg(feature = "oauth")]
+    /// Providers hosts can log in with, by name
+    #[serde(default)]
+    oauth_providers: HashMap<String, RawOAuthProviderConfig>,
+    #[cfg(feature = "oauth")]
+    /// IRMA or Yivi servers hosts can log in with, by name
+    #[serde(default)]
+    irma_providers: HashMap<String, RawIrmaProviderConfig>,
+    #[cfg(feature = "saml")]
+    /// SAML 2.0 identity providers hosts can log in with, by name
+    #[serde(default)]
+    saml_providers: HashMap<String, RawSamlProviderConfig>,
+
+    /// Directory containing custom templates. Embedded templates are used for any not found there
+    template_dir: Option<PathBuf>,
+    /// Autoescaping, allowed functions and sandboxing of templates
+    template_settings: Option<TemplateSettings>,
+    /// Directory containing custom translation files. Embedded translations are used if not found there
+    translations_dir: Option<PathBuf>,
+    /// Timezone timestamps are rendered in, such as `Europe/Amsterdam`
+    timezone: Option<String>,
+    /// Locale used when none of the requested locales are available
+    default_locale: Option<String>,
+    /// Fail rendering on keys the requested locale does not translate, for
+    /// catching untranslated pages in tests and development
+    #[serde(default)]
+    strict_translations: bool,
+    /// Inline the default stylesheet into rendered pages
+    #[serde(default)]
+    inline_default_css: bool,
+    /// Custom stylesheet inlined into rendered pages
+    custom_css: Option<String>,
+    /// Show guests whose auth result expired as such, without their
+    /// attributes. Expiry is not checked by default
+    #[serde(default)]
+    check_auth_result_expiry: bool,
+
+    #[cfg(feature = "session_db")]
+    /// How long sessions are kept
+    #[serde(default)]
+    retention: RetentionConfig,
+    #[cfg(feature = "archive")]
+    /// Archival of session summaries before they are removed. Disabled if not set
+    archive: Option<RawArchiveConfig>,
+    #[cfg(feature = "session_db")]
+    /// Session database queries taking longer than this are logged
+    slow_query_threshold_ms: Option<u64>,
+    #[cfg(feature = "session_db")]
+    /// Seconds a long polling host waits for changes, 30 by default
+    long_poll_timeout_secs: Option<u64>,
+    #[cfg(feature = "session_db")]
+    /// Secret of at least 32 bytes for signing read-only share links to the
+    /// credentials of a room. Sharing is disabled if not set
+    share_secret: Option<ShareSigner>,
+    #[cfg(feature = "session_db")]
+    /// Seconds share links are valid for, 15 minutes by default
+    share_link_ttl_secs: Option<u64>,
+    #[cfg(feature = "session_db")]
+    /// Responses to hosts viewing a room no guest has joined yet
+    #[serde(default)]
+    empty_room: EmptyRoomConfig,
+
+    #[cfg(feature = "amqp")]
+    /// AMQP exchange to notify of received auth results
+    amqp: Option<AmqpConfig>,
+
+    #[cfg(feature = "notify")]
+    /// SMTP settings for e-mail notifications of completed authentications
+    notify: Option<NotifyConfig>,
+
+    #[cfg(feature = "json_logging")]
+    /// Log format and filtering
+    #[serde(default)]
+    logging: LoggingConfig,
+
+    #[cfg(feature = "rocket")]
+    /// Address ranges of reverse proxies whose forwarding headers are believed
+    #[serde(default)]
+    trusted_proxies: TrustedProxies,
 
     #[cfg(feature = "auth_during_comm")]
     #[serde(flatten)]
@@ -30,6 +210,86 @@ pub struct RawConfig {
     auth_during_comm_config: RawAuthDuringCommConfig,
 }
 
+/// Response to a host viewing a room no guest has joined yet
+#[cfg(feature = "session_db")]
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmptyRoomResponse {
+    /// Fail with [`Error::NotFound`]
+    NotFound,
+    /// Render the room, with a "no guests yet" notice in HTML
+    Render,
+}
+
+/// Responses to hosts viewing a room no guest has joined yet, by render
+/// type. JSON requests get a 404 and HTML requests a notice by default.
+#[cfg(feature = "session_db")]
+#[derive(Deserialize, Debug, Clone)]
+pub struct EmptyRoomConfig {
+    #[serde(default = "EmptyRoomConfig::not_found")]
+    json: EmptyRoomResponse,
+    #[serde(default = "EmptyRoomConfig::render")]
+    html: EmptyRoomResponse,
+    #[serde(default = "EmptyRoomConfig::render")]
+    html_page: EmptyRoomResponse,
+}
+
+#[cfg(feature = "session_db")]
+impl EmptyRoomConfig {
+    fn not_found() -> EmptyRoomResponse {
+        EmptyRoomResponse::NotFound
+    }
+
+    fn render() -> EmptyRoomResponse {
+        EmptyRoomResponse::Render
+    }
+
+    /// Response to rooms without guests for the given render type
+    pub fn response(&self, render_type: RenderType) -> EmptyRoomResponse {
+        match render_type {
+            RenderType::Json => self.json,
+            RenderType::Html => self.html,
+            RenderType::HtmlPage => self.html_page,
+        }
+    }
+}
+
+#[cfg(feature = "session_db")]
+impl Default for EmptyRoomConfig {
+    fn default() -> Self {
+        EmptyRoomConfig {
+            json: EmptyRoomResponse::NotFound,
+            html: EmptyRoomResponse::Render,
+            html_page: EmptyRoomResponse::Render,
+        }
+    }
+}
+
+/// Configuration of a purpose sessions can be started for
+#[derive(Deserialize, Debug, Clone)]
+pub struct PurposeConfig {
+    /// Name of the purpose as presented to users
+    pub display_name: String,
+    /// Attributes requested for this purpose
+    #[serde(default)]
+    pub attributes: Vec<String>,
+    /// Authentication methods permitted for this purpose. All methods are permitted if empty
+    #[serde(default)]
+    pub auth_methods: Vec<String>,
+    /// Alternative templates for this purpose, by the name of the template they
+    /// replace, e.g. `credentials.html: credentials_wmo.html`
+    #[serde(default)]
+    pub templates: HashMap<String, String>,
+}
+
+impl PurposeConfig {
+    /// Name of the template to render in place of `name` for this purpose,
+    /// falling back to `name` itself if no alternative is configured or loaded
+    pub fn template<'a>(&'a self, name: &'a str) -> &'a str {
+        select_template(name, self.templates.get(name).map(String::as_str))
+    }
+}
+
 /// configuration container for a typical id-contact communication plugin
 #[derive(Debug, Deserialize)]
 #[serde(try_from = "RawConfig")]
@@ -38,14 +298,120 @@ pub struct Config {
     pub external_url: Option<String>,
     pub sentry_dsn: Option<String>,
 
-    pub decrypter: Box<dyn JweDecrypter>,
-    pub validator: Box<dyn JwsVerifier>,
+    pub keys: KeyStore,
+    pub host_encrypter: Option<Box<dyn JweEncrypter>>,
+    pub callback_signer: Option<CallbackSigner>,
+    pub attr_ids: Box<dyn AttrIdGenerator>,
+    pub api_auth: ApiAuthConfig,
+
+    pub retry: RetryConfig,
+    pub purposes: HashMap<String, PurposeConfig>,
+    pub allowed_redirect_hosts: Vec<String>,
+    pub transformers: Vec<TransformerConfig>,
+    pub check_auth_result_expiry: bool,
+    #[cfg(feature = "oauth")]
+    pub oauth_providers: HashMap<String, OAuthProvider>,
+    #[cfg(feature = "oauth")]
+    pub irma_providers: HashMap<String, IrmaProvider>,
+    #[cfg(feature = "saml")]
+    pub saml_providers: HashMap<String, SamlProvider>,
+
+    #[cfg(feature = "session_db")]
+    pub retention: RetentionConfig,
+    #[cfg(feature = "session_db")]
+    pub long_poll_timeout_secs: Option<u64>,
+    #[cfg(feature = "session_db")]
+    pub share_signer: Option<ShareSigner>,
+    #[cfg(feature = "session_db")]
+    pub share_link_ttl_secs: Option<u64>,
+    #[cfg(feature = "session_db")]
+    pub empty_room: EmptyRoomConfig,
+
+    #[cfg(feature = "amqp")]
+    pub amqp: Option<AmqpConfig>,
+
+    #[cfg(feature = "notify")]
+    pub notify: Option<NotifyConfig>,
+
+    #[cfg(feature = "json_logging")]
+    pub logging: LoggingConfig,
+
+    #[cfg(feature = "rocket")]
+    pub trusted_proxies: TrustedProxies,
+
+    pub process_settings: ProcessSettings,
 
     #[cfg(feature = "auth_during_comm")]
     #[serde(flatten)]
     pub auth_during_comm_config: AuthDuringCommConfig,
 }
 
+/// Settings kept in process-wide state, such as the template directory and
+/// the lockout of clients. Parsing a configuration leaves that state alone;
+/// it is only changed by [`Config::install`].
+#[derive(Debug, Default)]
+pub struct ProcessSettings {
+    pub template_dir: Option<PathBuf>,
+    pub template_settings: Option<TemplateSettings>,
+    pub inline_default_css: bool,
+    pub custom_css: Option<String>,
+    pub translations_dir: Option<PathBuf>,
+    pub default_locale: Option<String>,
+    pub strict_translations: bool,
+    pub timezone: Option<Tz>,
+    #[cfg(feature = "platform_token")]
+    pub credential_cache: Option<CredentialCacheConfig>,
+    #[cfg(feature = "session_db")]
+    pub lockout: LockoutConfig,
+    #[cfg(feature = "session_db")]
+    pub session_limits: SessionLimits,
+    #[cfg(feature = "session_db")]
+    pub slow_query_threshold_ms: Option<u64>,
+    #[cfg(feature = "oauth")]
+    pub host_sessions: HostSessionConfig,
+    #[cfg(feature = "archive")]
+    pub archiver: Option<Arc<Archiver>>,
+}
+
+impl ProcessSettings {
+    fn install(&self) -> Result<(), Error> {
+        if let Some(template_dir) = &self.template_dir {
+            set_template_dir(template_dir);
+        }
+        if let Some(template_settings) = &self.template_settings {
+            set_template_settings(template_settings.clone());
+        }
+        set_inline_css(self.inline_default_css, self.custom_css.as_deref());
+        if let Some(translations_dir) = &self.translations_dir {
+            set_translations_dir(translations_dir);
+        }
+        if let Some(default_locale) = &self.default_locale {
+            set_default_locale(default_locale)?;
+        }
+        set_strict_translations(self.strict_translations);
+        if let Some(timezone) = self.timezone {
+            set_timezone(timezone);
+        }
+        #[cfg(feature = "platform_token")]
+        if let Some(credential_cache) = &self.credential_cache {
+            configure_credential_cache(credential_cache.clone());
+        }
+        #[cfg(feature = "session_db")]
+        configure_lockout(self.lockout.clone());
+        #[cfg(feature = "session_db")]
+        configure_session_limits(self.session_limits.clone());
+        #[cfg(feature = "session_db")]
+        if let Some(threshold) = self.slow_query_threshold_ms {
+            crate::metrics::set_slow_query_threshold(std::time::Duration::from_millis(threshold));
+        }
+        #[cfg(feature = "oauth")]
+        configure_host_sessions(self.host_sessions.clone());
+        #[cfg(feature = "archive")]
+        set_archiver(self.archiver.clone());
+        Ok(())
+    }
+}
+
 // This tryfrom can be removed once try_from for fields lands in serde
 impl TryFrom<RawConfig> for Config {
     type Error = Error;
@@ -54,6 +420,57 @@ impl TryFrom<RawConfig> for Config {
         let auth_during_comm_config =
             AuthDuringCommConfig::try_from(raw_config.auth_during_comm_config)?;
 
+        let process_settings = ProcessSettings {
+            template_dir: raw_config.template_dir,
+            template_settings: raw_config.template_settings,
+            inline_default_css: raw_config.inline_default_css,
+            custom_css: raw_config.custom_css,
+            translations_dir: raw_config.translations_dir,
+            default_locale: raw_config.default_locale,
+            strict_translations: raw_config.strict_translations,
+            timezone: raw_config
+                .timezone
+                .map(|timezone| {
+                    timezone.parse().map_err(|_| {
+                        log::error!("Unknown timezone {}", timezone);
+                        Error::Config("Invalid timezone")
+                    })
+                })
+                .transpose()?,
+            #[cfg(feature = "platform_token")]
+            credential_cache: raw_config.credential_cache,
+            #[cfg(feature = "session_db")]
+            lockout: raw_config.lockout,
+            #[cfg(feature = "session_db")]
+            session_limits: raw_config.session_limits,
+            #[cfg(feature = "session_db")]
+            slow_query_threshold_ms: raw_config.slow_query_threshold_ms,
+            #[cfg(feature = "oauth")]
+            host_sessions: raw_config.host_sessions,
+            #[cfg(feature = "archive")]
+            archiver: raw_config
+                .archive
+                .map(Archiver::try_from)
+                .transpose()?
+                .map(Arc::new),
+        };
+
+        let raw_keys =
+            match (
+                &raw_config.key_file,
+                raw_config.decryption_privkey,
+                raw_config.signature_pubkey,
+            ) {
+                (Some(key_file), None, None) => RawKeys::from_file(key_file)?,
+                (None, Some(decryption_privkey), Some(signature_pubkey)) => RawKeys {
+                    decryption_privkey,
+                    signature_pubkey,
+                },
+                _ => return Err(Error::Config(
+                    "Configure either a key file or both decryption_privkey and signature_pubkey",
+                )),
+            };
+
         Ok(Config {
             #[cfg(feature = "auth_during_comm")]
             auth_during_comm_config,
@@ -61,19 +478,133 @@ impl TryFrom<RawConfig> for Config {
             external_url: raw_config.external_url,
             sentry_dsn: raw_config.sentry_dsn,
 
-            decrypter: Box::<dyn JweDecrypter>::try_from(raw_config.decryption_privkey)?,
-            validator: Box::<dyn JwsVerifier>::try_from(raw_config.signature_pubkey)?,
+            keys: KeyStore::new(Keys::try_from(raw_keys)?, raw_config.key_file),
+            host_encrypter: raw_config
+                .host_encryption_pubkey
+                .map(Box::<dyn JweEncrypter>::try_from)
+                .transpose()?,
+            callback_signer: raw_config.callback_secret,
+            attr_ids: match raw_config.attr_id_secret {
+                Some(generator) => Box::new(generator),
+                None => Box::new(RandomAttrIds),
+            },
+            api_auth: ApiAuthConfig::try_from(raw_config.api_auth)?,
+            retry: raw_config.retry,
+            purposes: raw_config.purposes,
+            allowed_redirect_hosts: raw_config.allowed_redirect_hosts,
+            transformers: raw_config.transformers,
+            check_auth_result_expiry: raw_config.check_auth_result_expiry,
+            #[cfg(feature = "oauth")]
+            oauth_providers: raw_config
+                .oauth_providers
+                .into_iter()
+                .map(|(name, provider)| Ok((name, OAuthProvider::try_from(provider)?)))
+                .collect::<Result<_, Error>>()?,
+            #[cfg(feature = "oauth")]
+            irma_providers: raw_config
+                .irma_providers
+                .into_iter()
+                .map(|(name, provider)| Ok((name, IrmaProvider::try_from(provider)?)))
+                .collect::<Result<_, Error>>()?,
+            #[cfg(feature = "saml")]
+            saml_providers: raw_config
+                .saml_providers
+                .into_iter()
+                .map(|(name, provider)| Ok((name, SamlProvider::try_from(provider)?)))
+                .collect::<Result<_, Error>>()?,
+            #[cfg(feature = "session_db")]
+            retention: raw_config.retention,
+            #[cfg(feature = "session_db")]
+            long_poll_timeout_secs: raw_config.long_poll_timeout_secs,
+            #[cfg(feature = "session_db")]
+            share_signer: raw_config.share_secret,
+            #[cfg(feature = "session_db")]
+            share_link_ttl_secs: raw_config.share_link_ttl_secs,
+            #[cfg(feature = "session_db")]
+            empty_room: raw_config.empty_room,
+            #[cfg(feature = "amqp")]
+            amqp: raw_config.amqp,
+            #[cfg(feature = "notify")]
+            notify: raw_config.notify,
+            #[cfg(feature = "json_logging")]
+            logging: raw_config.logging,
+            #[cfg(feature = "rocket")]
+            trusted_proxies: raw_config.trusted_proxies,
+            process_settings,
         })
     }
 }
 
 impl Config {
-    pub fn decrypter(&self) -> &dyn JweDecrypter {
-        self.decrypter.as_ref()
+    /// Parse a YAML or JSON configuration. Malformed input results in an
+    /// error, never a panic.
+    pub fn from_yaml(source: &str) -> Result<Config, Error> {
+        serde_yaml::from_str(source).map_err(|e| {
+            log::error!("Invalid configuration: {}", e);
+            Error::Config("Invalid configuration")
+        })
+    }
+
+    /// Apply the settings kept in process-wide state, such as the template
+    /// directory, translations, credential cache and lockout. Call this once
+    /// after parsing, for the configuration the process serves with;
+    /// installing another configuration replaces these settings.
+    pub fn install(&self) -> Result<(), Error> {
+        self.process_settings.install()
+    }
+
+    /// Keys for ID Contact JWEs, which may be replaced while running
+    pub fn keys(&self) -> &KeyStore {
+        &self.keys
+    }
+
+    /// Decrypter for ID Contact JWEs, with the keys in use right now
+    #[deprecated(note = "use `keys().current().decrypter()`, which follows key rotation")]
+    pub fn decrypter(&self) -> Box<dyn JweDecrypter> {
+        self.keys.current().decrypter().box_clone()
+    }
+
+    /// Verifier for ID Contact JWSs, with the keys in use right now
+    #[deprecated(note = "use `keys().current().validator()`, which follows key rotation")]
+    pub fn validator(&self) -> Box<dyn JwsVerifier> {
+        self.keys.current().validator().box_clone()
+    }
+
+    /// Encrypter for credentials handed to hosts, if a host key is configured
+    pub fn host_encrypter(&self) -> Option<&dyn JweEncrypter> {
+        self.host_encrypter.as_deref()
+    }
+
+    /// Signer for callback URLs, if a callback secret is configured
+    pub fn callback_signer(&self) -> Option<&CallbackSigner> {
+        self.callback_signer.as_ref()
+    }
+
+    /// Generator of attr_ids for new sessions
+    pub fn attr_id_generator(&self) -> &dyn AttrIdGenerator {
+        self.attr_ids.as_ref()
+    }
+
+    /// Provider hosts can log in with, by its name in the configuration
+    #[cfg(feature = "oauth")]
+    pub fn oauth_provider(&self, name: &str) -> Result<&OAuthProvider, Error> {
+        self.oauth_providers.get(name).ok_or(Error::NotFound)
+    }
+
+    /// IRMA server hosts can log in with, by its name in the configuration
+    #[cfg(feature = "oauth")]
+    pub fn irma_provider(&self, name: &str) -> Result<&IrmaProvider, Error> {
+        self.irma_providers.get(name).ok_or(Error::NotFound)
+    }
+
+    /// SAML identity provider hosts can log in with, by its name in the configuration
+    #[cfg(feature = "saml")]
+    pub fn saml_provider(&self, name: &str) -> Result<&SamlProvider, Error> {
+        self.saml_providers.get(name).ok_or(Error::NotFound)
     }
 
-    pub fn validator(&self) -> &dyn JwsVerifier {
-        self.validator.as_ref()
+    pub fn api_auth(&self) -> &ApiAuthConfig {
+        &self.api_auth
     }
 
     pub fn internal_url(&self) -> &str {
@@ -91,29 +622,197 @@ impl Config {
         self.sentry_dsn.as_deref()
     }
 
+    pub fn retry_config(&self) -> &RetryConfig {
+        &self.retry
+    }
+
+    #[cfg(feature = "session_db")]
+    pub fn retention_config(&self) -> &RetentionConfig {
+        &self.retention
+    }
+
+    #[cfg(feature = "session_db")]
+    pub fn long_poll_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.long_poll_timeout_secs.unwrap_or(30))
+    }
+
+    /// Responses to hosts viewing a room no guest has joined yet
+    #[cfg(feature = "session_db")]
+    pub fn empty_room_config(&self) -> &EmptyRoomConfig {
+        &self.empty_room
+    }
+
+    /// Signer for share links, if a share secret is configured
+    #[cfg(feature = "session_db")]
+    pub fn share_signer(&self) -> Option<&ShareSigner> {
+        self.share_signer.as_ref()
+    }
+
+    #[cfg(feature = "session_db")]
+    pub fn share_link_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.share_link_ttl_secs.unwrap_or(15 * 60))
+    }
+
+    #[cfg(feature = "json_logging")]
+    pub fn logging_config(&self) -> &LoggingConfig {
+        &self.logging
+    }
+
+    #[cfg(feature = "rocket")]
+    pub fn trusted_proxies(&self) -> &TrustedProxies {
+        &self.trusted_proxies
+    }
+
+    #[cfg(feature = "amqp")]
+    pub fn amqp_config(&self) -> Option<&AmqpConfig> {
+        self.amqp.as_ref()
+    }
+
+    #[cfg(feature = "notify")]
+    pub fn notify_config(&self) -> Option<&NotifyConfig> {
+        self.notify.as_ref()
+    }
+
+    /// Configuration of the given purpose, if known
+    pub fn purpose(&self, purpose: &str) -> Option<&PurposeConfig> {
+        self.purposes.get(purpose)
+    }
+
+    /// Whether guests whose auth result expired are shown as such
+    pub fn checks_auth_result_expiry(&self) -> bool {
+        self.check_auth_result_expiry
+    }
+
+    pub fn transformers(&self) -> &[TransformerConfig] {
+        &self.transformers
+    }
+
+    /// Attributes allowed in auth results for the given purpose. `None` if
+    /// the purpose does not restrict its attributes.
+    pub fn allowed_attributes(&self, purpose: &str) -> Option<&[String]> {
+        self.purpose(purpose)
+            .map(|p| p.attributes.as_slice())
+            .filter(|attributes| !attributes.is_empty())
+    }
+
+    /// Check that sessions may be started for the given purpose
+    pub fn validate_purpose(&self, purpose: &str) -> Result<(), Error> {
+        if self.purposes.is_empty() || self.purposes.contains_key(purpose) {
+            Ok(())
+        } else {
+            Err(Error::UnknownPurpose(purpose.to_string()))
+        }
+    }
+
+    /// Check that guests may be redirected to the given URL
+    pub fn validate_redirect_url(&self, url: &str) -> Result<(), Error> {
+        validate_redirect_url(url, &self.allowed_redirect_hosts)
+    }
+
+    /// Check that the given authentication method is permitted for the given purpose
+    pub fn validate_auth_method(&self, purpose: &str, auth_method: &str) -> Result<(), Error> {
+        self.validate_purpose(purpose)?;
+        match self.purpose(purpose) {
+            Some(purpose_config)
+                if !purpose_config.auth_methods.is_empty()
+                    && !purpose_config.auth_methods.iter().any(|m| m == auth_method) =>
+            {
+                Err(Error::AuthMethodNotPermitted {
+                    auth_method: auth_method.to_string(),
+                    permitted: purpose_config.auth_methods.clone(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
     #[cfg(feature = "auth_during_comm")]
     pub fn auth_during_comm_config(&self) -> &AuthDuringCommConfig {
         &self.auth_during_comm_config
     }
+
+    /// Start URL to embed in the auth select parameters, built from the
+    /// external URL and the configured start URL pattern. All values are
+    /// percent-encoded, so they cannot change the origin or path structure.
+    #[cfg(feature = "auth_during_comm")]
+    pub fn start_url(
+        &self,
+        purpose: &str,
+        session_id: &str,
+        attr_id: &str,
+        guest_token: &str,
+    ) -> Result<String, Error> {
+        fill_url_pattern(
+            self.external_url(),
+            self.auth_during_comm_config.start_url_pattern(),
+            &auth_during_comm::start_url_values(purpose, session_id, attr_id, guest_token),
+        )
+    }
+
+    /// Link for hosts to have a guest with an expired auth result verify
+    /// again, built from the external URL and the configured reverify URL
+    /// pattern. `None` if no pattern is configured.
+    #[cfg(feature = "auth_during_comm")]
+    pub fn reverify_url(&self, purpose: &str, session_id: &str) -> Result<Option<String>, Error> {
+        self.auth_during_comm_config
+            .reverify_url_pattern()
+            .map(|pattern| {
+                fill_url_pattern(
+                    self.external_url(),
+                    pattern,
+                    &auth_during_comm::reverify_url_values(purpose, session_id),
+                )
+            })
+            .transpose()
+    }
 }
 
 #[cfg(feature = "auth_during_comm")]
 mod auth_during_comm {
-    use id_contact_jwt::SignKeyConfig;
+    use id_contact_jwt::{EncryptionKeyConfig, SignKeyConfig};
     use serde::Deserialize;
-    use std::{convert::TryFrom, fmt::Debug};
+    use std::{collections::HashMap, convert::TryFrom, fmt::Debug};
+    use zeroize::Zeroizing;
+
+    use josekit::{
+        jwe::JweEncrypter,
+        jws::{alg::hmac::HmacJwsAlgorithm, JwsSigner, JwsVerifier},
+    };
 
-    use josekit::jws::{alg::hmac::HmacJwsAlgorithm, JwsSigner, JwsVerifier};
+    use crate::{error::Error, jwt::JwtError, util::fill_url_pattern};
 
-    use crate::error::Error;
+    /// Start URL pattern used when none is configured
+    const DEFAULT_START_URL_PATTERN: &str = "start/{purpose}/{guest_token}";
 
+    pub(super) fn start_url_values<'a>(
+        purpose: &'a str,
+        session_id: &'a str,
+        attr_id: &'a str,
+        guest_token: &'a str,
+    ) -> [(&'static str, &'a str); 4] {
+        [
+            ("purpose", purpose),
+            ("session_id", session_id),
+            ("attr_id", attr_id),
+            ("guest_token", guest_token),
+        ]
+    }
+
+    pub(super) fn reverify_url_values<'a>(
+        purpose: &'a str,
+        session_id: &'a str,
+    ) -> [(&'static str, &'a str); 2] {
+        [("purpose", purpose), ("session_id", session_id)]
+    }
+
+    /// Shared secret for HS256 platform tokens, wiped from memory when dropped
     #[derive(Deserialize)]
     #[serde(from = "String")]
-    struct TokenSecret(String);
+    struct TokenSecret(Zeroizing<String>);
 
     impl From<String> for TokenSecret {
         fn from(value: String) -> Self {
-            TokenSecret(value)
+            TokenSecret(Zeroizing::new(value))
         }
     }
 
@@ -123,6 +822,71 @@ mod auth_during_comm {
         }
     }
 
+    /// Key material for verifying platform tokens. Either a shared secret,
+    /// used with HS256, or a public key, used with RS256 or ES256.
+    #[derive(Deserialize, Debug)]
+    #[serde(untagged)]
+    enum TokenKeyConfig {
+        Secret(TokenSecret),
+        PublicKey(SignKeyConfig),
+    }
+
+    impl TryFrom<TokenKeyConfig> for Box<dyn JwsVerifier> {
+        type Error = Error;
+        fn try_from(key_config: TokenKeyConfig) -> Result<Self, Error> {
+            match key_config {
+                TokenKeyConfig::Secret(secret) => Ok(Box::new(
+                    HmacJwsAlgorithm::Hs256
+                        .verifier_from_bytes(secret.0.as_bytes())
+                        .map_err(JwtError::from)?,
+                )),
+                TokenKeyConfig::PublicKey(key) => Ok(Box::<dyn JwsVerifier>::try_from(key)?),
+            }
+        }
+    }
+
+    #[derive(Deserialize, Debug)]
+    /// Configuration for a single communication platform instance (tenant)
+    pub struct RawInstanceConfig {
+        /// Display name for this instance, defaults to the plugin display name
+        display_name: Option<String>,
+        /// Private key to sign widget parameters, defaults to the plugin key
+        widget_signing_privkey: Option<SignKeyConfig>,
+        /// Key id of the instance's widget signing key, set as `kid` header
+        widget_signing_key_id: Option<String>,
+        /// Secret or public key for verifying guest tokens of this instance
+        #[serde(alias = "guest_signature_secret")]
+        guest_signature_key: TokenKeyConfig,
+        /// Secret or public key for verifying host tokens of this instance
+        #[serde(alias = "host_signature_secret")]
+        host_signature_key: TokenKeyConfig,
+    }
+
+    #[derive(Debug)]
+    pub struct InstanceConfig {
+        pub(crate) display_name: Option<String>,
+        pub(crate) widget_signer: Option<Box<dyn JwsSigner>>,
+        pub(crate) widget_key_id: Option<String>,
+        pub(crate) guest_validator: Box<dyn JwsVerifier>,
+        pub(crate) host_validator: Box<dyn JwsVerifier>,
+    }
+
+    impl TryFrom<RawInstanceConfig> for InstanceConfig {
+        type Error = Error;
+        fn try_from(raw_config: RawInstanceConfig) -> Result<InstanceConfig, Error> {
+            Ok(InstanceConfig {
+                display_name: raw_config.display_name,
+                widget_signer: raw_config
+                    .widget_signing_privkey
+                    .map(Box::<dyn JwsSigner>::try_from)
+                    .transpose()?,
+                widget_key_id: raw_config.widget_signing_key_id,
+                guest_validator: Box::<dyn JwsVerifier>::try_from(raw_config.guest_signature_key)?,
+                host_validator: Box::<dyn JwsVerifier>::try_from(raw_config.host_signature_key)?,
+            })
+        }
+    }
+
     #[derive(Deserialize, Debug)]
     /// Configuration specific for auth during comm
     pub struct RawAuthDuringCommConfig {
@@ -132,16 +896,39 @@ mod auth_during_comm {
         widget_url: String,
         /// Display name for this plugin, to be presented to user
         display_name: String,
-        /// Private key to sign widget parameters
-        widget_signing_privkey: SignKeyConfig,
+        /// Path of the start URL passed to the widget, relative to the
+        /// external URL, with `{purpose}`, `{session_id}`, `{attr_id}` and
+        /// `{guest_token}` placeholders. `start/{purpose}/{guest_token}` by
+        /// default
+        start_url_pattern: Option<String>,
+        /// Path of the link hosts get to have a guest with an expired auth
+        /// result verify again, relative to the external URL, with
+        /// `{purpose}` and `{session_id}` placeholders. No link if not set
+        reverify_url_pattern: Option<String>,
+        /// Private key to sign widget parameters. Not needed if the active
+        /// key is one of `widget_signing_keys`
+        widget_signing_privkey: Option<SignKeyConfig>,
+        /// Key id of the active widget signing key, set as `kid` header
+        widget_signing_key_id: Option<String>,
+        /// Widget signing keys by key id, so the active key can be switched
+        /// by changing `widget_signing_key_id` only
+        #[serde(default)]
+        widget_signing_keys: HashMap<String, SignKeyConfig>,
+        /// Public key of the widget, to additionally encrypt widget parameters for
+        widget_encryption_pubkey: Option<EncryptionKeyConfig>,
         /// Private key to sign start authenticate requests
         start_auth_signing_privkey: SignKeyConfig,
         /// Key Identifier of start authentication key
         start_auth_key_id: String,
-        /// Secret for verifying guest tokens
-        guest_signature_secret: TokenSecret,
-        /// Secret for verifying host tokens
-        host_signature_secret: TokenSecret,
+        /// Secret or public key for verifying guest tokens
+        #[serde(alias = "guest_signature_secret")]
+        guest_signature_key: TokenKeyConfig,
+        /// Secret or public key for verifying host tokens
+        #[serde(alias = "host_signature_secret")]
+        host_signature_key: TokenKeyConfig,
+        /// Per-instance overrides, keyed by the instance id in platform tokens
+        #[serde(default)]
+        instances: HashMap<String, RawInstanceConfig>,
     }
 
     #[derive(Debug, Deserialize)]
@@ -150,36 +937,87 @@ mod auth_during_comm {
         pub(crate) core_url: String,
         pub(crate) widget_url: String,
         pub(crate) display_name: String,
+        pub(crate) start_url_pattern: String,
+        pub(crate) reverify_url_pattern: Option<String>,
         pub(crate) widget_signer: Box<dyn JwsSigner>,
+        pub(crate) widget_key_id: Option<String>,
+        pub(crate) widget_signers: HashMap<String, Box<dyn JwsSigner>>,
+        pub(crate) widget_encrypter: Option<Box<dyn JweEncrypter>>,
         pub(crate) start_auth_signer: Box<dyn JwsSigner>,
         pub(crate) start_auth_key_id: String,
         pub(crate) guest_validator: Box<dyn JwsVerifier>,
         pub(crate) host_validator: Box<dyn JwsVerifier>,
+        pub(crate) instances: HashMap<String, InstanceConfig>,
     }
 
     // This tryfrom can be removed once try_from for fields lands in serde
     impl TryFrom<RawAuthDuringCommConfig> for AuthDuringCommConfig {
         type Error = Error;
         fn try_from(raw_config: RawAuthDuringCommConfig) -> Result<AuthDuringCommConfig, Error> {
-            let guest_validator = HmacJwsAlgorithm::Hs256
-                .verifier_from_bytes(raw_config.guest_signature_secret.0)
-                .unwrap();
-            let host_validator = HmacJwsAlgorithm::Hs256
-                .verifier_from_bytes(raw_config.host_signature_secret.0)
-                .unwrap();
+            let mut widget_signers = raw_config
+                .widget_signing_keys
+                .into_iter()
+                .map(|(kid, key)| Ok((kid, Box::<dyn JwsSigner>::try_from(key)?)))
+                .collect::<Result<HashMap<_, _>, Error>>()?;
+            let active_signer = raw_config
+                .widget_signing_key_id
+                .as_ref()
+                .and_then(|kid| widget_signers.remove(kid));
+            let widget_signer = match (active_signer, raw_config.widget_signing_privkey) {
+                (Some(_), Some(_)) => {
+                    return Err(Error::Config(
+                        "Active widget key is configured both as widget_signing_privkey and in widget_signing_keys",
+                    ))
+                }
+                (Some(signer), None) => signer,
+                (None, Some(key)) => Box::<dyn JwsSigner>::try_from(key)?,
+                (None, None) => {
+                    return Err(Error::Config("No active widget signing key configured"))
+                }
+            };
+
+            let start_url_pattern = raw_config
+                .start_url_pattern
+                .unwrap_or_else(|| DEFAULT_START_URL_PATTERN.to_string());
+            // Check the pattern up front, rather than when the first guest starts
+            fill_url_pattern(
+                "https://example.com",
+                &start_url_pattern,
+                &start_url_values("purpose", "session", "attr", "token"),
+            )?;
+            if let Some(pattern) = &raw_config.reverify_url_pattern {
+                fill_url_pattern(
+                    "https://example.com",
+                    pattern,
+                    &reverify_url_values("purpose", "session"),
+                )?;
+            }
 
             Ok(AuthDuringCommConfig {
                 core_url: raw_config.core_url,
                 widget_url: raw_config.widget_url,
                 display_name: raw_config.display_name,
+                start_url_pattern,
+                reverify_url_pattern: raw_config.reverify_url_pattern,
 
-                widget_signer: Box::<dyn JwsSigner>::try_from(raw_config.widget_signing_privkey)?,
+                widget_signer,
+                widget_key_id: raw_config.widget_signing_key_id,
+                widget_signers,
+                widget_encrypter: raw_config
+                    .widget_encryption_pubkey
+                    .map(Box::<dyn JweEncrypter>::try_from)
+                    .transpose()?,
                 start_auth_signer: Box::<dyn JwsSigner>::try_from(
                     raw_config.start_auth_signing_privkey,
                 )?,
                 start_auth_key_id: raw_config.start_auth_key_id,
-                guest_validator: Box::new(guest_validator),
-                host_validator: Box::new(host_validator),
+                guest_validator: Box::<dyn JwsVerifier>::try_from(raw_config.guest_signature_key)?,
+                host_validator: Box::<dyn JwsVerifier>::try_from(raw_config.host_signature_key)?,
+                instances: raw_config
+                    .instances
+                    .into_iter()
+                    .map(|(id, instance)| Ok((id, InstanceConfig::try_from(instance)?)))
+                    .collect::<Result<_, Error>>()?,
             })
         }
     }
@@ -197,10 +1035,37 @@ mod auth_during_comm {
             &self.display_name
         }
 
+        pub fn start_url_pattern(&self) -> &str {
+            &self.start_url_pattern
+        }
+
+        pub fn reverify_url_pattern(&self) -> Option<&str> {
+            self.reverify_url_pattern.as_deref()
+        }
+
         pub fn widget_signer(&self) -> &dyn JwsSigner {
             self.widget_signer.as_ref()
         }
 
+        /// Key id of the active widget signing key, if configured
+        pub fn widget_key_id(&self) -> Option<&str> {
+            self.widget_key_id.as_deref()
+        }
+
+        /// Encrypter for widget parameters, if a widget encryption key is configured
+        pub fn widget_encrypter(&self) -> Option<&dyn JweEncrypter> {
+            self.widget_encrypter.as_deref()
+        }
+
+        /// Widget signer with the given key id, whether active or not
+        pub fn widget_signer_by_id(&self, kid: &str) -> Option<&dyn JwsSigner> {
+            if self.widget_key_id() == Some(kid) {
+                Some(self.widget_signer())
+            } else {
+                self.widget_signers.get(kid).map(|signer| signer.as_ref())
+            }
+        }
+
         pub fn start_auth_signer(&self) -> &dyn JwsSigner {
             self.start_auth_signer.as_ref()
         }
@@ -216,24 +1081,294 @@ mod auth_during_comm {
         pub fn host_validator(&self) -> &dyn JwsVerifier {
             self.host_validator.as_ref()
         }
+
+        /// Configuration of a specific instance, if any
+        pub fn instance(&self, instance: &str) -> Option<&InstanceConfig> {
+            self.instances.get(instance)
+        }
+
+        /// Display name for the given instance, falling back to the plugin display name
+        pub fn display_name_for(&self, instance: &str) -> &str {
+            self.instance(instance)
+                .and_then(|i| i.display_name.as_deref())
+                .unwrap_or(&self.display_name)
+        }
+
+        /// Widget signer for the given instance, falling back to the plugin signer
+        pub fn widget_signer_for(&self, instance: &str) -> &dyn JwsSigner {
+            self.instance(instance)
+                .and_then(|i| i.widget_signer.as_deref())
+                .unwrap_or_else(|| self.widget_signer())
+        }
+
+        /// Key id of the widget signer for the given instance
+        pub fn widget_key_id_for(&self, instance: &str) -> Option<&str> {
+            match self.instance(instance) {
+                Some(i) if i.widget_signer.is_some() => i.widget_key_id.as_deref(),
+                _ => self.widget_key_id(),
+            }
+        }
+
+        /// Guest token validator for the given instance, falling back to the plugin validator
+        pub fn guest_validator_for(&self, instance: &str) -> &dyn JwsVerifier {
+            self.instance(instance)
+                .map(|i| i.guest_validator.as_ref())
+                .unwrap_or_else(|| self.guest_validator())
+        }
+
+        /// Host token validator for the given instance, falling back to the plugin validator
+        pub fn host_validator_for(&self, instance: &str) -> &dyn JwsVerifier {
+            self.instance(instance)
+                .map(|i| i.host_validator.as_ref())
+                .unwrap_or_else(|| self.host_validator())
+        }
     }
 
     #[cfg(test)]
     mod tests {
         use josekit::jws::alg::hmac::HmacJwsAlgorithm;
 
-        use super::TokenSecret;
+        use super::{TokenKeyConfig, TokenSecret};
+        use crate::{
+            config::Config,
+            error::Error,
+            test_helpers::{test_raw_config, EC_PUBKEY},
+        };
+        use proptest::prelude::*;
 
         #[test]
         fn test_log_hiding() {
-            let test_secret = TokenSecret("test1234123412341234123412341234".into());
+            let test_secret = TokenSecret::from("test1234123412341234123412341234".to_string());
             assert_eq!(format!("{:?}", test_secret), "TokenSecret");
 
             // Cannary test for something going wrong in the jose library
             let test_verifier = HmacJwsAlgorithm::Hs256
-                .verifier_from_bytes(test_secret.0)
+                .verifier_from_bytes(test_secret.0.as_bytes())
                 .unwrap();
             assert_eq!(format!("{:?}", test_verifier), "HmacJwsVerifier { algorithm: Hs256, private_key: PKey { algorithm: \"HMAC\" }, key_id: None }");
         }
+
+        #[test]
+        fn test_token_key_config() {
+            let secret: TokenKeyConfig =
+                serde_yaml::from_str("test1234123412341234123412341234").unwrap();
+            assert!(matches!(secret, TokenKeyConfig::Secret(_)));
+
+            let public_key: TokenKeyConfig = serde_yaml::from_str(EC_PUBKEY).unwrap();
+            assert!(matches!(public_key, TokenKeyConfig::PublicKey(_)));
+        }
+
+        #[test]
+        fn test_instance_config() {
+            let mut raw_config = test_raw_config();
+            let instances: serde_yaml::Value = serde_yaml::from_str(
+                r"
+                tenant:
+                    display_name: Tenant
+                    guest_signature_secret: 0f1e2d3c4b5a69788796a5b4c3d2e1f00f1e2d3c4b5a69788796a5b4c3d2e1f0
+                    host_signature_secret: 00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff
+                ",
+            )
+            .unwrap();
+            raw_config.insert("instances".into(), instances);
+            let config: Config =
+                serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+
+            let auth_during_comm_config = config.auth_during_comm_config();
+            assert!(auth_during_comm_config.instance("tenant").is_some());
+            assert!(auth_during_comm_config.instance("other").is_none());
+            assert_eq!(auth_during_comm_config.display_name_for("tenant"), "Tenant");
+            assert_eq!(
+                auth_during_comm_config.display_name_for("other"),
+                "comm-common"
+            );
+        }
+
+        #[test]
+        fn test_widget_signing_keys() {
+            let config = crate::test_helpers::test_config();
+            assert_eq!(config.auth_during_comm_config().widget_key_id(), None);
+
+            let mut raw_config = test_raw_config();
+            let privkey = raw_config.remove(&"widget_signing_privkey".into()).unwrap();
+            let mut keys = serde_yaml::Mapping::new();
+            keys.insert("2021-1".into(), privkey.clone());
+            keys.insert("2021-2".into(), privkey.clone());
+            raw_config.insert("widget_signing_keys".into(), keys.into());
+            raw_config.insert("widget_signing_key_id".into(), "2021-2".into());
+            let config: Config =
+                serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config.clone())).unwrap();
+
+            let auth_during_comm_config = config.auth_during_comm_config();
+            assert_eq!(auth_during_comm_config.widget_key_id(), Some("2021-2"));
+            assert_eq!(
+                auth_during_comm_config.widget_key_id_for("tenant"),
+                Some("2021-2")
+            );
+            assert!(auth_during_comm_config
+                .widget_signer_by_id("2021-1")
+                .is_some());
+            assert!(auth_during_comm_config
+                .widget_signer_by_id("2021-2")
+                .is_some());
+            assert!(auth_during_comm_config
+                .widget_signer_by_id("2020-1")
+                .is_none());
+
+            raw_config.insert("widget_signing_key_id".into(), "2020-1".into());
+            assert!(serde_yaml::from_value::<Config>(serde_yaml::Value::Mapping(
+                raw_config.clone()
+            ))
+            .is_err());
+            raw_config.insert("widget_signing_privkey".into(), privkey);
+            assert!(
+                serde_yaml::from_value::<Config>(serde_yaml::Value::Mapping(raw_config)).is_ok()
+            );
+        }
+
+        #[test]
+        fn test_start_url() {
+            let config = crate::test_helpers::test_config();
+            assert_eq!(
+                config
+                    .start_url("report_move", "session", "attr", "a/b")
+                    .unwrap(),
+                format!("{}/start/report_move/a%2Fb", config.external_url())
+            );
+
+            let mut raw_config = test_raw_config();
+            raw_config.insert(
+                "start_url_pattern".into(),
+                "guest/{session_id}?purpose={purpose}".into(),
+            );
+            assert!(serde_yaml::from_value::<Config>(serde_yaml::Value::Mapping(
+                raw_config.clone()
+            ))
+            .is_err());
+            raw_config.insert(
+                "start_url_pattern".into(),
+                "guest/{session_id}/{attr_id}/{purpose}".into(),
+            );
+            let config: Config =
+                serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+            assert_eq!(
+                config
+                    .start_url("report_move", "session", "attr", "token")
+                    .unwrap(),
+                format!("{}/guest/session/attr/report_move", config.external_url())
+            );
+        }
+
+        #[test]
+        fn test_reverify_url() {
+            let config = crate::test_helpers::test_config();
+            assert_eq!(config.reverify_url("report_move", "session").unwrap(), None);
+
+            let mut raw_config = test_raw_config();
+            raw_config.insert(
+                "reverify_url_pattern".into(),
+                "reverify/{session_id}/{attr_id}".into(),
+            );
+            assert!(serde_yaml::from_value::<Config>(serde_yaml::Value::Mapping(
+                raw_config.clone()
+            ))
+            .is_err());
+            raw_config.insert(
+                "reverify_url_pattern".into(),
+                "reverify/{purpose}/{session_id}".into(),
+            );
+            let config: Config =
+                serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+            assert_eq!(
+                config.reverify_url("report_move", "a/b").unwrap(),
+                Some(format!(
+                    "{}/reverify/report_move/a%2Fb",
+                    config.external_url()
+                ))
+            );
+        }
+
+        #[test]
+        fn test_purpose_validation() {
+            let config: Config =
+                serde_yaml::from_value(serde_yaml::Value::Mapping(test_raw_config())).unwrap();
+            assert!(config.validate_purpose("anything").is_ok());
+
+            let mut raw_config = test_raw_config();
+            let purposes: serde_yaml::Value = serde_yaml::from_str(
+                r"
+                report_move:
+                    display_name: Verhuizing doorgeven
+                    attributes: [email]
+                    auth_methods: [irma, digid]
+                request_passport:
+                    display_name: Paspoort aanvragen
+                ",
+            )
+            .unwrap();
+            raw_config.insert("purposes".into(), purposes);
+            let config: Config =
+                serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+
+            assert!(config.validate_purpose("report_move").is_ok());
+            assert!(matches!(
+                config.validate_purpose("report_mvoe"),
+                Err(Error::UnknownPurpose(_))
+            ));
+            assert_eq!(
+                config.purpose("report_move").unwrap().attributes,
+                vec!["email".to_string()]
+            );
+
+            assert!(config.validate_auth_method("report_move", "irma").is_ok());
+            assert!(config
+                .validate_auth_method("request_passport", "irma")
+                .is_ok());
+            match config.validate_auth_method("report_move", "email") {
+                Err(Error::AuthMethodNotPermitted { permitted, .. }) => {
+                    assert_eq!(permitted, vec!["irma".to_string(), "digid".to_string()])
+                }
+                _ => panic!("Expected auth method to be rejected"),
+            }
+        }
+
+        #[test]
+        fn test_process_settings() {
+            let mut raw_config = test_raw_config();
+            raw_config.insert("timezone".into(), "Asia/Tokyo".into());
+            raw_config.insert("strict_translations".into(), true.into());
+            let config: Config =
+                serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config.clone())).unwrap();
+            assert_eq!(
+                config.process_settings.timezone,
+                Some(chrono_tz::Asia::Tokyo)
+            );
+            assert!(config.process_settings.strict_translations);
+            // Only installing the configuration changes process-wide state
+            assert_eq!(
+                crate::translations::timezone(),
+                chrono_tz::Europe::Amsterdam
+            );
+            assert!(!crate::translations::strict_translations());
+
+            raw_config.insert("timezone".into(), "Mars/Olympus_Mons".into());
+            let source = serde_yaml::to_string(&raw_config).unwrap();
+            assert!(matches!(Config::from_yaml(&source), Err(Error::Config(_))));
+        }
+
+        proptest! {
+            #[test]
+            fn prop_malformed_config(cut in 0usize..4000, garbage in "\\PC{0,8}") {
+                let source = serde_yaml::to_string(&test_raw_config()).unwrap();
+                let cut = source
+                    .char_indices()
+                    .nth(cut)
+                    .map(|(i, _)| i)
+                    .unwrap_or(source.len());
+                let mangled = format!("{}{}{}", &source[..cut], garbage, &source[cut..]);
+                // Only the absence of panics matters here
+                let _ = Config::from_yaml(&mangled);
+            }
+        }
     }
 }
@@ -0,0 +1,170 @@
+use crate::{
+    config::Config,
+    keys::KeyFingerprints,
+    migrations::{schema_version, SCHEMA_VERSION},
+    session::SessionDBConn,
+    templates::{check_templates, operator_templates, TEMPLATES},
+    translations::{check_coverage, TranslationCoverage},
+};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Time the core gets to respond to the reachability check
+const CORE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Features this crate was compiled with
+fn enabled_features() -> Vec<&'static str> {
+    let features: [(&'static str, bool); 14] = [
+        ("auth_during_comm", cfg!(feature = "auth_during_comm")),
+        ("platform_token", cfg!(feature = "platform_token")),
+        ("session_db", cfg!(feature = "session_db")),
+        ("amqp", cfg!(feature = "amqp")),
+        ("archive", cfg!(feature = "archive")),
+        ("notify", cfg!(feature = "notify")),
+        ("oauth", cfg!(feature = "oauth")),
+        ("saml", cfg!(feature = "saml")),
+        ("websocket", cfg!(feature = "websocket")),
+        ("sentry_reporting", cfg!(feature = "sentry_reporting")),
+        ("vault_secrets", cfg!(feature = "vault_secrets")),
+        ("aws_secrets", cfg!(feature = "aws_secrets")),
+        ("json_logging", cfg!(feature = "json_logging")),
+        ("rocket", cfg!(feature = "rocket")),
+    ];
+    features
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Templates in use, and whether they render
+#[derive(Serialize, Debug, Clone)]
+pub struct TemplateDiagnostics {
+    pub loaded: Vec<String>,
+    /// Templates loaded from the template directory
+    pub custom: Vec<String>,
+    /// Errors rendering the templates with sample contexts, if any
+    pub error: Option<String>,
+}
+
+/// Reachability and schema version of the session database
+#[derive(Serialize, Debug, Clone)]
+pub struct DatabaseDiagnostics {
+    pub connected: bool,
+    pub schema_version: Option<i32>,
+    pub expected_schema_version: i32,
+    pub error: Option<String>,
+}
+
+/// Reachability of the ID Contact core
+#[derive(Serialize, Debug, Clone)]
+pub struct CoreDiagnostics {
+    pub url: String,
+    /// Whether the core responded at all, with any status
+    pub reachable: bool,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+/// Report on the state of a deployment, for supporting operators
+#[derive(Serialize, Debug, Clone)]
+pub struct Diagnostics {
+    pub version: &'static str,
+    pub features: Vec<&'static str>,
+    pub templates: TemplateDiagnostics,
+    pub translations: Vec<TranslationCoverage>,
+    pub database: DatabaseDiagnostics,
+    pub keys: KeyFingerprints,
+    pub core: CoreDiagnostics,
+}
+
+fn template_diagnostics(config: &Config) -> TemplateDiagnostics {
+    let mut loaded: Vec<String> = TEMPLATES.get_template_names().map(String::from).collect();
+    loaded.sort();
+    TemplateDiagnostics {
+        loaded,
+        custom: operator_templates(),
+        error: check_templates(config).err().map(|e| e.to_string()),
+    }
+}
+
+async fn database_diagnostics(db: Option<&SessionDBConn>) -> DatabaseDiagnostics {
+    let result = match db {
+        Some(db) => schema_version(db).await.map_err(|e| e.to_string()),
+        None => Err("No session database connection available".to_string()),
+    };
+    DatabaseDiagnostics {
+        connected: result.is_ok(),
+        schema_version: result.as_ref().ok().copied(),
+        expected_schema_version: SCHEMA_VERSION,
+        error: result.err(),
+    }
+}
+
+async fn core_diagnostics(config: &Config) -> CoreDiagnostics {
+    let url = config.auth_during_comm_config().core_url().to_string();
+    let result = reqwest::Client::new()
+        .get(&url)
+        .timeout(CORE_TIMEOUT)
+        .send()
+        .await;
+    CoreDiagnostics {
+        url,
+        reachable: result.is_ok(),
+        status: result.as_ref().ok().map(|r| r.status().as_u16()),
+        error: result.err().map(|e| e.to_string()),
+    }
+}
+
+/// Check templates, translations, the session database, keys and the core.
+/// Problems are reported rather than returned as errors, so a single
+/// response shows everything that is wrong.
+pub async fn diagnostics(config: &Config, db: Option<&SessionDBConn>) -> Diagnostics {
+    Diagnostics {
+        version: env!("CARGO_PKG_VERSION"),
+        features: enabled_features(),
+        templates: template_diagnostics(config),
+        translations: check_coverage(),
+        database: database_diagnostics(db).await,
+        keys: config.keys().current().fingerprints().clone(),
+        core: core_diagnostics(config).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::test_raw_config;
+
+    #[test]
+    fn test_diagnostics() {
+        // A port nothing listens on, so the core is unreachable without delay
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let core_url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let mut raw_config = test_raw_config();
+        raw_config.insert("core_url".into(), core_url.clone().into());
+        let config: Config =
+            serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let diagnostics = runtime.block_on(diagnostics(&config, None));
+        assert!(diagnostics.features.contains(&"session_db"));
+        assert!(diagnostics
+            .templates
+            .loaded
+            .contains(&"credentials.html".to_string()));
+        assert_eq!(diagnostics.templates.error, None);
+        assert!(diagnostics.translations.iter().any(|t| t.locale == "en"));
+        assert!(!diagnostics.database.connected);
+        assert_eq!(diagnostics.database.expected_schema_version, SCHEMA_VERSION);
+        assert_eq!(diagnostics.core.url, core_url);
+        assert!(!diagnostics.core.reachable);
+
+        let json = serde_json::to_string(&diagnostics).unwrap();
+        assert!(json.contains(&diagnostics.keys.decryption_privkey));
+    }
+}
@@ -1,57 +1,336 @@
-use crate::jwt::JwtError;
-use rocket::{
-    http::{ContentType, Status},
-    Response,
-};
+use crate::{jwt::JwtError, translations::TRANSLATIONS};
+#[cfg(feature = "session_db")]
 use rocket_sync_db_pools::postgres;
+use serde::Serialize;
 use serde_json::json;
+use strum_macros::{AsRefStr, Display};
 use tera;
 use thiserror::Error;
 
+/// Machine-readable category of an [`Error`], included as `code` in error
+/// responses, so clients can react to errors without parsing messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Display, AsRefStr)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[strum(serialize_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorKind {
+    NotFound,
+    SessionNotFound,
+    InvalidRequest,
+    UnknownPurpose,
+    AuthMethodNotPermitted,
+    Unauthorized,
+    Forbidden,
+    RateLimited,
+    SessionLimitReached,
+    Timeout,
+    InvalidToken,
+    TokenExpired,
+    DbUnavailable,
+    DbConflict,
+    DbError,
+    SchemaMismatch,
+    UpstreamUnavailable,
+    UpstreamError,
+    TemplateError,
+    InvalidConfig,
+    Internal,
+}
+
+impl ErrorKind {
+    /// Whether the failed operation may succeed when tried again later
+    pub fn is_retryable(self) -> bool {
+        use ErrorKind::*;
+        matches!(
+            self,
+            RateLimited | Timeout | DbUnavailable | DbConflict | UpstreamUnavailable
+        )
+    }
+}
+
+/// Description of a JSON error without the message itself, which may quote
+/// values from the input such as attribute values
+pub(crate) fn json_error_summary(error: &serde_json::Error) -> String {
+    format!(
+        "{:?} error at line {} column {}",
+        error.classify(),
+        error.line(),
+        error.column()
+    )
+}
+
 #[derive(Debug, Error)]
 /// General Error type, used to capture all kinds of common errors. Can be used to respond to requests
 pub enum Error {
     #[error("Not found")]
     NotFound,
+    #[error("Session not found")]
+    SessionNotFound,
     #[error("Bad Request: {0}")]
     BadRequest(&'static str),
+    #[error("Invalid configuration: {0}")]
+    Config(&'static str),
+    #[error("Unknown purpose: {0}")]
+    UnknownPurpose(String),
+    #[error("Authentication method {auth_method} not permitted, use one of {permitted:?}")]
+    AuthMethodNotPermitted {
+        auth_method: String,
+        permitted: Vec<String>,
+    },
+    #[error("Unauthorized: {0}")]
+    Unauthorized(&'static str),
+    #[error("Forbidden: {0}")]
+    Forbidden(&'static str),
+    #[error("Too many requests, retry after {0} seconds")]
+    TooManyRequests(u64),
+    #[error("Timeout: {0}")]
+    Timeout(&'static str),
+    #[error("Session limit of {limit} per {scope} reached")]
+    SessionLimitReached { scope: &'static str, limit: u32 },
     #[error("JWE Error: {0}")]
     Jwe(#[from] JwtError),
+    #[cfg(feature = "session_db")]
     #[error("Postgres Error: {0}")]
     Postgres(#[from] postgres::Error),
+    #[cfg(feature = "session_db")]
+    #[error("Session database schema is at version {found}, expected {expected}; run the migrations or upgrade the plugin")]
+    SchemaVersionMismatch { expected: i32, found: i32 },
     #[error("Reqwest Error: {0}")]
     Reqwest(#[from] reqwest::Error),
-    #[error("JSON Error: {0}")]
+    #[error("JSON Error: {}", json_error_summary(.0))]
     Json(#[from] serde_json::Error),
     #[error("Parse Error: {0}")]
     Parse(#[from] strum::ParseError),
     #[error("Template Error: {0}")]
     Template(#[from] tera::Error),
+    #[error("IO Error: {0}")]
+    Io(#[from] std::io::Error),
+    #[cfg(feature = "amqp")]
+    #[error("AMQP Error: {0}")]
+    Amqp(#[from] lapin::Error),
+    #[cfg(feature = "notify")]
+    #[error("SMTP Error: {0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[cfg(feature = "notify")]
+    #[error("E-mail Error: {0}")]
+    Email(#[from] lettre::error::Error),
+    #[cfg(feature = "notify")]
+    #[error("E-mail Address Error: {0}")]
+    Address(#[from] lettre::address::AddressError),
 }
 
-impl<'r, 'o: 'r> rocket::response::Responder<'r, 'o> for Error {
-    fn respond_to(self, request: &'r rocket::Request<'_>) -> rocket::response::Result<'o> {
+impl Error {
+    /// HTTP status code corresponding to this error
+    pub fn status_code(&self) -> u16 {
         use Error::*;
-        let (body, status) = match &self {
-            NotFound => (json!({"error": "NotFound"}), Status::NotFound),
-            BadRequest(m) => (
-                json!({"error": "BadRequest", "detail": m}),
-                Status::BadRequest,
-            ),
-            Jwe(e) => (
-                json!({"error": "BadRequest", "detail": format!("{}", e)}),
-                Status::BadRequest,
-            ),
-            Template(e) => (
-                json!({"error": "TemplateError", "detail": format!("{}", e)}),
-                Status::InternalServerError,
+        match self {
+            NotFound | SessionNotFound => 404,
+            BadRequest(_) | UnknownPurpose(_) | AuthMethodNotPermitted { .. } | Jwe(_) => 400,
+            Unauthorized(_) => 401,
+            Forbidden(_) => 403,
+            TooManyRequests(_) | SessionLimitReached { .. } => 429,
+            Timeout(_) => 504,
+            _ => 500,
+        }
+    }
+
+    /// Machine-readable category of this error
+    pub fn kind(&self) -> ErrorKind {
+        use Error::*;
+        match self {
+            NotFound => ErrorKind::NotFound,
+            SessionNotFound => ErrorKind::SessionNotFound,
+            BadRequest(_) => ErrorKind::InvalidRequest,
+            UnknownPurpose(_) => ErrorKind::UnknownPurpose,
+            AuthMethodNotPermitted { .. } => ErrorKind::AuthMethodNotPermitted,
+            Unauthorized(_) => ErrorKind::Unauthorized,
+            Forbidden(_) => ErrorKind::Forbidden,
+            TooManyRequests(_) => ErrorKind::RateLimited,
+            SessionLimitReached { .. } => ErrorKind::SessionLimitReached,
+            Timeout(_) => ErrorKind::Timeout,
+            Jwe(JwtError::Expired) => ErrorKind::TokenExpired,
+            Jwe(_) => ErrorKind::InvalidToken,
+            #[cfg(feature = "session_db")]
+            Postgres(e) => postgres_error_kind(e),
+            #[cfg(feature = "session_db")]
+            SchemaVersionMismatch { .. } => ErrorKind::SchemaMismatch,
+            Reqwest(e)
+                if e.is_connect()
+                    || e.is_timeout()
+                    || matches!(e.status(), Some(status) if status.is_server_error()) =>
+            {
+                ErrorKind::UpstreamUnavailable
+            }
+            Reqwest(_) => ErrorKind::UpstreamError,
+            Template(_) => ErrorKind::TemplateError,
+            Config(_) => ErrorKind::InvalidConfig,
+            #[cfg(feature = "amqp")]
+            Amqp(_) => ErrorKind::UpstreamUnavailable,
+            #[cfg(feature = "notify")]
+            Smtp(_) => ErrorKind::UpstreamUnavailable,
+            _ => ErrorKind::Internal,
+        }
+    }
+
+    /// Whether the failed operation may succeed when tried again later
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
+
+    /// Log this error along with its code, as an error if it is a server error
+    #[cfg(any(feature = "rocket", feature = "axum"))]
+    fn log(&self, request_id: Option<&str>) {
+        let level = match self.status_code() {
+            500..=599 => log::Level::Error,
+            _ => log::Level::Debug,
+        };
+        match request_id {
+            Some(request_id) => log::log!(
+                level,
+                "Request {} failed with {}: {}",
+                request_id,
+                self.kind(),
+                self
             ),
-            _ => return rocket::response::Debug::from(self).respond_to(request),
+            None => log::log!(level, "Request failed with {}: {}", self.kind(), self),
+        }
+    }
+
+    /// Framework-agnostic JSON description of this error, to be used as response body
+    pub fn to_problem(&self) -> serde_json::Value {
+        let mut problem = self.problem_fields();
+        if let Some(problem) = problem.as_object_mut() {
+            problem.insert("code".to_string(), json!(self.kind()));
+        }
+        problem
+    }
+
+    fn problem_fields(&self) -> serde_json::Value {
+        use Error::*;
+        match self {
+            NotFound | SessionNotFound => json!({"error": "NotFound"}),
+            BadRequest(m) => json!({"error": "BadRequest", "detail": m}),
+            UnknownPurpose(purpose) => json!({
+                "error": "UnknownPurpose",
+                "detail": TRANSLATIONS.get("unknown_purpose"),
+                "purpose": purpose,
+            }),
+            AuthMethodNotPermitted {
+                auth_method,
+                permitted,
+            } => json!({
+                "error": "AuthMethodNotPermitted",
+                "detail": TRANSLATIONS.get("auth_method_not_permitted"),
+                "auth_method": auth_method,
+                "permitted": permitted,
+            }),
+            Unauthorized(m) => json!({"error": "Unauthorized", "detail": m}),
+            Forbidden(m) => json!({"error": "Forbidden", "detail": m}),
+            TooManyRequests(retry_after) => {
+                json!({
+                    "error": "TooManyRequests",
+                    "detail": TRANSLATIONS.get("too_many_requests"),
+                    "retry_after": retry_after,
+                })
+            }
+            Timeout(_) => json!({"error": "Timeout", "detail": TRANSLATIONS.get("timeout")}),
+            SessionLimitReached { scope, limit } => json!({
+                "error": "SessionLimitReached",
+                "detail": TRANSLATIONS.get("session_limit_reached"),
+                "scope": scope,
+                "limit": limit,
+            }),
+            // Details of lower level errors are only logged, as they may
+            // reveal internals such as queries or key material
+            Jwe(_) => json!({"error": "BadRequest", "detail": TRANSLATIONS.get("invalid_token")}),
+            Template(_) => json!({"error": "TemplateError", "detail": TRANSLATIONS.get("error")}),
+            _ if self.is_retryable() => json!({
+                "error": "InternalServerError",
+                "detail": TRANSLATIONS.get("service_unavailable"),
+            }),
+            _ => json!({"error": "InternalServerError", "detail": TRANSLATIONS.get("error")}),
+        }
+    }
+
+    /// Additional headers to be sent along with the response for this error
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Error::Unauthorized(_) => vec![("WWW-Authenticate", "Bearer".to_string())],
+            Error::TooManyRequests(retry_after) => {
+                vec![("Retry-After", retry_after.to_string())]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(feature = "session_db")]
+fn postgres_error_kind(e: &postgres::Error) -> ErrorKind {
+    use postgres::error::SqlState;
+    match e.code() {
+        Some(&SqlState::T_R_SERIALIZATION_FAILURE) | Some(&SqlState::T_R_DEADLOCK_DETECTED) => {
+            ErrorKind::DbConflict
+        }
+        // Connection exceptions and operator intervention, such as a shutdown
+        Some(code) if code.code().starts_with("08") || code.code().starts_with("57P") => {
+            ErrorKind::DbUnavailable
+        }
+        Some(_) => ErrorKind::DbError,
+        None => ErrorKind::DbUnavailable,
+    }
+}
+
+#[cfg(feature = "rocket")]
+impl<'r, 'o: 'r> rocket::response::Responder<'r, 'o> for Error {
+    fn respond_to(self, request: &'r rocket::Request<'_>) -> rocket::response::Result<'o> {
+        use crate::{
+            reporting::{report_error, ErrorContext},
+            request_id::RequestId,
+        };
+        use rocket::{
+            http::{ContentType, Header, Status},
+            Response,
         };
-        Ok(Response::build_from(body.respond_to(request).unwrap())
-            .status(status)
-            .header(ContentType::JSON)
-            .finalize())
+
+        self.log(Some(&RequestId::of(request).to_string()));
+        report_error(&self, &ErrorContext::of(request));
+
+        let status = Status::from_code(self.status_code()).unwrap_or(Status::InternalServerError);
+        let mut body = self.to_problem();
+        if let Some(body) = body.as_object_mut() {
+            body.insert(
+                "request_id".to_string(),
+                RequestId::of(request).to_string().into(),
+            );
+        }
+        let mut response = Response::build_from(body.respond_to(request)?);
+        response.status(status).header(ContentType::JSON);
+        for (name, value) in self.headers() {
+            response.header(Header::new(name, value));
+        }
+        Ok(response.finalize())
+    }
+}
+
+#[cfg(feature = "axum")]
+impl axum::response::IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        use crate::reporting::{report_error, ErrorContext};
+        use axum::http::{HeaderName, HeaderValue, StatusCode};
+
+        self.log(None);
+        report_error(&self, &ErrorContext::default());
+        let status =
+            StatusCode::from_u16(self.status_code()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut response = (status, axum::Json(self.to_problem())).into_response();
+        for (name, value) in self.headers() {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(&value),
+            ) {
+                response.headers_mut().insert(name, value);
+            }
+        }
+        response
     }
 }
 
@@ -60,3 +339,51 @@ impl From<id_contact_jwt::Error> for Error {
         Error::Jwe(JwtError::Jwe(e))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_kind() {
+        let error = Error::TooManyRequests(60);
+        assert_eq!(error.kind(), ErrorKind::RateLimited);
+        assert!(error.is_retryable());
+        assert_eq!(error.to_problem()["code"], "RATE_LIMITED");
+        assert_eq!(error.kind().to_string(), "RATE_LIMITED");
+
+        assert_eq!(Error::NotFound.to_problem()["code"], "NOT_FOUND");
+        assert_eq!(
+            Error::Json(serde_json::from_str::<u32>("").unwrap_err()).to_problem(),
+            json!({
+                "error": "InternalServerError",
+                "code": "INTERNAL",
+                "detail": TRANSLATIONS.get("error"),
+            })
+        );
+    }
+
+    #[cfg(feature = "session_db")]
+    #[test]
+    fn test_token_expired() {
+        use crate::{
+            proxy::ClientAddr,
+            routes::verify_host_token,
+            test_helpers::{sign_expired_host_token, test_config, test_host_token},
+        };
+
+        let expired = sign_expired_host_token(&test_host_token("room"));
+        let error = verify_host_token(&expired, &test_config(), ClientAddr::default()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TokenExpired);
+        assert!(!error.is_retryable());
+        assert_eq!(error.to_problem()["code"], "TOKEN_EXPIRED");
+        assert_eq!(
+            error.to_problem()["detail"],
+            json!(TRANSLATIONS.get("invalid_token"))
+        );
+
+        let error =
+            verify_host_token("not-a-jwt", &test_config(), ClientAddr::default()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidToken);
+    }
+}
@@ -1,17 +1,23 @@
-use crate::types::AuthSelectParams;
+use crate::types::{AuthSelectParams, Credentials};
 #[cfg(feature = "auth_during_comm")]
 use id_contact_proto::StartRequestAuthOnly;
 use josekit::{
-    jws::{JwsHeader, JwsSigner},
+    jwe::{JweEncrypter, JweHeader},
+    jws::{JwsHeader, JwsSigner, JwsVerifier},
     jwt::JwtPayload,
 };
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum JwtError {
     #[error("Invalid Structure for key {0}")]
     InvalidStructure(&'static str),
-    #[error("JSON error: {0}")]
+    #[error("Token expired")]
+    Expired,
+    #[error("JSON error: {}", crate::error::json_error_summary(.0))]
     Json(#[from] serde_json::Error),
     #[error("24 Sessions JWT error: {0}")]
     Jwt(#[from] josekit::JoseError),
@@ -45,23 +51,234 @@ pub fn sign_auth_select_params(
     params: AuthSelectParams,
     signer: &dyn JwsSigner,
 ) -> Result<String, JwtError> {
-    let mut sig_header = JwsHeader::new();
-    sig_header.set_token_type("JWT");
-    let mut sig_payload = JwtPayload::new();
-    sig_payload.set_subject("id-contact-widget-params");
+    sign_auth_select_params_with_key_id(params, None, signer)
+}
+
+/// Serialize and sign a set of AuthSelectParams, setting the `kid` header so
+/// the widget can select the matching verification key during key rotation
+pub fn sign_auth_select_params_with_key_id(
+    params: AuthSelectParams,
+    kid: Option<&str>,
+    signer: &dyn JwsSigner,
+) -> Result<String, JwtError> {
+    WidgetClaims::builder(params).build().sign(kid, signer)
+}
 
-    sig_payload.set_claim("purpose", Some(serde_json::to_value(&params.purpose)?))?;
-    sig_payload.set_claim("start_url", Some(serde_json::to_value(&params.start_url)?))?;
-    sig_payload.set_claim(
-        "display_name",
-        Some(serde_json::to_value(&params.display_name)?),
-    )?;
+const WIDGET_PARAMS_SUBJECT: &str = "id-contact-widget-params";
 
-    sig_payload.set_issued_at(&std::time::SystemTime::now());
-    sig_payload
-        .set_expires_at(&(std::time::SystemTime::now() + std::time::Duration::from_secs(5 * 60)));
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Claims of the JWT passing AuthSelectParams to the widget. Claims unknown
+/// to this version of the protocol are kept in `extra`, so they survive a
+/// round trip.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WidgetClaims {
+    pub sub: String,
+    pub purpose: String,
+    pub start_url: String,
+    pub display_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    pub iat: u64,
+    pub exp: u64,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl WidgetClaims {
+    pub fn builder(params: AuthSelectParams) -> WidgetClaimsBuilder {
+        WidgetClaimsBuilder {
+            params,
+            ttl: Duration::from_secs(5 * 60),
+            audience: None,
+            extra: Map::new(),
+        }
+    }
+
+    /// The AuthSelectParams contained in these claims
+    pub fn params(&self) -> AuthSelectParams {
+        AuthSelectParams {
+            purpose: self.purpose.clone(),
+            start_url: self.start_url.clone(),
+            display_name: self.display_name.clone(),
+        }
+    }
+
+    /// Sign the claims, setting the `kid` header if given
+    pub fn sign(&self, kid: Option<&str>, signer: &dyn JwsSigner) -> Result<String, JwtError> {
+        let mut sig_header = JwsHeader::new();
+        sig_header.set_token_type("JWT");
+        if let Some(kid) = kid {
+            sig_header.set_key_id(kid);
+        }
+
+        let claims = match serde_json::to_value(self)? {
+            Value::Object(claims) => claims,
+            _ => return Err(JwtError::InvalidStructure("widget claims")),
+        };
+        let sig_payload = JwtPayload::from_map(claims)?;
+
+        Ok(josekit::jwt::encode_with_signer(
+            &sig_payload,
+            &sig_header,
+            signer,
+        )?)
+    }
+
+    /// Verify and decode signed widget claims, rejecting expired ones
+    pub fn decode(jwt: &str, verifier: &dyn JwsVerifier) -> Result<Self, JwtError> {
+        let (payload, _) = josekit::jwt::decode_with_verifier(jwt, verifier)?;
+        let claims: WidgetClaims =
+            serde_json::from_value(Value::Object(payload.claims_set().clone()))?;
+
+        if claims.sub != WIDGET_PARAMS_SUBJECT {
+            return Err(JwtError::InvalidStructure("sub"));
+        }
+        if claims.exp < unix_time(SystemTime::now()) {
+            return Err(JwtError::Expired);
+        }
+        Ok(claims)
+    }
+}
+
+/// Builder for [`WidgetClaims`]
+pub struct WidgetClaimsBuilder {
+    params: AuthSelectParams,
+    ttl: Duration,
+    audience: Option<String>,
+    extra: Map<String, Value>,
+}
+
+impl WidgetClaimsBuilder {
+    /// Time the claims are valid for, 5 minutes by default
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn audience(mut self, audience: impl Into<String>) -> Self {
+        self.audience = Some(audience.into());
+        self
+    }
+
+    /// Add a claim not (yet) known to this version of the widget protocol
+    pub fn claim(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.extra.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn build(self) -> WidgetClaims {
+        let now = SystemTime::now();
+        WidgetClaims {
+            sub: WIDGET_PARAMS_SUBJECT.to_string(),
+            purpose: self.params.purpose,
+            start_url: self.params.start_url,
+            display_name: self.params.display_name,
+            aud: self.audience,
+            iat: unix_time(now),
+            exp: unix_time(now + self.ttl),
+            extra: self.extra,
+        }
+    }
+}
+
+/// Sign a set of AuthSelectParams and additionally encrypt the resulting JWT
+/// for the widget, so purpose, start url and display name are not readable
+/// from the URL the params are passed in
+pub fn sign_and_encrypt_auth_select_params(
+    params: AuthSelectParams,
+    kid: Option<&str>,
+    signer: &dyn JwsSigner,
+    encrypter: &dyn JweEncrypter,
+) -> Result<String, JwtError> {
+    let jws = sign_auth_select_params_with_key_id(params, kid, signer)?;
+
+    let mut header = JweHeader::new();
+    header.set_token_type("JWT");
+    header.set_content_type("JWT");
+    header.set_content_encryption("A256GCM");
+
+    Ok(josekit::jwe::serialize_compact(
+        jws.as_bytes(),
+        &header,
+        encrypter,
+    )?)
+}
+
+/// Serialize a set of credentials and encrypt them as a JWE for the given key,
+/// so they can be handed to a host without exposing them in plain JSON
+pub fn encrypt_credentials(
+    credentials: &[Credentials],
+    encrypter: &dyn JweEncrypter,
+) -> Result<String, JwtError> {
+    let mut header = JweHeader::new();
+    header.set_token_type("JWT");
+    header.set_content_type("application/json");
+    header.set_content_encryption("A256GCM");
+
+    let payload = serde_json::to_vec(credentials)?;
+    Ok(josekit::jwe::serialize_compact(
+        &payload, &header, encrypter,
+    )?)
+}
+
+#[cfg(all(test, feature = "auth_during_comm"))]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{test_config, EC_PUBKEY};
+    use id_contact_jwt::EncryptionKeyConfig;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_widget_claims() {
+        let params = AuthSelectParams {
+            purpose: "report_move".to_string(),
+            start_url: "https://example.com/start".to_string(),
+            display_name: "Example".to_string(),
+        };
+        let claims = WidgetClaims::builder(params)
+            .ttl(Duration::from_secs(60))
+            .audience("widget")
+            .claim("locale", "en")
+            .build();
+        assert_eq!(claims.exp - claims.iat, 60);
+        assert_eq!(claims.params().purpose, "report_move");
+
+        let config = test_config();
+        let jwt = claims
+            .sign(None, config.auth_during_comm_config().widget_signer())
+            .unwrap();
+        let decoded = WidgetClaims::decode(&jwt, config.keys().current().validator()).unwrap();
+        assert_eq!(decoded, claims);
+        assert_eq!(decoded.extra.get("locale"), Some(&Value::from("en")));
+    }
+
+    #[test]
+    fn test_encrypted_auth_select_params() {
+        let config = test_config();
+        let key: EncryptionKeyConfig = serde_yaml::from_str(EC_PUBKEY).unwrap();
+        let encrypter = Box::<dyn JweEncrypter>::try_from(key).unwrap();
 
-    let jws = josekit::jwt::encode_with_signer(&sig_payload, &sig_header, signer)?;
+        let params = AuthSelectParams {
+            purpose: "report_move".to_string(),
+            start_url: "https://example.com/start".to_string(),
+            display_name: "Example".to_string(),
+        };
+        let jwe = sign_and_encrypt_auth_select_params(
+            params,
+            Some("2021-1"),
+            config.auth_during_comm_config().widget_signer(),
+            encrypter.as_ref(),
+        )
+        .unwrap();
+        assert!(!jwe.contains("report_move"));
 
-    Ok(jws)
+        let (jws, _) =
+            josekit::jwe::deserialize_compact(&jwe, config.keys().current().decrypter()).unwrap();
+        assert_eq!(String::from_utf8(jws).unwrap().split('.').count(), 3);
+    }
 }
@@ -0,0 +1,207 @@
+use crate::error::Error;
+use arc_swap::ArcSwap;
+use id_contact_jwt::{EncryptionKeyConfig, SignKeyConfig};
+use josekit::{jwe::JweDecrypter, jws::JwsVerifier};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    convert::TryFrom,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use zeroize::Zeroizing;
+
+/// Key material for ID Contact JWEs, as found in the configuration or a key file
+#[derive(Deserialize, Debug)]
+pub struct RawKeys {
+    /// Private key used to decrypt ID Contact JWEs
+    pub decryption_privkey: EncryptionKeyConfig,
+    /// Public key used to verify ID Contact JWSs
+    pub signature_pubkey: SignKeyConfig,
+}
+
+impl RawKeys {
+    /// Read key material from a YAML or JSON file. The file contents, which
+    /// include the private key, are wiped from memory once parsed.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let contents = Zeroizing::new(std::fs::read_to_string(path).map_err(|e| {
+            log::error!("Could not read key file {}: {}", path.display(), e);
+            Error::Config("Could not read key file")
+        })?);
+        serde_yaml::from_str(&contents).map_err(|e| {
+            log::error!("Invalid key file {}: {}", path.display(), e);
+            Error::Config("Invalid key file")
+        })
+    }
+}
+
+/// Fingerprints of the keys in use, for telling whether deployments use
+/// the same keys. These are not the standard fingerprints of the keys.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeyFingerprints {
+    pub decryption_privkey: String,
+    pub signature_pubkey: String,
+}
+
+/// Truncated SHA-256 of a key configuration. id-contact-jwt does not expose
+/// the key material, so this is taken over the Debug representation, which
+/// includes it and is wiped once hashed.
+fn fingerprint(key: &impl Debug) -> String {
+    let representation = Zeroizing::new(format!("{:?}", key));
+    Sha256::digest(representation.as_bytes())[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/// Keys for decrypting and verifying ID Contact JWEs
+#[derive(Debug)]
+pub struct Keys {
+    decrypter: Box<dyn JweDecrypter>,
+    validator: Box<dyn JwsVerifier>,
+    fingerprints: KeyFingerprints,
+}
+
+impl TryFrom<RawKeys> for Keys {
+    type Error = Error;
+    fn try_from(raw_keys: RawKeys) -> Result<Keys, Error> {
+        let fingerprints = KeyFingerprints {
+            decryption_privkey: fingerprint(&raw_keys.decryption_privkey),
+            signature_pubkey: fingerprint(&raw_keys.signature_pubkey),
+        };
+        Ok(Keys {
+            decrypter: Box::<dyn JweDecrypter>::try_from(raw_keys.decryption_privkey)?,
+            validator: Box::<dyn JwsVerifier>::try_from(raw_keys.signature_pubkey)?,
+            fingerprints,
+        })
+    }
+}
+
+impl Keys {
+    pub fn decrypter(&self) -> &dyn JweDecrypter {
+        self.decrypter.as_ref()
+    }
+
+    pub fn validator(&self) -> &dyn JwsVerifier {
+        self.validator.as_ref()
+    }
+
+    pub fn fingerprints(&self) -> &KeyFingerprints {
+        &self.fingerprints
+    }
+}
+
+/// Current keys of the plugin, which can be replaced at runtime when the
+/// keys are rotated. Clones share the same keys.
+#[derive(Debug, Clone)]
+pub struct KeyStore {
+    keys: Arc<ArcSwap<Keys>>,
+    key_file: Option<PathBuf>,
+}
+
+impl KeyStore {
+    pub fn new(keys: Keys, key_file: Option<PathBuf>) -> Self {
+        KeyStore {
+            keys: Arc::new(ArcSwap::from_pointee(keys)),
+            key_file,
+        }
+    }
+
+    /// Keys in use right now. Hold on to the result for the duration of an
+    /// operation, so it uses the same keys throughout.
+    pub fn current(&self) -> Arc<Keys> {
+        self.keys.load_full()
+    }
+
+    /// Start using new keys. The current keys stay in use if the new ones are invalid.
+    pub fn replace(&self, raw_keys: RawKeys) -> Result<(), Error> {
+        self.keys.store(Arc::new(Keys::try_from(raw_keys)?));
+        Ok(())
+    }
+
+    /// Reload the keys from the configured key file
+    pub fn reload(&self) -> Result<(), Error> {
+        let key_file = self
+            .key_file
+            .as_deref()
+            .ok_or(Error::Config("No key file configured"))?;
+        self.replace(RawKeys::from_file(key_file)?)?;
+        log::info!("Reloaded keys from {}", key_file.display());
+        Ok(())
+    }
+
+    /// Reload the keys whenever the key file changes, checking every
+    /// `interval`. Must be called from within a Tokio runtime.
+    pub fn watch(&self, interval: Duration) -> Result<(), Error> {
+        let key_file = self
+            .key_file
+            .clone()
+            .ok_or(Error::Config("No key file configured"))?;
+        let store = self.clone();
+        let modified = move || {
+            std::fs::metadata(&key_file)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        };
+
+        tokio::spawn(async move {
+            let mut last_modified: Option<SystemTime> = modified();
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                let current = modified();
+                if current != last_modified {
+                    last_modified = current;
+                    if let Err(e) = store.reload() {
+                        log::error!("Keeping previous keys: {}", e);
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "auth_during_comm"))]
+mod tests {
+    use super::*;
+    use crate::{config::Config, test_helpers::test_raw_config};
+
+    #[test]
+    fn test_key_reload() {
+        let mut raw_config = test_raw_config();
+        let mut raw_keys = serde_yaml::Mapping::new();
+        for key in ["decryption_privkey", "signature_pubkey"].iter() {
+            let value = raw_config.remove(&(*key).into()).unwrap();
+            raw_keys.insert((*key).into(), value);
+        }
+        let key_file = std::env::temp_dir().join(format!(
+            "comm-common-keys-{}.yml",
+            crate::util::random_string(8)
+        ));
+        std::fs::write(&key_file, serde_yaml::to_string(&raw_keys).unwrap()).unwrap();
+        raw_config.insert("key_file".into(), key_file.to_str().unwrap().into());
+
+        let config: Config =
+            serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+        let before = config.keys().current();
+        config.keys().reload().unwrap();
+        assert!(!Arc::ptr_eq(&before, &config.keys().current()));
+        let fingerprints = before.fingerprints();
+        assert_eq!(fingerprints, config.keys().current().fingerprints());
+        assert_ne!(
+            fingerprints.decryption_privkey,
+            fingerprints.signature_pubkey
+        );
+
+        std::fs::write(&key_file, "decryption_privkey: [").unwrap();
+        let before = config.keys().current();
+        assert!(config.keys().reload().is_err());
+        assert!(Arc::ptr_eq(&before, &config.keys().current()));
+
+        std::fs::remove_file(&key_file).unwrap();
+    }
+}
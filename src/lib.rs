@@ -1,37 +1,153 @@
+/// Bearer token authentication of platform backends
+pub mod api_token;
+#[cfg(feature = "archive")]
+/// Archival of session summaries to cold storage
+pub mod archive;
+/// Generation of the attr_ids matching delivered attributes to sessions
+pub mod attr_id;
+#[cfg(feature = "oauth")]
+/// Host login with OAuth2 / OpenID Connect, SAML and IRMA providers
+pub mod auth;
+#[cfg(any(feature = "aws_secrets", feature = "archive"))]
+/// Signing of AWS API requests
+mod aws;
+#[cfg(feature = "platform_token")]
+/// Cache of decrypted auth results
+pub mod cache;
+/// Signed, expiring callback URLs
+pub mod callback;
 /// Common configuration mechanisms
 pub mod config;
+#[cfg(feature = "session_db")]
+/// Self-test report for supporting deployments
+pub mod diagnostics;
 /// Error type with responder implementation
 pub mod error;
+#[cfg(feature = "session_db")]
+/// Session lifecycle events and their publishers
+pub mod events;
 /// JWT signing functionality
 pub mod jwt;
+/// Key material that can be rotated while running
+pub mod keys;
+#[cfg(feature = "session_db")]
+/// Temporary lockout of clients presenting invalid host tokens
+pub mod lockout;
+#[cfg(feature = "json_logging")]
+/// Structured logging setup and request logging
+pub mod logging;
+#[cfg(feature = "session_db")]
+/// Timing metrics of session database queries
+pub mod metrics;
+#[cfg(feature = "session_db")]
+/// Schema migrations for the session database
+pub mod migrations;
+#[cfg(feature = "notify")]
+/// E-mail notifications for completed authentications
+pub mod notify;
+#[cfg(any(feature = "rocket", feature = "axum"))]
+/// Client addresses and schemes behind trusted reverse proxies
+pub mod proxy;
+/// Reporting of unexpected errors to error tracking services
+pub mod reporting;
+#[cfg(feature = "rocket")]
+/// Request id assignment and propagation
+pub mod request_id;
+/// Retrying of outbound HTTP calls
+pub mod retry;
+#[cfg(feature = "session_db")]
+/// Routes for communication plugins to mount
+pub mod routes;
+/// Fetching of secrets referred to by the configuration
+pub mod secrets;
 #[cfg(feature = "session_db")]
 /// Database manipulation code for keeping track of sessions based on platform tokens
 pub mod session;
+#[cfg(feature = "session_db")]
+/// Read-only share links for the credentials of a room
+pub mod share;
+#[cfg(feature = "session_db")]
+/// Starting of authentication sessions for guests
+pub mod start;
+#[cfg(feature = "session_db")]
+/// Aggregate usage statistics, without personal data
+pub mod stats;
+/// Templates for user-facing pages and messages
+pub mod templates;
+#[cfg(all(feature = "auth_during_comm", any(test, feature = "test_helpers")))]
+/// Keys, configuration and tokens for use in tests of communication plugins
+pub mod test_helpers;
+/// Post-processing of received attributes
+pub mod transform;
+/// Translations of user-facing text
+pub mod translations;
 /// Common types
 pub mod types;
 /// Utilities
 pub mod util;
+#[cfg(feature = "websocket")]
+/// WebSocket channel pushing credential updates to hosts
+pub mod websocket;
 // credential collection and rendering
 #[cfg(feature = "platform_token")]
 pub mod credentials;
-#[cfg(feature = "platform_token")]
 #[macro_use]
 extern crate lazy_static;
 
 pub mod prelude {
+    pub use crate::api_token::ApiToken;
+    pub use crate::attr_id::{AttrIdGenerator, HmacAttrIds, RandomAttrIds};
     pub use crate::config::Config;
     pub use crate::error::Error;
-    pub use crate::jwt::sign_auth_select_params;
+    pub use crate::jwt::{
+        sign_and_encrypt_auth_select_params, sign_auth_select_params,
+        sign_auth_select_params_with_key_id, WidgetClaims,
+    };
+    #[cfg(feature = "rocket")]
+    pub use crate::proxy::{ClientAddr, TrustedProxies, TrustedProxyFairing};
+    #[cfg(feature = "rocket")]
+    pub use crate::request_id::{RequestId, RequestIdFairing, WithRequestId};
+    #[cfg(feature = "rocket")]
+    pub use crate::secrets::load_config;
+    pub use crate::secrets::{register_secret_source, SecretSource};
+    #[cfg(all(feature = "session_db", feature = "axum"))]
+    pub use crate::session::SessionDB;
+    #[cfg(feature = "session_db")]
+    pub use crate::session::{GuestStatus, RoomSummary, Session, SessionDBConn, SessionDBPool};
     #[cfg(feature = "session_db")]
-    pub use crate::session::{Session, SessionDBConn};
+    pub use crate::start::{renew_guest_session, start_guest_session, StartResponse};
+    pub use crate::templates::{
+        register_context_extender, set_template_settings, ContextExtender, TemplateSettings,
+        TEMPLATES,
+    };
+    pub use crate::translations::{Translations, TRANSLATIONS};
     pub use crate::types::StartRequest;
-    pub use crate::types::{AuthSelectParams, Credentials, GuestAuthResult};
-    pub use crate::util::random_string;
+    pub use crate::types::{
+        AuthResultSet, AuthSelectParams, Credentials, GuestAuthResult, SortedCredentials,
+    };
+    pub use crate::util::{constant_time_eq, join_url, random_string, random_token};
 
-    #[cfg(feature = "session_db")]
-    pub use crate::credentials::get_credentials_for_host;
     #[cfg(feature = "platform_token")]
-    pub use crate::credentials::{collect_credentials, render_credentials};
+    pub use crate::credentials::{
+        collect_credentials, render_credentials, render_credentials_for_purpose,
+        render_credentials_localized, CredentialFilter, RenderType, RenderedContent,
+        RoomCredentials, RoomState,
+    };
+    #[cfg(feature = "session_db")]
+    pub use crate::credentials::{
+        get_credentials_for_host, get_encrypted_credentials_for_host,
+        get_filtered_credentials_for_host, get_filtered_encrypted_credentials_for_host,
+        get_room_credentials_for_host, render_room_credentials,
+    };
     #[cfg(feature = "platform_token")]
     pub use crate::types::{FromPlatformJwt, GuestToken, HostToken};
+
+    #[cfg(feature = "saml")]
+    pub use crate::auth::SamlProvider;
+    #[cfg(feature = "oauth")]
+    pub use crate::auth::{
+        exchange_code, logout, render_login, render_login_result, render_logout_result,
+        AuthProvider, HostUser, IrmaProvider, LoginState, LoginUrl, OAuthClient, OAuthProvider,
+        OidcProvider, PkceVerifier, TokenResponse,
+    };
 }
@@ -0,0 +1,159 @@
+use crate::error::Error;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Configuration of the temporary lockout after repeated invalid host tokens
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct LockoutConfig {
+    /// Failures within the window after which further attempts are blocked.
+    /// Zero disables the lockout.
+    pub max_failures: u32,
+    /// Seconds within which failures are counted together
+    pub window_secs: u64,
+    /// Seconds attempts are blocked for once locked out
+    pub lockout_secs: u64,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        LockoutConfig {
+            max_failures: 10,
+            window_secs: 300,
+            lockout_secs: 900,
+        }
+    }
+}
+
+struct Failures {
+    count: u32,
+    since: Instant,
+    locked_until: Option<Instant>,
+}
+
+struct Lockout {
+    config: LockoutConfig,
+    failures: HashMap<String, Failures>,
+}
+
+/// Number of tracked clients above which expired entries are pruned
+const PRUNE_THRESHOLD: usize = 10_000;
+
+lazy_static! {
+    static ref LOCKOUT: Mutex<Lockout> = Mutex::new(Lockout {
+        config: LockoutConfig::default(),
+        failures: HashMap::new(),
+    });
+}
+
+/// Replace the lockout configuration, forgetting all failures so far
+pub fn configure_lockout(config: LockoutConfig) {
+    let mut lockout = LOCKOUT.lock().expect("Lockout lock poisoned");
+    lockout.config = config;
+    lockout.failures.clear();
+}
+
+/// Key failures are counted under. Only the client's address is used: keying
+/// on anything read from the unverified token, such as its room, would let
+/// anyone lock a host out of their room by sending bad tokens for it.
+pub(crate) fn key(client_ip: IpAddr) -> String {
+    format!("ip:{}", client_ip)
+}
+
+impl Lockout {
+    fn check(&self, key: &str) -> Result<(), Error> {
+        let now = Instant::now();
+        match self.failures.get(key).and_then(|f| f.locked_until) {
+            Some(locked_until) if locked_until > now => {
+                Err(Error::TooManyRequests((locked_until - now).as_secs() + 1))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn record_failure(&mut self, key: &str) {
+        if self.config.max_failures == 0 {
+            return;
+        }
+        let window = Duration::from_secs(self.config.window_secs);
+        let now = Instant::now();
+
+        if self.failures.len() > PRUNE_THRESHOLD {
+            self.failures.retain(|_, failures| {
+                failures.since.elapsed() < window
+                    || matches!(failures.locked_until, Some(until) if until > now)
+            });
+        }
+
+        let failures = self.failures.entry(key.to_string()).or_insert(Failures {
+            count: 0,
+            since: now,
+            locked_until: None,
+        });
+        let locked = matches!(failures.locked_until, Some(until) if until > now);
+        if failures.since.elapsed() >= window && !locked {
+            *failures = Failures {
+                count: 0,
+                since: now,
+                locked_until: None,
+            };
+        }
+        failures.count += 1;
+        if failures.count >= self.config.max_failures {
+            log::warn!(
+                "Locking out {} after {} failed attempts",
+                key,
+                failures.count
+            );
+            failures.locked_until = Some(now + Duration::from_secs(self.config.lockout_secs));
+        }
+    }
+}
+
+/// Fail with `TooManyRequests` if the key is locked out
+pub(crate) fn check(key: &str) -> Result<(), Error> {
+    LOCKOUT.lock().expect("Lockout lock poisoned").check(key)
+}
+
+/// Count a failed attempt, locking the key out once it has failed too often
+pub(crate) fn record_failure(key: &str) {
+    LOCKOUT
+        .lock()
+        .expect("Lockout lock poisoned")
+        .record_failure(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lockout() {
+        let mut lockout = Lockout {
+            config: LockoutConfig {
+                max_failures: 3,
+                window_secs: 60,
+                lockout_secs: 60,
+            },
+            failures: HashMap::new(),
+        };
+
+        let ip = key("192.0.2.1".parse().unwrap());
+        assert_eq!(ip, "ip:192.0.2.1");
+        for _ in 0..2 {
+            lockout.record_failure(&ip);
+            assert!(lockout.check(&ip).is_ok());
+        }
+        lockout.record_failure(&ip);
+        assert!(matches!(
+            lockout.check(&ip),
+            Err(Error::TooManyRequests(60))
+        ));
+        assert!(lockout.check("ip:192.0.2.2").is_ok());
+    }
+}
@@ -0,0 +1,127 @@
+use crate::{error::Error, proxy::ClientAddr, reporting::ErrorContext};
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Data, Request, Response,
+};
+use serde::Deserialize;
+use std::time::Instant;
+use tracing_subscriber::EnvFilter;
+
+/// Output format of log lines
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One JSON object per line, for log aggregation
+    Json,
+    /// Human readable lines, for development
+    Text,
+}
+
+/// Configuration of the log output of a plugin
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// Filter directives in `RUST_LOG` syntax, e.g. `info,rocket=warn`
+    pub filter: String,
+    /// Log a line for every handled request. Requires the [`RequestLogFairing`]
+    pub log_requests: bool,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::Json,
+            filter: "info".to_string(),
+            log_requests: true,
+        }
+    }
+}
+
+/// Install the global subscriber for both `tracing` events and `log` records.
+/// Can only be done once per process.
+pub fn init_logging(config: &LoggingConfig) -> Result<(), Error> {
+    let filter =
+        EnvFilter::try_new(&config.filter).map_err(|_| Error::Config("Invalid log filter"))?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = match config.format {
+        LogFormat::Json => builder.json().flatten_event(true).try_init(),
+        LogFormat::Text => builder.try_init(),
+    };
+    result.map_err(|_| Error::Config("Logging was already initialized"))
+}
+
+struct RequestStart(Option<Instant>);
+
+/// Fairing logging the method, route, status and latency of every request,
+/// along with its request id, platform instance and client address. Routes
+/// are logged as declared, such as `/credentials/<host_token>`, so tokens in
+/// URLs stay out of the logs. Attach it after the
+/// [`TrustedProxyFairing`](crate::proxy::TrustedProxyFairing) to log clients
+/// rather than proxies.
+pub struct RequestLogFairing {
+    enabled: bool,
+}
+
+impl RequestLogFairing {
+    pub fn new(config: &LoggingConfig) -> Self {
+        RequestLogFairing {
+            enabled: config.log_requests,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RequestLogFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request logging",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        request.local_cache(|| RequestStart(Some(Instant::now())));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !self.enabled {
+            return;
+        }
+
+        let latency_ms = request
+            .local_cache(|| RequestStart(None))
+            .0
+            .map(|start| start.elapsed().as_millis() as u64);
+        let context = ErrorContext::of(request);
+        tracing::info!(
+            target: "request",
+            method = %request.method(),
+            path = context.route.as_deref().unwrap_or("<unmatched>"),
+            status = response.status().code,
+            latency_ms,
+            request_id = context.request_id.as_deref(),
+            instance = context.instance.as_deref(),
+            client_ip = ClientAddr::of(request).ip.map(|ip| ip.to_string()).as_deref(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_logging_config() {
+        let config: LoggingConfig = serde_yaml::from_str("format: text").unwrap();
+        assert_eq!(config.format, LogFormat::Text);
+        assert_eq!(config.filter, "info");
+        assert!(config.log_requests);
+
+        let config = LoggingConfig {
+            filter: "info,[".to_string(),
+            ..LoggingConfig::default()
+        };
+        assert!(init_logging(&config).is_err());
+    }
+}
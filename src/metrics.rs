@@ -0,0 +1,136 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Timing statistics of a single kind of session database query
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryStats {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+    /// Number of executions that took longer than the slow query threshold
+    pub slow: u64,
+}
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref QUERY_STATS: Mutex<BTreeMap<&'static str, QueryStats>> = Mutex::new(BTreeMap::new());
+    static ref SLOW_QUERY_THRESHOLD: RwLock<Duration> = RwLock::new(Duration::from_secs(1));
+}
+
+/// Change the duration above which queries are logged as slow, one second by default
+pub fn set_slow_query_threshold(threshold: Duration) {
+    *SLOW_QUERY_THRESHOLD
+        .write()
+        .expect("Slow query threshold lock poisoned") = threshold;
+}
+
+/// Statistics of all queries executed so far, by query name
+pub fn query_stats() -> BTreeMap<&'static str, QueryStats> {
+    QUERY_STATS
+        .lock()
+        .expect("Query stats lock poisoned")
+        .clone()
+}
+
+/// Number of queries currently waiting for or using a database connection
+pub fn queries_in_flight() -> usize {
+    IN_FLIGHT.load(Ordering::Relaxed)
+}
+
+/// Run a query future, recording its duration under the given name
+pub(crate) async fn timed<F: Future>(name: &'static str, query: F) -> F::Output {
+    IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+    let start = Instant::now();
+    let result = query.await;
+    let elapsed = start.elapsed();
+    IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+
+    let threshold = *SLOW_QUERY_THRESHOLD
+        .read()
+        .expect("Slow query threshold lock poisoned");
+    let slow = elapsed > threshold;
+    if slow {
+        log::warn!("Slow session query {} took {:?}", name, elapsed);
+    }
+
+    let mut stats = QUERY_STATS.lock().expect("Query stats lock poisoned");
+    let entry = stats.entry(name).or_default();
+    entry.count += 1;
+    entry.total += elapsed;
+    entry.max = entry.max.max(elapsed);
+    if slow {
+        entry.slow += 1;
+    }
+    result
+}
+
+fn push_metric(
+    out: &mut String,
+    metric: &str,
+    kind: &str,
+    stats: &BTreeMap<&'static str, QueryStats>,
+    value: impl Fn(&QueryStats) -> String,
+) {
+    out.push_str(&format!("# TYPE {} {}\n", metric, kind));
+    for (query, s) in stats.iter() {
+        out.push_str(&format!("{}{{query=\"{}\"}} {}\n", metric, query, value(s)));
+    }
+}
+
+/// Render the query statistics in the Prometheus text exposition format
+pub fn render_prometheus() -> String {
+    let stats = query_stats();
+    let mut out = String::new();
+
+    out.push_str("# TYPE session_db_queries_in_flight gauge\n");
+    out.push_str(&format!(
+        "session_db_queries_in_flight {}\n",
+        queries_in_flight()
+    ));
+
+    push_metric(&mut out, "session_db_query_count", "counter", &stats, |s| {
+        s.count.to_string()
+    });
+    push_metric(
+        &mut out,
+        "session_db_query_seconds_total",
+        "counter",
+        &stats,
+        |s| s.total.as_secs_f64().to_string(),
+    );
+    push_metric(
+        &mut out,
+        "session_db_query_seconds_max",
+        "gauge",
+        &stats,
+        |s| s.max.as_secs_f64().to_string(),
+    );
+    push_metric(
+        &mut out,
+        "session_db_slow_query_count",
+        "counter",
+        &stats,
+        |s| s.slow.to_string(),
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rocket::async_test]
+    async fn test_timed() {
+        set_slow_query_threshold(Duration::from_millis(0));
+        assert_eq!(timed("test_query", async { 42 }).await, 42);
+
+        let stats = query_stats()["test_query"];
+        assert_eq!(stats.count, 1);
+        assert_eq!(stats.slow, 1);
+        assert!(render_prometheus().contains("session_db_query_count{query=\"test_query\"} 1\n"));
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{error::Error, session::SessionDBConn};
+use rocket::{fairing::AdHoc, Build, Rocket};
+
+/// Session database migrations, by schema version
+const MIGRATIONS: &[(i32, &str)] = &[
+    (1, include_str!("migrations/001_create_session.sql")),
+    (2, include_str!("migrations/002_session_left_at.sql")),
+    (3, include_str!("migrations/003_session_deleted_at.sql")),
+    (4, include_str!("migrations/004_session_indexes.sql")),
+    (5, include_str!("migrations/005_session_metadata.sql")),
+    (6, include_str!("migrations/006_session_created_at.sql")),
+    (7, include_str!("migrations/007_usage_stats.sql")),
+    (
+        8,
+        include_str!("migrations/008_session_authenticated_at.sql"),
+    ),
+];
+
+/// Schema version this version of the crate expects
+pub const SCHEMA_VERSION: i32 = MIGRATIONS[MIGRATIONS.len() - 1].0;
+
+/// Bring the session database schema up to date. Migrations that were applied
+/// before are skipped, so this can safely be run on every startup.
+pub async fn migrate(db: &SessionDBConn) -> Result<(), Error> {
+    db.run(|c| -> Result<(), Error> {
+        let mut transaction = c.transaction()?;
+        transaction.batch_execute(
+            "CREATE TABLE IF NOT EXISTS session_schema_version (version INTEGER NOT NULL);
+            LOCK TABLE session_schema_version IN EXCLUSIVE MODE;",
+        )?;
+        let current: i32 = transaction
+            .query_one(
+                "SELECT COALESCE(MAX(version), 0) FROM session_schema_version",
+                &[],
+            )?
+            .get(0);
+
+        for (version, migration) in MIGRATIONS.iter().filter(|(v, _)| *v > current) {
+            transaction.batch_execute(migration)?;
+            transaction.execute(
+                "INSERT INTO session_schema_version (version) VALUES ($1)",
+                &[version],
+            )?;
+        }
+
+        transaction.commit()?;
+        Ok(())
+    })
+    .await
+}
+
+/// Current schema version of the session database, 0 if it was never migrated
+pub async fn schema_version(db: &SessionDBConn) -> Result<i32, Error> {
+    db.run(|c| -> Result<i32, Error> {
+        let exists: bool = c
+            .query_one(
+                "SELECT to_regclass('session_schema_version') IS NOT NULL",
+                &[],
+            )?
+            .get(0);
+        if !exists {
+            return Ok(0);
+        }
+        Ok(c.query_one(
+            "SELECT COALESCE(MAX(version), 0) FROM session_schema_version",
+            &[],
+        )?
+        .get(0))
+    })
+    .await
+}
+
+/// Fail if the session database schema is not the one this crate expects
+pub async fn check_schema_version(db: &SessionDBConn) -> Result<(), Error> {
+    let found = schema_version(db).await?;
+    if found != SCHEMA_VERSION {
+        return Err(Error::SchemaVersionMismatch {
+            expected: SCHEMA_VERSION,
+            found,
+        });
+    }
+    Ok(())
+}
+
+/// Fairing aborting launch if the session database schema is out of sync
+/// with this crate. Attach it after `SessionDBConn::fairing()`.
+pub fn schema_check_fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Session schema check", |rocket: Rocket<Build>| async move {
+        let result = match SessionDBConn::get_one(&rocket).await {
+            Some(db) => check_schema_version(&db).await,
+            None => {
+                log::error!("No session database connection available for the schema check");
+                return Err(rocket);
+            }
+        };
+        match result {
+            Ok(()) => Ok(rocket),
+            Err(e) => {
+                log::error!("{}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS session (
+    session_id TEXT NOT NULL PRIMARY KEY,
+    room_id TEXT NOT NULL,
+    domain TEXT NOT NULL,
+    redirect_url TEXT NOT NULL,
+    purpose TEXT NOT NULL,
+    name TEXT NOT NULL,
+    instance TEXT NOT NULL,
+    attr_id TEXT NOT NULL UNIQUE,
+    auth_result TEXT,
+    last_activity TIMESTAMPTZ NOT NULL
+);
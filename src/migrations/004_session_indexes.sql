@@ -0,0 +1,3 @@
+-- attr_id is already indexed by its unique constraint
+CREATE INDEX IF NOT EXISTS session_room_id_idx ON session (room_id);
+CREATE INDEX IF NOT EXISTS session_last_activity_idx ON session (last_activity);
@@ -0,0 +1 @@
+ALTER TABLE session ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,3 @@
+-- Sessions created before this migration keep a NULL creation time
+ALTER TABLE session ADD COLUMN created_at TIMESTAMPTZ;
+ALTER TABLE session ALTER COLUMN created_at SET DEFAULT now();
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS usage_stats (
+    day DATE NOT NULL,
+    metric TEXT NOT NULL,
+    label TEXT NOT NULL,
+    count BIGINT NOT NULL,
+    PRIMARY KEY (day, metric, label)
+);
@@ -0,0 +1,2 @@
+-- Sessions authenticated before this migration keep a NULL authentication time
+ALTER TABLE session ADD COLUMN authenticated_at TIMESTAMPTZ;
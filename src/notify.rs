@@ -0,0 +1,153 @@
+use crate::{
+    error::Error,
+    events::{SessionEvent, SessionEventPublisher},
+    templates,
+    translations::TRANSLATIONS,
+};
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use serde::Deserialize;
+use std::fmt::Debug;
+
+/// SMTP settings and recipients for e-mail notifications
+#[derive(Deserialize, Clone)]
+pub struct NotifyConfig {
+    smtp_host: String,
+    smtp_port: Option<u16>,
+    smtp_username: Option<String>,
+    smtp_password: Option<String>,
+    /// Sender address, e.g. `ID Contact <noreply@example.com>`
+    from: String,
+    /// Addresses that receive a notification for every completed authentication
+    to: Vec<String>,
+    /// Name of the deployment, mentioned in the e-mail
+    display_name: Option<String>,
+}
+
+// Custom implementation to keep the SMTP password out of the logs
+impl Debug for NotifyConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NotifyConfig")
+            .field("smtp_host", &self.smtp_host)
+            .field("smtp_port", &self.smtp_port)
+            .field("smtp_username", &self.smtp_username)
+            .field("from", &self.from)
+            .field("to", &self.to)
+            .field("display_name", &self.display_name)
+            .finish()
+    }
+}
+
+/// Render the body of the notification for a completed authentication in the given room
+pub fn render_notification(room_id: &str, display_name: Option<&str>) -> Result<String, Error> {
+    let mut context = templates::localized_context(&TRANSLATIONS);
+    context.insert("room_id", room_id);
+    context.insert("display_name", &display_name);
+    Ok(templates::render("notify_email.txt", context)?)
+}
+
+/// Publisher sending an e-mail whenever a guest completes authentication
+pub struct EmailNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    display_name: Option<String>,
+}
+
+impl EmailNotifier {
+    pub fn new(config: &NotifyConfig) -> Result<Self, Error> {
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host)?;
+        if let Some(port) = config.smtp_port {
+            transport = transport.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+            transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        let to = config
+            .to
+            .iter()
+            .map(|address| address.parse())
+            .collect::<Result<Vec<Mailbox>, _>>()?;
+        if to.is_empty() {
+            return Err(Error::Config("No recipients configured for notifications"));
+        }
+
+        Ok(EmailNotifier {
+            transport: transport.build(),
+            from: config.from.parse()?,
+            to,
+            display_name: config.display_name.clone(),
+        })
+    }
+
+    fn message(&self, room_id: &str) -> Result<Message, Error> {
+        let body = render_notification(room_id, self.display_name.as_deref())?;
+        let mut builder = Message::builder()
+            .from(self.from.clone())
+            .subject(TRANSLATIONS.get("notify_subject"));
+        for to in &self.to {
+            builder = builder.to(to.clone());
+        }
+        Ok(builder.body(body)?)
+    }
+}
+
+impl SessionEventPublisher for EmailNotifier {
+    fn publish(&self, event: &SessionEvent) {
+        let room_id = match event {
+            SessionEvent::AuthResultReceived { room_id, .. } => room_id,
+            _ => return,
+        };
+
+        let message = match self.message(room_id) {
+            Ok(message) => message,
+            Err(e) => {
+                log::warn!("Could not compose notification e-mail: {}", e);
+                return;
+            }
+        };
+
+        let transport = self.transport.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = transport.send(message).await {
+                        log::warn!("Could not send notification e-mail: {}", e);
+                    }
+                });
+            }
+            Err(_) => log::warn!("E-mail notifier called outside of async runtime"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notification() {
+        let config: NotifyConfig = serde_yaml::from_str(
+            r"
+            smtp_host: smtp.example.com
+            smtp_username: user
+            smtp_password: hunter2
+            from: ID Contact <noreply@example.com>
+            to:
+              - host@example.com
+            display_name: Gemeente
+            ",
+        )
+        .unwrap();
+        assert!(!format!("{:?}", config).contains("hunter2"));
+
+        let notifier = EmailNotifier::new(&config).unwrap();
+        let message = String::from_utf8(notifier.message("room42").unwrap().formatted()).unwrap();
+        assert!(message.contains("room42"));
+        assert!(message.contains("Gemeente"));
+        assert!(message.contains("host@example.com"));
+    }
+}
@@ -0,0 +1,365 @@
+#[cfg(feature = "rocket")]
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    request::{FromRequest, Outcome},
+    Data, Request,
+};
+use serde::Deserialize;
+use std::{
+    convert::{Infallible, TryFrom},
+    fmt,
+    net::{IpAddr, SocketAddr},
+};
+
+/// Header listing the client and the proxies a request passed through
+pub const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+/// Header with the scheme the client used to reach the first proxy
+pub const FORWARDED_PROTO_HEADER: &str = "X-Forwarded-Proto";
+
+/// A range of addresses in CIDR notation, such as `10.0.0.0/8` or `::1`.
+/// A single address is a range of one.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = String;
+    fn try_from(range: String) -> Result<Self, String> {
+        let invalid = || format!("Invalid IP range {}", range);
+        let (network, prefix) = match range.split_once('/') {
+            Some((network, prefix)) => (network, Some(prefix)),
+            None => (range.as_str(), None),
+        };
+        let network: IpAddr = network.parse().map_err(|_| invalid())?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+            None => max_prefix,
+        };
+        if prefix > max_prefix {
+            return Err(invalid());
+        }
+        Ok(IpRange { network, prefix })
+    }
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, canonical(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// IPv4 clients of a server listening on IPv6 show up as mapped addresses
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    }
+}
+
+/// Reverse proxies whose `X-Forwarded-For` and `X-Forwarded-Proto` headers
+/// are believed. Empty by default, in which case these headers are ignored.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct TrustedProxies(Vec<IpRange>);
+
+impl TrustedProxies {
+    pub fn new(ranges: Vec<IpRange>) -> Self {
+        TrustedProxies(ranges)
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.0.iter().any(|range| range.contains(ip))
+    }
+
+    /// Determine the client address from the address of the peer and the
+    /// forwarding headers. `X-Forwarded-For` is walked from the right, as
+    /// only the entries added by trusted proxies can be relied upon: the
+    /// client is the first address not belonging to a trusted proxy.
+    pub fn resolve(
+        &self,
+        peer: Option<IpAddr>,
+        forwarded_for: Option<&str>,
+        forwarded_proto: Option<&str>,
+    ) -> ClientAddr {
+        let peer = peer.map(canonical);
+        let direct = ClientAddr {
+            ip: peer,
+            scheme: None,
+        };
+        let peer = match peer {
+            Some(peer) if self.is_trusted(peer) => peer,
+            _ => return direct,
+        };
+
+        let mut ip = peer;
+        for hop in forwarded_for.unwrap_or_default().rsplit(',') {
+            if !self.is_trusted(ip) {
+                break;
+            }
+            match parse_hop(hop) {
+                Some(hop) => ip = hop,
+                // Anything left of a malformed entry cannot be trusted
+                None => break,
+            }
+        }
+
+        let scheme = forwarded_proto
+            .and_then(|proto| proto.split(',').next())
+            .and_then(|proto| match proto.trim().to_ascii_lowercase().as_str() {
+                "http" => Some(Scheme::Http),
+                "https" => Some(Scheme::Https),
+                _ => None,
+            });
+        ClientAddr {
+            ip: Some(ip),
+            scheme,
+        }
+    }
+}
+
+/// Entries of `X-Forwarded-For` are plain addresses, but some proxies add ports
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim();
+    hop.parse::<IpAddr>()
+        .ok()
+        .or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .map(canonical)
+}
+
+/// Scheme a client used to reach the plugin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Http,
+    Https,
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+        })
+    }
+}
+
+/// Address of the client that sent a request, and the scheme it used if a
+/// trusted proxy reported it. Can be used as request guard. Unlike Rocket's
+/// own client IP, forwarding headers are only believed when sent by a
+/// trusted proxy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientAddr {
+    pub ip: Option<IpAddr>,
+    pub scheme: Option<Scheme>,
+}
+
+#[cfg(feature = "rocket")]
+impl ClientAddr {
+    /// Retrieve the client address of the current request. If the
+    /// [`TrustedProxyFairing`] is not attached, this is the address of the
+    /// peer and forwarding headers are ignored.
+    pub fn of(request: &Request<'_>) -> Self {
+        *request.local_cache(|| ClientAddr {
+            ip: request.remote().map(|addr| canonical(addr.ip())),
+            scheme: None,
+        })
+    }
+}
+
+impl fmt::Display for ClientAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.ip {
+            Some(ip) => write!(f, "{}", ip),
+            None => f.write_str("unknown address"),
+        }
+    }
+}
+
+#[cfg(feature = "rocket")]
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientAddr {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ClientAddr::of(request))
+    }
+}
+
+/// Extracts the client address in axum handlers. The peer address is taken
+/// from [`ConnectInfo`](axum::extract::ConnectInfo), so serve the router with
+/// `into_make_service_with_connect_info`. Forwarding headers are only
+/// believed if a [`TrustedProxies`] extension is added to the router.
+#[cfg(feature = "axum")]
+#[axum::async_trait]
+impl<B: Send> axum::extract::FromRequest<B> for ClientAddr {
+    type Rejection = Infallible;
+
+    async fn from_request(
+        request: &mut axum::extract::RequestParts<B>,
+    ) -> Result<Self, Self::Rejection> {
+        let peer = request
+            .extensions()
+            .get::<axum::extract::ConnectInfo<SocketAddr>>()
+            .map(|connect_info| connect_info.0.ip());
+        let proxies = match request.extensions().get::<TrustedProxies>() {
+            Some(proxies) => proxies,
+            None => return Ok(TrustedProxies::default().resolve(peer, None, None)),
+        };
+        let headers = request.headers();
+        let forwarded_for = headers
+            .get_all(FORWARDED_FOR_HEADER)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        Ok(proxies.resolve(
+            peer,
+            Some(forwarded_for.as_str()).filter(|header| !header.is_empty()),
+            headers
+                .get(FORWARDED_PROTO_HEADER)
+                .and_then(|value| value.to_str().ok()),
+        ))
+    }
+}
+
+/// Fairing resolving the [`ClientAddr`] of each request from the forwarding
+/// headers set by the configured trusted proxies. Attach it before fairings
+/// that log or limit requests by client.
+#[cfg(feature = "rocket")]
+pub struct TrustedProxyFairing {
+    proxies: TrustedProxies,
+}
+
+#[cfg(feature = "rocket")]
+impl TrustedProxyFairing {
+    pub fn new(proxies: TrustedProxies) -> Self {
+        TrustedProxyFairing { proxies }
+    }
+}
+
+#[cfg(feature = "rocket")]
+#[rocket::async_trait]
+impl Fairing for TrustedProxyFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Trusted proxies",
+            kind: Kind::Request,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let forwarded_for = request
+            .headers()
+            .get(FORWARDED_FOR_HEADER)
+            .collect::<Vec<_>>()
+            .join(",");
+        let client_addr = self.proxies.resolve(
+            request.remote().map(|addr| addr.ip()),
+            Some(forwarded_for.as_str()).filter(|header| !header.is_empty()),
+            request.headers().get_one(FORWARDED_PROTO_HEADER),
+        );
+        request.local_cache(|| client_addr);
+    }
+}
+
+#[cfg(all(test, feature = "rocket"))]
+mod tests {
+    use super::*;
+    use rocket::{http::Header, local::blocking::Client};
+
+    fn proxies() -> TrustedProxies {
+        serde_yaml::from_str("[10.0.0.0/8, '::1']").unwrap()
+    }
+
+    #[test]
+    fn test_ip_range() {
+        let range = IpRange::try_from("10.1.0.0/16".to_string()).unwrap();
+        assert!(range.contains("10.1.2.3".parse().unwrap()));
+        assert!(range.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("10.2.0.1".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+
+        let all = IpRange::try_from("::/0".to_string()).unwrap();
+        assert!(all.contains("2001:db8::1".parse().unwrap()));
+        let single = IpRange::try_from("2001:db8::1".to_string()).unwrap();
+        assert!(single.contains("2001:db8::1".parse().unwrap()));
+        assert!(!single.contains("2001:db8::2".parse().unwrap()));
+
+        assert!(IpRange::try_from("10.0.0.0/33".to_string()).is_err());
+        assert!(IpRange::try_from("10.0.0/8".to_string()).is_err());
+        assert!(serde_yaml::from_str::<TrustedProxies>("[example.com]").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let proxies = proxies();
+        let proxy = Some("10.0.0.1".parse().unwrap());
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+
+        // Headers from untrusted peers are ignored
+        let resolved = proxies.resolve(Some(client), Some("198.51.100.1"), Some("https"));
+        assert_eq!(resolved.ip, Some(client));
+        assert_eq!(resolved.scheme, None);
+
+        let resolved = proxies.resolve(proxy, Some("203.0.113.7"), Some("HTTPS"));
+        assert_eq!(resolved.ip, Some(client));
+        assert_eq!(resolved.scheme, Some(Scheme::Https));
+
+        // Addresses spoofed by the client are left of the one it was seen with
+        let resolved = proxies.resolve(proxy, Some("198.51.100.1, 203.0.113.7, 10.0.0.2"), None);
+        assert_eq!(resolved.ip, Some(client));
+        let resolved = proxies.resolve(proxy, Some("garbage, 203.0.113.7:4321"), None);
+        assert_eq!(resolved.ip, Some(client));
+        let resolved = proxies.resolve(proxy, Some("203.0.113.7, garbage"), Some("ftp"));
+        assert_eq!(resolved.ip, proxy);
+        assert_eq!(resolved.scheme, None);
+
+        assert_eq!(proxies.resolve(proxy, None, None).ip, proxy);
+        assert_eq!(proxies.resolve(None, Some("203.0.113.7"), None).ip, None);
+    }
+
+    #[rocket::get("/")]
+    fn client_addr(client: ClientAddr) -> String {
+        format!("{:?} {:?}", client.ip, client.scheme)
+    }
+
+    #[test]
+    fn test_trusted_proxy_fairing() {
+        let rocket = rocket::build()
+            .attach(TrustedProxyFairing::new(proxies()))
+            .mount("/", rocket::routes![client_addr]);
+        let client = Client::tracked(rocket).unwrap();
+
+        let response = client
+            .get("/")
+            .remote("10.0.0.1:8000".parse().unwrap())
+            .header(Header::new(FORWARDED_FOR_HEADER, "198.51.100.1"))
+            .header(Header::new(FORWARDED_FOR_HEADER, "203.0.113.7"))
+            .header(Header::new(FORWARDED_PROTO_HEADER, "https"))
+            .dispatch();
+        assert_eq!(
+            response.into_string().unwrap(),
+            "Some(203.0.113.7) Some(Https)"
+        );
+
+        let response = client
+            .get("/")
+            .remote("203.0.113.7:8000".parse().unwrap())
+            .header(Header::new(FORWARDED_FOR_HEADER, "10.0.0.1"))
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), "Some(203.0.113.7) None");
+    }
+}
@@ -0,0 +1,181 @@
+use crate::error::{Error, ErrorKind};
+use std::{any::Any, panic, sync::RwLock};
+
+/// Where an unexpected error occurred, as far as known
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub request_id: Option<String>,
+    /// Route that was handling the request
+    pub route: Option<String>,
+    /// Communication platform instance the request was made for
+    pub instance: Option<String>,
+}
+
+/// Receiver of unexpected errors, such as an error tracking service
+pub trait ErrorReporter: Send + Sync {
+    fn report(&self, message: &str, kind: ErrorKind, context: &ErrorContext);
+}
+
+lazy_static! {
+    static ref REPORTERS: RwLock<Vec<Box<dyn ErrorReporter>>> = RwLock::new(vec![]);
+}
+
+/// Register a reporter to receive unexpected errors from now on
+pub fn register_error_reporter(reporter: impl ErrorReporter + 'static) {
+    REPORTERS
+        .write()
+        .expect("Error reporters lock poisoned")
+        .push(Box::new(reporter));
+}
+
+fn report(message: &str, kind: ErrorKind, context: &ErrorContext) {
+    for reporter in REPORTERS
+        .read()
+        .expect("Error reporters lock poisoned")
+        .iter()
+    {
+        reporter.report(message, kind, context);
+    }
+}
+
+/// Report an error to the registered reporters. Errors caused by the client,
+/// which are answered with a 4xx status, are not reported.
+pub fn report_error(error: &Error, context: &ErrorContext) {
+    if error.status_code() >= 500 {
+        report(&error.to_string(), error.kind(), context);
+    }
+}
+
+/// Report panics to the registered reporters, in addition to the panic
+/// handling already in place
+pub fn install_panic_hook() {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let message = match info.location() {
+            Some(location) => format!("Panic at {}: {}", location, panic_message(info.payload())),
+            None => format!("Panic: {}", panic_message(info.payload())),
+        };
+        report(&message, ErrorKind::Internal, &ErrorContext::default());
+        previous(info);
+    }));
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .unwrap_or("unknown cause"),
+    }
+}
+
+#[cfg(feature = "rocket")]
+mod request {
+    use super::ErrorContext;
+    use crate::request_id::RequestId;
+    use rocket::Request;
+
+    struct RequestInstance(Option<String>);
+
+    /// Record the platform instance a request is made for, to be included
+    /// in reports of errors occurring while handling it
+    pub fn set_request_instance(request: &Request<'_>, instance: &str) {
+        request.local_cache(|| RequestInstance(Some(instance.to_string())));
+    }
+
+    impl ErrorContext {
+        /// Context of an error occurring while handling the given request
+        pub fn of(request: &Request<'_>) -> Self {
+            ErrorContext {
+                request_id: Some(RequestId::of(request).to_string()),
+                route: request.route().map(|route| route.uri.to_string()),
+                instance: request.local_cache(|| RequestInstance(None)).0.clone(),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "rocket")]
+pub use request::set_request_instance;
+
+#[cfg(feature = "sentry_reporting")]
+mod sentry_reporter {
+    use super::{register_error_reporter, ErrorContext, ErrorReporter};
+    use crate::error::ErrorKind;
+
+    /// Reporter sending errors to Sentry
+    pub struct SentryReporter;
+
+    impl ErrorReporter for SentryReporter {
+        fn report(&self, message: &str, kind: ErrorKind, context: &ErrorContext) {
+            sentry::with_scope(
+                |scope| {
+                    scope.set_tag("code", kind);
+                    let tags = [
+                        ("request_id", &context.request_id),
+                        ("route", &context.route),
+                        ("instance", &context.instance),
+                    ];
+                    for (key, value) in tags.iter() {
+                        if let Some(value) = value {
+                            scope.set_tag(key, value);
+                        }
+                    }
+                },
+                || sentry::capture_message(message, sentry::Level::Error),
+            );
+        }
+    }
+
+    /// Initialize the Sentry client if a DSN is configured, and register it
+    /// as error reporter. Errors are reported as long as the returned guard lives.
+    pub fn init_sentry(dsn: Option<&str>) -> Option<sentry::ClientInitGuard> {
+        let guard = sentry::init((
+            dsn?,
+            sentry::ClientOptions {
+                release: sentry::release_name!(),
+                ..Default::default()
+            },
+        ));
+        register_error_reporter(SentryReporter);
+        Some(guard)
+    }
+}
+
+#[cfg(feature = "sentry_reporting")]
+pub use sentry_reporter::{init_sentry, SentryReporter};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct Collector(Arc<Mutex<Vec<(String, ErrorKind)>>>);
+
+    impl ErrorReporter for Collector {
+        fn report(&self, message: &str, kind: ErrorKind, context: &ErrorContext) {
+            if context.route.as_deref() == Some("/test_report_error") {
+                self.0.lock().unwrap().push((message.to_string(), kind));
+            }
+        }
+    }
+
+    #[test]
+    fn test_report_error() {
+        let reports = Arc::new(Mutex::new(vec![]));
+        register_error_reporter(Collector(reports.clone()));
+
+        let context = ErrorContext {
+            route: Some("/test_report_error".to_string()),
+            ..ErrorContext::default()
+        };
+        report_error(&Error::NotFound, &context);
+        report_error(&Error::Timeout("core"), &context);
+
+        assert_eq!(
+            *reports.lock().unwrap(),
+            vec![("Timeout: core".to_string(), ErrorKind::Timeout)]
+        );
+    }
+}
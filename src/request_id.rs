@@ -0,0 +1,153 @@
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    request::{FromRequest, Outcome},
+    Data, Request, Response,
+};
+use std::{convert::Infallible, fmt};
+
+/// Header used to propagate request ids between services
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Identifier of a single request, used to correlate logs and errors across services.
+/// Can be used as request guard.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Generate a new random request id
+    pub fn generate() -> Self {
+        RequestId(uuid::Uuid::new_v4().to_string())
+    }
+
+    /// Take over a request id provided by the caller, if it looks sane
+    fn from_header(value: &str) -> Option<Self> {
+        let valid = !value.is_empty()
+            && value.len() <= 64
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if valid {
+            Some(RequestId(value.to_string()))
+        } else {
+            None
+        }
+    }
+
+    /// Retrieve the id of the current request, generating one if
+    /// the [`RequestIdFairing`] is not attached
+    pub fn of(request: &Request<'_>) -> Self {
+        request.local_cache(RequestId::generate).clone()
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestId {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestId::of(request))
+    }
+}
+
+/// Extracts the request id in axum handlers, reusing the `X-Request-Id`
+/// header if the caller provided one
+#[cfg(feature = "axum")]
+#[axum::async_trait]
+impl<B: Send> axum::extract::FromRequest<B> for RequestId {
+    type Rejection = Infallible;
+
+    async fn from_request(
+        request: &mut axum::extract::RequestParts<B>,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(RequestId::from_header)
+            .unwrap_or_else(RequestId::generate))
+    }
+}
+
+/// Fairing assigning an id to each request, reusing the `X-Request-Id` header
+/// if the caller provided one, and returning it in the response headers.
+pub struct RequestIdFairing;
+
+#[rocket::async_trait]
+impl Fairing for RequestIdFairing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request ID",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        let request_id = request
+            .headers()
+            .get_one(REQUEST_ID_HEADER)
+            .and_then(RequestId::from_header)
+            .unwrap_or_else(RequestId::generate);
+        request.local_cache(|| request_id);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        response.set_raw_header(REQUEST_ID_HEADER, RequestId::of(request).0);
+    }
+}
+
+/// Forward the request id on outbound calls, such as to the core
+pub trait WithRequestId {
+    fn request_id(self, request_id: &RequestId) -> Self;
+}
+
+impl WithRequestId for reqwest::RequestBuilder {
+    fn request_id(self, request_id: &RequestId) -> Self {
+        self.header(REQUEST_ID_HEADER, request_id.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::{http::Header, local::blocking::Client};
+
+    #[rocket::get("/")]
+    fn request_id(request_id: RequestId) -> String {
+        request_id.to_string()
+    }
+
+    #[test]
+    fn test_request_id_fairing() {
+        let rocket = rocket::build()
+            .attach(RequestIdFairing)
+            .mount("/", rocket::routes![request_id]);
+        let client = Client::tracked(rocket).unwrap();
+
+        let response = client.get("/").dispatch();
+        let header = response.headers().get_one(REQUEST_ID_HEADER).unwrap();
+        assert_eq!(header.len(), 36);
+        assert_eq!(header.to_string(), response.into_string().unwrap());
+
+        let response = client
+            .get("/")
+            .header(Header::new(REQUEST_ID_HEADER, "platform-1234"))
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), "platform-1234");
+
+        let response = client
+            .get("/")
+            .header(Header::new(REQUEST_ID_HEADER, "<script>"))
+            .dispatch();
+        assert_ne!(response.into_string().unwrap(), "<script>");
+    }
+}
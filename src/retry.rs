@@ -0,0 +1,130 @@
+use crate::error::Error;
+use rand::{thread_rng, Rng};
+use reqwest::{RequestBuilder, Response};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Configuration for retrying outbound calls to the core and identity providers
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Maximum number of attempts, including the first one
+    pub attempts: u32,
+    /// Delay before the first retry, in milliseconds
+    pub base_delay_ms: u64,
+    /// Upper bound for the delay between attempts, in milliseconds
+    pub max_delay_ms: u64,
+    /// Time a single attempt may take, including reading the response body,
+    /// in milliseconds
+    pub attempt_timeout_ms: u64,
+    /// Time all attempts together may take, including the delays between
+    /// them, in milliseconds
+    pub deadline_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            attempts: 3,
+            base_delay_ms: 200,
+            max_delay_ms: 2000,
+            attempt_timeout_ms: 5000,
+            deadline_ms: 15000,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Jittered exponential backoff delay after the given (zero-based) failed attempt
+    fn delay(&self, attempt: u32) -> Duration {
+        let max_delay = self
+            .base_delay_ms
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_delay_ms);
+        Duration::from_millis(thread_rng().gen_range(0..=max_delay))
+    }
+}
+
+/// Map timeouts of outbound calls to [`Error::Timeout`]
+fn upstream_error(e: reqwest::Error) -> Error {
+    if e.is_timeout() {
+        Error::Timeout("Upstream request timed out")
+    } else {
+        Error::Reqwest(e)
+    }
+}
+
+/// Send a request, retrying on connection errors, timeouts and 5xx responses.
+/// Requests with a streaming body cannot be cloned and are sent only once.
+/// Fails with [`Error::Timeout`] when an attempt or all attempts together
+/// take longer than configured.
+pub async fn send_with_retry(
+    request: RequestBuilder,
+    config: &RetryConfig,
+) -> Result<Response, Error> {
+    let request = request.timeout(Duration::from_millis(config.attempt_timeout_ms));
+    tokio::time::timeout(
+        Duration::from_millis(config.deadline_ms),
+        send_attempts(request, config),
+    )
+    .await
+    .map_err(|_| Error::Timeout("Upstream request exceeded deadline"))?
+}
+
+async fn send_attempts(request: RequestBuilder, config: &RetryConfig) -> Result<Response, Error> {
+    let mut attempt = 0;
+    loop {
+        let this_attempt = match request.try_clone() {
+            Some(this_attempt) => this_attempt,
+            None => return request.send().await.map_err(upstream_error),
+        };
+
+        let result = this_attempt.send().await;
+        let retryable = match &result {
+            Ok(response) => response.status().is_server_error(),
+            Err(e) => e.is_connect() || e.is_timeout(),
+        };
+
+        attempt += 1;
+        if !retryable || attempt >= config.attempts {
+            return result.map_err(upstream_error);
+        }
+
+        tokio::time::sleep(config.delay(attempt - 1)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_bounds() {
+        let config = RetryConfig::default();
+        for attempt in 0..20 {
+            let delay = config.delay(attempt);
+            assert!(delay <= Duration::from_millis(config.max_delay_ms));
+            assert!(delay <= Duration::from_millis(config.base_delay_ms << attempt.min(16)));
+        }
+    }
+
+    #[test]
+    fn test_deadline() {
+        // Nothing answers on a listener that never accepts
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let config = RetryConfig {
+            attempt_timeout_ms: 50,
+            deadline_ms: 120,
+            base_delay_ms: 0,
+            ..RetryConfig::default()
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let result = runtime.block_on(send_with_retry(reqwest::Client::new().get(url), &config));
+        assert!(matches!(result, Err(Error::Timeout(_))));
+    }
+}
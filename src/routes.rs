@@ -0,0 +1,567 @@
+use crate::{
+    api_token::ApiToken,
+    callback::SignedCallback,
+    config::Config,
+    credentials::{
+        get_filtered_encrypted_credentials_for_host, get_room_credentials, render_room_credentials,
+        CredentialFilter, RenderType, RenderedContent,
+    },
+    diagnostics::Diagnostics,
+    error::Error,
+    jwt::JwtError,
+    lockout,
+    metrics::render_prometheus,
+    proxy::ClientAddr,
+    request_id::RequestId,
+    session::{GuestStatus, RoomSummary, Session, SessionDBConn, SessionDBPool},
+    share::{ShareLink, ShareToken},
+    stats::{usage_stats, DayStats},
+    translations::Translations,
+    types::platform_token::{FromPlatformJwt, GuestToken, HostToken},
+};
+use rocket::{get, post, serde::json::Json, State};
+use serde::Serialize;
+
+#[cfg(feature = "axum")]
+mod axum_routes;
+#[cfg(feature = "axum")]
+pub use axum_routes::router;
+
+/// Routes communication plugins can mount alongside their own
+pub fn routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        room_summary,
+        host_token_info,
+        credentials_version,
+        wait_for_credentials,
+        guest_left,
+        guest_status,
+        close_room,
+        encrypted_credentials,
+        share_room,
+        shared_credentials,
+        auth_result,
+        metrics,
+        stats,
+        reload_keys,
+        diagnostics
+    ]
+}
+
+/// Verify a host token against the key of the instance it was issued for.
+/// Clients presenting invalid tokens too often are temporarily locked out,
+/// during which even valid tokens are refused, so guessing does not pay off.
+/// Expired tokens do not count as failures, and clients whose address is
+/// unknown are not tracked.
+pub(crate) fn verify_host_token(
+    host_token: &str,
+    config: &Config,
+    client: ClientAddr,
+) -> Result<HostToken, Error> {
+    let lockout_key = client.ip.map(lockout::key);
+    if let Some(lockout_key) = &lockout_key {
+        lockout::check(lockout_key)?;
+    }
+
+    HostToken::from_instance_platform_jwt(host_token, |instance| {
+        config
+            .auth_during_comm_config()
+            .host_validator_for(instance)
+    })
+    .map_err(|error| {
+        if let Some(lockout_key) = &lockout_key {
+            if !matches!(error, JwtError::Expired) {
+                lockout::record_failure(lockout_key);
+            }
+        }
+        error.into()
+    })
+}
+
+/// Verify a guest token against the key of the instance it was issued for,
+/// rejecting tokens that would redirect guests to hosts not allowed
+pub(crate) fn verify_guest_token(guest_token: &str, config: &Config) -> Result<GuestToken, Error> {
+    let guest_token = GuestToken::from_instance_platform_jwt(guest_token, |instance| {
+        config
+            .auth_during_comm_config()
+            .guest_validator_for(instance)
+    })?;
+    config.validate_redirect_url(&guest_token.redirect_url)?;
+    Ok(guest_token)
+}
+
+/// Number of guests in the host's room that did and did not yet authenticate,
+/// e.g. to show "2 of 5 guests verified" without fetching any credentials
+#[get("/room_summary/<host_token>")]
+pub async fn room_summary(
+    host_token: String,
+    client: ClientAddr,
+    config: &State<Config>,
+    db: SessionDBConn,
+) -> Result<Json<RoomSummary>, Error> {
+    let host_token = verify_host_token(&host_token, config, client)?;
+    Ok(Json(
+        Session::summary_by_room(host_token.room_id, host_token.instance, &db).await?,
+    ))
+}
+
+/// Claims of a valid host token, as returned by [`host_token_info`]
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostTokenInfo {
+    pub id: String,
+    pub room_id: String,
+    pub instance: String,
+    /// Seconds since the epoch, if the token has an `iat` claim
+    pub issued_at: Option<u64>,
+    /// Seconds since the epoch, if the token has an `exp` claim
+    pub expires_at: Option<u64>,
+}
+
+/// Verify a host token and return its claims
+pub(crate) fn inspect_host_token(
+    host_token: &str,
+    config: &Config,
+    client: ClientAddr,
+) -> Result<HostTokenInfo, Error> {
+    let token = verify_host_token(host_token, config, client)?;
+    Ok(HostTokenInfo {
+        id: token.id,
+        room_id: token.room_id,
+        instance: token.instance,
+        issued_at: token.issued_at,
+        expires_at: token.expires_at,
+    })
+}
+
+/// Claims of a host token, if it is valid, without fetching anything from
+/// the room. For platforms debugging why their tokens are rejected; invalid
+/// tokens get the same error as on any other host route.
+#[get("/host_token_info/<host_token>")]
+pub async fn host_token_info(
+    host_token: String,
+    client: ClientAddr,
+    config: &State<Config>,
+) -> Result<Json<HostTokenInfo>, Error> {
+    Ok(Json(inspect_host_token(&host_token, config, client)?))
+}
+
+/// Version of the credentials in the host's room, for hosts to poll
+/// cheaply before fetching the credentials themselves
+#[get("/credentials_version/<host_token>")]
+pub async fn credentials_version(
+    host_token: String,
+    client: ClientAddr,
+    config: &State<Config>,
+    db: SessionDBConn,
+) -> Result<Json<serde_json::Value>, Error> {
+    let host_token = verify_host_token(&host_token, config, client)?;
+    let version =
+        Session::credentials_version(host_token.room_id, host_token.instance, &db).await?;
+    Ok(Json(serde_json::json!({ "version": version })))
+}
+
+/// Long poll for changes to the credentials in the host's room. Responds as
+/// soon as the version differs from `since`, or when the configured timeout
+/// passes, with the version at that point.
+#[get("/credentials/<host_token>/wait?<since>")]
+pub async fn wait_for_credentials(
+    host_token: String,
+    since: Option<String>,
+    client: ClientAddr,
+    config: &State<Config>,
+    db: SessionDBPool<'_>,
+) -> Result<Json<serde_json::Value>, Error> {
+    let host_token = verify_host_token(&host_token, config, client)?;
+    Ok(Json(
+        wait_for_room_change(host_token, since, config, &db).await?,
+    ))
+}
+
+async fn wait_for_room_change(
+    host_token: HostToken,
+    since: Option<String>,
+    config: &Config,
+    db: &SessionDBPool<'_>,
+) -> Result<serde_json::Value, Error> {
+    let since = since.unwrap_or_default();
+    let version = Session::wait_for_credentials_change(
+        host_token.room_id,
+        host_token.instance,
+        &since,
+        config.long_poll_timeout(),
+        db,
+    )
+    .await?;
+    Ok(serde_json::json!({
+        "version": version,
+        "changed": version != since,
+    }))
+}
+
+/// Called by the communication platform when a guest leaves the room,
+/// so that their data is removed right away
+#[post("/guest_left/<guest_token>")]
+pub async fn guest_left(
+    guest_token: String,
+    config: &State<Config>,
+    db: SessionDBConn,
+) -> Result<(), Error> {
+    let guest_token = verify_guest_token(&guest_token, config)?;
+    Session::delete(guest_token.id, guest_token.instance, &db).await
+}
+
+/// Status of the guest's own session and the URL to return to, for showing
+/// a "waiting for verification" page. Reveals nothing about other guests.
+#[get("/guest_status/<guest_token>")]
+pub async fn guest_status(
+    guest_token: String,
+    config: &State<Config>,
+    db: SessionDBConn,
+) -> Result<Json<GuestStatus>, Error> {
+    Ok(Json(status_of_guest(&guest_token, config, &db).await?))
+}
+
+async fn status_of_guest(
+    guest_token: &str,
+    config: &Config,
+    db: &SessionDBConn,
+) -> Result<GuestStatus, Error> {
+    let guest_token = verify_guest_token(guest_token, config)?;
+    let session = Session::find_by_id(guest_token.id, db).await?;
+    if session.guest_token.instance != guest_token.instance {
+        return Err(Error::SessionNotFound);
+    }
+    // The stored URL may predate the current allowlist
+    config.validate_redirect_url(&session.guest_token.redirect_url)?;
+    Ok(session.guest_status_with_retention(config.retention_config()))
+}
+
+/// Called when the call in a room has ended, to remove the data of all its guests
+#[post("/close_room/<host_token>")]
+pub async fn close_room(
+    host_token: String,
+    client: ClientAddr,
+    config: &State<Config>,
+    db: SessionDBConn,
+) -> Result<(), Error> {
+    let host_token = verify_host_token(&host_token, config, client)?;
+    Session::close_room(host_token.room_id, host_token.instance, &db).await?;
+    Ok(())
+}
+
+/// Credentials of the guests in the host's room, as a JWE encrypted
+/// for the host key from the configuration. Query parameters `name` and
+/// `has` select guests, as described at [`CredentialFilter`].
+#[get("/encrypted_credentials/<host_token>?<filter..>")]
+pub async fn encrypted_credentials(
+    host_token: String,
+    filter: CredentialFilter,
+    client: ClientAddr,
+    config: &State<Config>,
+    db: SessionDBConn,
+) -> Result<String, Error> {
+    get_filtered_encrypted_credentials_for_host(host_token, client, &filter, config, db).await
+}
+
+/// Create a read-only link to the credentials in the host's room, for
+/// handing off to a colleague joining the call late
+#[post("/share/<host_token>")]
+pub async fn share_room(
+    host_token: String,
+    client: ClientAddr,
+    config: &State<Config>,
+) -> Result<Json<ShareLink>, Error> {
+    let host_token = verify_host_token(&host_token, config, client)?;
+    Ok(Json(create_share_link(&host_token, config)?))
+}
+
+fn create_share_link(host_token: &HostToken, config: &Config) -> Result<ShareLink, Error> {
+    let signer = config
+        .share_signer()
+        .ok_or(Error::Config("Room sharing not configured"))?;
+    let token = ShareToken::for_host(host_token, config.share_link_ttl());
+    signer.share_link(&token, config.external_url())
+}
+
+/// Credentials of the guests in a room, for holders of a share link, in the
+/// language they request
+#[get("/shared_credentials?<filter..>")]
+pub async fn shared_credentials(
+    share_token: ShareToken,
+    filter: CredentialFilter,
+    render_type: RenderType,
+    translations: Translations,
+    config: &State<Config>,
+    db: SessionDBConn,
+) -> Result<RenderedContent, Error> {
+    let room = get_room_credentials(
+        share_token.room_id,
+        share_token.instance,
+        &filter,
+        config,
+        &db,
+    )
+    .await?;
+    render_room_credentials(room, render_type, &translations, config)
+}
+
+/// Upper bound on the size of a delivered auth result
+const MAX_AUTH_RESULT_LENGTH: usize = 64 * 1024;
+
+/// Register an auth result delivered by the core for the session with the
+/// given attr_id, after checking it is a compact JWE. Fails with
+/// [`Error::SessionNotFound`] if there is no such session, or if it already has one.
+pub async fn deliver_attributes(
+    attr_id: String,
+    auth_result: String,
+    db: &SessionDBConn,
+) -> Result<(), Error> {
+    let auth_result = auth_result.trim().to_string();
+    let is_compact_jwe = auth_result.split('.').count() == 5
+        && auth_result
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if auth_result.len() > MAX_AUTH_RESULT_LENGTH || !is_compact_jwe {
+        return Err(Error::BadRequest("Auth result is not a compact JWE"));
+    }
+    Session::register_auth_result(attr_id, auth_result, db).await
+}
+
+/// Called by the core to deliver the attributes of a guest. If a callback
+/// secret is configured, only URLs signed with it are accepted.
+#[post("/auth_result/<attr_id>", data = "<auth_result>")]
+pub async fn auth_result(
+    attr_id: String,
+    auth_result: String,
+    signed: Result<SignedCallback, Error>,
+    request_id: RequestId,
+    client: ClientAddr,
+    config: &State<Config>,
+    db: SessionDBConn,
+) -> Result<(), Error> {
+    receive_auth_result(
+        attr_id,
+        auth_result,
+        signed,
+        request_id,
+        client,
+        config,
+        &db,
+    )
+    .await
+}
+
+async fn receive_auth_result(
+    attr_id: String,
+    auth_result: String,
+    signed: Result<SignedCallback, Error>,
+    request_id: RequestId,
+    client: ClientAddr,
+    config: &Config,
+    db: &SessionDBConn,
+) -> Result<(), Error> {
+    if config.callback_signer().is_some() {
+        if let Err(e) = signed {
+            log::warn!(target: "audit", "Request {} from {}: rejected unsigned auth result delivery", request_id, client);
+            return Err(e);
+        }
+    }
+
+    let result = deliver_attributes(attr_id, auth_result, db).await;
+    match &result {
+        Ok(()) => {
+            log::info!(target: "audit", "Request {} from {}: auth result registered", request_id, client)
+        }
+        Err(e) => {
+            log::warn!(target: "audit", "Request {} from {}: auth result rejected: {}", request_id, client, e)
+        }
+    }
+    result
+}
+
+/// Session database and usage metrics in the Prometheus text format.
+/// Requires API authentication.
+#[get("/metrics")]
+pub fn metrics(token: Result<ApiToken, Error>) -> Result<String, Error> {
+    token?;
+    Ok(render_metrics())
+}
+
+fn render_metrics() -> String {
+    render_prometheus() + &crate::stats::render_prometheus()
+}
+
+/// Days of usage statistics returned when not specified
+const DEFAULT_STATS_DAYS: u32 = 31;
+
+/// Usage statistics per day, for the last 31 days unless specified otherwise.
+/// Requires API authentication.
+#[get("/stats?<days>")]
+pub async fn stats(
+    token: Result<ApiToken, Error>,
+    days: Option<u32>,
+    db: SessionDBConn,
+) -> Result<Json<Vec<DayStats>>, Error> {
+    token?;
+    Ok(Json(stats_for(days, &db).await?))
+}
+
+async fn stats_for(days: Option<u32>, db: &SessionDBConn) -> Result<Vec<DayStats>, Error> {
+    usage_stats(days.unwrap_or(DEFAULT_STATS_DAYS).min(366), db).await
+}
+
+/// Reload the keys from the configured key file after they were rotated.
+/// Requires API authentication.
+#[post("/admin/reload_keys")]
+pub fn reload_keys(
+    token: Result<ApiToken, Error>,
+    client: ClientAddr,
+    config: &State<Config>,
+) -> Result<(), Error> {
+    let token = token?;
+    log::info!(target: "audit", "Key reload requested by {:?} from {}", token, client);
+    config.keys().reload()
+}
+
+/// State of the deployment: templates, translations, the session
+/// database, keys, the core and compiled features. Requires API authentication.
+#[get("/diagnostics")]
+pub async fn diagnostics(
+    token: Result<ApiToken, Error>,
+    client: ClientAddr,
+    config: &State<Config>,
+    db: Option<SessionDBConn>,
+) -> Result<Json<Diagnostics>, Error> {
+    let token = token?;
+    log::info!(target: "audit", "Diagnostics requested by {:?} from {}", token, client);
+    Ok(Json(
+        crate::diagnostics::diagnostics(config, db.as_ref()).await,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{
+        sign_expired_host_token, sign_host_token, sign_platform_token, test_config,
+        test_host_token, test_raw_config, GUEST_SECRET, HOST_SECRET,
+    };
+    use rocket::{
+        http::{Header, Status},
+        local::blocking::Client,
+    };
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn client(ip: &str) -> ClientAddr {
+        ClientAddr {
+            ip: Some(ip.parse().unwrap()),
+            scheme: None,
+        }
+    }
+
+    #[test]
+    fn test_inspect_host_token() {
+        let config = test_config();
+        let token = test_host_token("room");
+        let info =
+            inspect_host_token(&sign_host_token(&token), &config, ClientAddr::default()).unwrap();
+        assert_eq!(
+            info,
+            HostTokenInfo {
+                id: token.id.clone(),
+                room_id: "room".to_string(),
+                instance: "test".to_string(),
+                issued_at: None,
+                expires_at: None,
+            }
+        );
+
+        // Times come from the same verified JWT as the other claims
+        let mut payload = josekit::jwt::JwtPayload::new();
+        payload
+            .set_claim("payload", Some(serde_json::to_value(&token).unwrap()))
+            .unwrap();
+        payload.set_issued_at(&(UNIX_EPOCH + Duration::from_secs(1_600_000_000)));
+        payload.set_expires_at(&(UNIX_EPOCH + Duration::from_secs(4_000_000_000)));
+        let signer = josekit::jws::HS256.signer_from_bytes(HOST_SECRET).unwrap();
+        let timed =
+            josekit::jwt::encode_with_signer(&payload, &josekit::jws::JwsHeader::new(), &signer)
+                .unwrap();
+        let info = inspect_host_token(&timed, &config, ClientAddr::default()).unwrap();
+        assert_eq!(info.issued_at, Some(1_600_000_000));
+        assert_eq!(info.expires_at, Some(4_000_000_000));
+
+        let wrongly_signed = sign_platform_token(&token, GUEST_SECRET);
+        assert!(inspect_host_token(&wrongly_signed, &config, ClientAddr::default()).is_err());
+        assert!(inspect_host_token("not-a-jwt", &config, ClientAddr::default()).is_err());
+    }
+
+    #[test]
+    fn test_metrics_require_api_token() {
+        let mut raw_config = test_raw_config();
+        let api_auth: serde_yaml::Value = serde_yaml::from_str("api_keys: [key1]").unwrap();
+        raw_config.insert("api_auth".into(), api_auth);
+        let config: Config =
+            serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+        let rocket = rocket::build()
+            .manage(config)
+            .mount("/", rocket::routes![metrics]);
+        let client = Client::tracked(rocket).unwrap();
+
+        let response = client.get("/metrics").dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .get("/metrics")
+            .header(Header::new("Authorization", "Bearer key1"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[test]
+    fn test_host_token_lockout() {
+        let config = test_config();
+        let token = test_host_token("lockout-room");
+        let valid = sign_host_token(&token);
+
+        let attacker = client("192.0.2.10");
+        for _ in 0..10 {
+            assert!(matches!(
+                verify_host_token("not-a-jwt", &config, attacker),
+                Err(Error::Jwe(_))
+            ));
+        }
+        assert!(matches!(
+            verify_host_token("not-a-jwt", &config, attacker),
+            Err(Error::TooManyRequests(_))
+        ));
+        // Valid tokens are refused too while the address is locked out
+        let error = verify_host_token(&valid, &config, attacker).unwrap_err();
+        assert!(matches!(error, Error::TooManyRequests(_)));
+        assert_eq!(error.status_code(), 429);
+
+        // Without a known address nobody is locked out, so hosts cannot be
+        // locked out of their room by others sending bad tokens
+        for _ in 0..20 {
+            assert!(matches!(
+                verify_host_token("not-a-jwt", &config, ClientAddr::default()),
+                Err(Error::Jwe(_))
+            ));
+        }
+        assert!(verify_host_token(&valid, &config, ClientAddr::default()).is_ok());
+
+        // Expired tokens are rejected without counting as failures
+        let expired = sign_expired_host_token(&token);
+        let host = client("192.0.2.11");
+        for _ in 0..20 {
+            assert!(matches!(
+                verify_host_token(&expired, &config, host),
+                Err(Error::Jwe(JwtError::Expired))
+            ));
+        }
+        assert!(verify_host_token("not-a-jwt", &config, host).is_err());
+        assert!(!matches!(
+            verify_host_token("not-a-jwt", &config, host),
+            Err(Error::TooManyRequests(_))
+        ));
+    }
+}
@@ -0,0 +1,327 @@
+use super::{
+    create_share_link, inspect_host_token, receive_auth_result, stats_for, status_of_guest,
+    verify_guest_token, verify_host_token, wait_for_room_change, HostTokenInfo,
+    MAX_AUTH_RESULT_LENGTH,
+};
+use crate::{
+    api_token::ApiToken,
+    callback::SignedCallback,
+    config::Config,
+    credentials::{
+        get_filtered_encrypted_credentials_for_host, get_room_credentials, render_room_credentials,
+        CredentialFilter, RenderType, RenderedContent,
+    },
+    diagnostics::Diagnostics,
+    error::Error,
+    proxy::ClientAddr,
+    request_id::RequestId,
+    session::{GuestStatus, RoomSummary, Session, SessionDBConn, SessionDBPool},
+    share::{ShareLink, ShareToken},
+    stats::DayStats,
+    translations::Translations,
+    types::platform_token::HostToken,
+};
+use axum::{
+    extract::{ContentLengthLimit, Extension, FromRequest, Path, Query, RequestParts},
+    http::{header, HeaderMap},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use std::{collections::HashMap, sync::Arc};
+
+/// Router with the same routes as [`routes`](super::routes), for plugins
+/// built on axum. Routes using the session database need a
+/// [`SessionDB`](crate::session::SessionDB) extension on the router. Serve it
+/// with `into_make_service_with_connect_info::<SocketAddr>()`, so clients
+/// presenting invalid host tokens can be locked out.
+pub fn router(config: Arc<Config>) -> Router {
+    Router::new()
+        .route("/room_summary/:host_token", get(room_summary))
+        .route("/host_token_info/:host_token", get(host_token_info))
+        .route("/credentials_version/:host_token", get(credentials_version))
+        .route("/credentials/:host_token/wait", get(wait_for_credentials))
+        .route("/guest_left/:guest_token", post(guest_left))
+        .route("/guest_status/:guest_token", get(guest_status))
+        .route("/close_room/:host_token", post(close_room))
+        .route(
+            "/encrypted_credentials/:host_token",
+            get(encrypted_credentials),
+        )
+        .route("/share/:host_token", post(share_room))
+        .route("/shared_credentials", get(shared_credentials))
+        .route("/auth_result/:attr_id", post(auth_result))
+        .route("/metrics", get(metrics))
+        .route("/stats", get(stats))
+        .route("/admin/reload_keys", post(reload_keys))
+        .route("/diagnostics", get(diagnostics))
+        .layer(Extension(config.trusted_proxies().clone()))
+        .layer(Extension(config))
+}
+
+/// Extracts the host token in the `host_token` path parameter in axum
+/// handlers, verified as on the host routes
+#[axum::async_trait]
+impl<B: Send> FromRequest<B> for HostToken {
+    type Rejection = Error;
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let config = request
+            .extensions()
+            .get::<Arc<Config>>()
+            .cloned()
+            .ok_or(Error::Config("Configuration not added to the router"))?;
+        let host_token = Path::<HashMap<String, String>>::from_request(request)
+            .await
+            .ok()
+            .and_then(|Path(mut params)| params.remove("host_token"))
+            .ok_or(Error::NotFound)?;
+        let client = ClientAddr::from_request(request).await.unwrap_or_default();
+        verify_host_token(&host_token, &config, client)
+    }
+}
+
+#[derive(Deserialize)]
+struct WaitParams {
+    since: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StatsParams {
+    days: Option<u32>,
+}
+
+async fn room_summary(
+    host_token: HostToken,
+    db: SessionDBConn,
+) -> Result<Json<RoomSummary>, Error> {
+    Ok(Json(
+        Session::summary_by_room(host_token.room_id, host_token.instance, &db).await?,
+    ))
+}
+
+async fn host_token_info(
+    Path(host_token): Path<String>,
+    client: ClientAddr,
+    Extension(config): Extension<Arc<Config>>,
+) -> Result<Json<HostTokenInfo>, Error> {
+    Ok(Json(inspect_host_token(&host_token, &config, client)?))
+}
+
+async fn credentials_version(
+    host_token: HostToken,
+    db: SessionDBConn,
+) -> Result<Json<serde_json::Value>, Error> {
+    let version =
+        Session::credentials_version(host_token.room_id, host_token.instance, &db).await?;
+    Ok(Json(serde_json::json!({ "version": version })))
+}
+
+async fn wait_for_credentials(
+    host_token: HostToken,
+    Query(params): Query<WaitParams>,
+    Extension(config): Extension<Arc<Config>>,
+    db: SessionDBPool<'static>,
+) -> Result<Json<serde_json::Value>, Error> {
+    Ok(Json(
+        wait_for_room_change(host_token, params.since, &config, &db).await?,
+    ))
+}
+
+async fn guest_left(
+    Path(guest_token): Path<String>,
+    Extension(config): Extension<Arc<Config>>,
+    db: SessionDBConn,
+) -> Result<(), Error> {
+    let guest_token = verify_guest_token(&guest_token, &config)?;
+    Session::delete(guest_token.id, guest_token.instance, &db).await
+}
+
+async fn guest_status(
+    Path(guest_token): Path<String>,
+    Extension(config): Extension<Arc<Config>>,
+    db: SessionDBConn,
+) -> Result<Json<GuestStatus>, Error> {
+    Ok(Json(status_of_guest(&guest_token, &config, &db).await?))
+}
+
+async fn close_room(host_token: HostToken, db: SessionDBConn) -> Result<(), Error> {
+    Session::close_room(host_token.room_id, host_token.instance, &db).await?;
+    Ok(())
+}
+
+async fn encrypted_credentials(
+    Path(host_token): Path<String>,
+    filter: CredentialFilter,
+    client: ClientAddr,
+    Extension(config): Extension<Arc<Config>>,
+    db: SessionDBConn,
+) -> Result<String, Error> {
+    get_filtered_encrypted_credentials_for_host(host_token, client, &filter, &config, db).await
+}
+
+async fn share_room(
+    host_token: HostToken,
+    Extension(config): Extension<Arc<Config>>,
+) -> Result<Json<ShareLink>, Error> {
+    Ok(Json(create_share_link(&host_token, &config)?))
+}
+
+async fn shared_credentials(
+    share_token: ShareToken,
+    filter: CredentialFilter,
+    render_type: RenderType,
+    translations: Translations,
+    headers: HeaderMap,
+    Extension(config): Extension<Arc<Config>>,
+    db: SessionDBConn,
+) -> Result<RenderedContent, Error> {
+    let room = get_room_credentials(
+        share_token.room_id,
+        share_token.instance,
+        &filter,
+        &config,
+        &db,
+    )
+    .await?;
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    Ok(
+        render_room_credentials(room, render_type, &translations, &config)?
+            .for_conditional_request(
+                header(header::IF_NONE_MATCH),
+                header(header::IF_MODIFIED_SINCE),
+            ),
+    )
+}
+
+async fn auth_result(
+    Path(attr_id): Path<String>,
+    signed: Result<SignedCallback, Error>,
+    request_id: RequestId,
+    client: ClientAddr,
+    Extension(config): Extension<Arc<Config>>,
+    db: SessionDBConn,
+    ContentLengthLimit(auth_result): ContentLengthLimit<String, { MAX_AUTH_RESULT_LENGTH as u64 }>,
+) -> Result<(), Error> {
+    receive_auth_result(
+        attr_id,
+        auth_result,
+        signed,
+        request_id,
+        client,
+        &config,
+        &db,
+    )
+    .await
+}
+
+async fn metrics(_token: ApiToken) -> String {
+    super::render_metrics()
+}
+
+async fn stats(
+    _token: ApiToken,
+    Query(params): Query<StatsParams>,
+    db: SessionDBConn,
+) -> Result<Json<Vec<DayStats>>, Error> {
+    Ok(Json(stats_for(params.days, &db).await?))
+}
+
+async fn reload_keys(
+    token: ApiToken,
+    client: ClientAddr,
+    Extension(config): Extension<Arc<Config>>,
+) -> Result<(), Error> {
+    log::info!(target: "audit", "Key reload requested by {:?} from {}", token, client);
+    config.keys().reload()
+}
+
+async fn diagnostics(
+    token: ApiToken,
+    client: ClientAddr,
+    Extension(config): Extension<Arc<Config>>,
+    db: Option<SessionDBConn>,
+) -> Json<Diagnostics> {
+    log::info!(target: "audit", "Diagnostics requested by {:?} from {}", token, client);
+    Json(crate::diagnostics::diagnostics(&config, db.as_ref()).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{
+        serve_router, sign_host_token, test_config, test_host_token, test_raw_config,
+    };
+
+    #[test]
+    fn test_host_token_info() {
+        let router = router(Arc::new(test_config()));
+        let host_token = test_host_token("axum_room");
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let url = serve_router(router);
+            let client = reqwest::Client::new();
+
+            let info: serde_json::Value = client
+                .get(format!(
+                    "{}/host_token_info/{}",
+                    url,
+                    sign_host_token(&host_token)
+                ))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(info["room_id"], "axum_room");
+
+            let response = client
+                .get(format!("{}/host_token_info/invalid", url))
+                .send()
+                .await
+                .unwrap();
+            assert!(response.status().is_client_error());
+
+            // Without a session database, routes needing it fail cleanly
+            let response = client
+                .get(format!(
+                    "{}/room_summary/{}",
+                    url,
+                    sign_host_token(&host_token)
+                ))
+                .send()
+                .await
+                .unwrap();
+            assert!(response.status().is_server_error());
+        });
+    }
+
+    #[test]
+    fn test_metrics_require_api_token() {
+        let mut raw_config = test_raw_config();
+        let api_auth: serde_yaml::Value = serde_yaml::from_str("api_keys: [key1]").unwrap();
+        raw_config.insert("api_auth".into(), api_auth);
+        let config: Config =
+            serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+        let router = router(Arc::new(config));
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let url = format!("{}/metrics", serve_router(router));
+            let client = reqwest::Client::new();
+
+            let response = client.get(&url).send().await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+            let response = client.get(&url).bearer_auth("key1").send().await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::OK);
+        });
+    }
+}
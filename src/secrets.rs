@@ -0,0 +1,306 @@
+use crate::error::Error;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, RwLock},
+};
+
+/// Secret fetched by a [`SecretSource`]
+pub type SecretFuture<'a> = Pin<Box<dyn Future<Output = Result<String, Error>> + Send + 'a>>;
+
+/// Store secrets can be fetched from while loading the configuration, such
+/// as a vault. Configuration values of the form `<scheme>:<reference>`, for
+/// the scheme the source is registered under, are replaced by the secret.
+pub trait SecretSource: Send + Sync {
+    /// Fetch the secret `reference` refers to, the part of the configuration
+    /// value after the scheme
+    fn fetch<'a>(&'a self, reference: &'a str) -> SecretFuture<'a>;
+}
+
+lazy_static! {
+    static ref SOURCES: RwLock<HashMap<String, Arc<dyn SecretSource>>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Register a source for configuration values starting with `<scheme>:`,
+/// such as `vault:secret/data/plugin#guest_signature_secret`
+pub fn register_secret_source(scheme: &str, source: impl SecretSource + 'static) {
+    SOURCES
+        .write()
+        .expect("Secret sources lock poisoned")
+        .insert(scheme.to_string(), Arc::new(source));
+}
+
+/// Source and reference of a configuration value referring to a secret
+fn source_for(value: &str) -> Option<(Arc<dyn SecretSource>, &str)> {
+    let (scheme, reference) = value.split_once(':')?;
+    let sources = SOURCES.read().expect("Secret sources lock poisoned");
+    Some((sources.get(scheme)?.clone(), reference))
+}
+
+fn collect_strings<'v>(value: &'v mut Value, strings: &mut Vec<&'v mut String>) {
+    match value {
+        Value::String(string) => strings.push(string),
+        Value::Array(values) => values
+            .iter_mut()
+            .for_each(|value| collect_strings(value, strings)),
+        Value::Object(values) => values
+            .values_mut()
+            .for_each(|value| collect_strings(value, strings)),
+        _ => {}
+    }
+}
+
+/// Replace all values in a raw configuration that refer to a secret in one
+/// of the registered sources by that secret
+pub async fn resolve_secrets(config: &mut Value) -> Result<(), Error> {
+    let mut strings = vec![];
+    collect_strings(config, &mut strings);
+
+    for string in strings {
+        if let Some((source, reference)) = source_for(string) {
+            let secret = source.fetch(reference).await.map_err(|e| {
+                log::error!("Could not fetch secret {}: {}", string, e);
+                e
+            })?;
+            *string = secret;
+        }
+    }
+    Ok(())
+}
+
+/// Load the configuration from a figment, such as the one of a Rocket
+/// instance, after fetching the secrets it refers to. Call
+/// [`Config::install`](crate::config::Config::install) on the result to
+/// apply its process-wide settings.
+#[cfg(feature = "rocket")]
+pub async fn load_config(
+    figment: &rocket::figment::Figment,
+) -> Result<crate::config::Config, Error> {
+    let mut config: Value = figment.extract().map_err(|e| {
+        log::error!("Could not read configuration: {}", e);
+        Error::Config("Invalid configuration")
+    })?;
+    resolve_secrets(&mut config).await?;
+    Ok(serde_json::from_value(config)?)
+}
+
+/// Split a `<path>#<field>` reference
+#[cfg(any(feature = "vault_secrets", feature = "aws_secrets"))]
+fn split_field(reference: &str) -> (&str, Option<&str>) {
+    match reference.split_once('#') {
+        Some((path, field)) => (path, Some(field)),
+        None => (reference, None),
+    }
+}
+
+#[cfg(feature = "vault_secrets")]
+fn env(name: &'static str) -> Result<String, Error> {
+    std::env::var(name).map_err(|_| {
+        log::error!("Environment variable {} not set", name);
+        Error::Config("Secret source not configured")
+    })
+}
+
+#[cfg(feature = "vault_secrets")]
+mod vault {
+    use super::{env, split_field, SecretFuture, SecretSource};
+    use crate::error::Error;
+    use serde_json::Value;
+
+    /// Secrets in a HashiCorp Vault KV engine, referred to as `<path>#<field>`,
+    /// e.g. `vault:secret/data/plugin#guest_signature_secret`
+    pub struct VaultSource {
+        address: String,
+        token: String,
+        client: reqwest::Client,
+    }
+
+    impl VaultSource {
+        pub fn new(address: String, token: String) -> Self {
+            VaultSource {
+                address,
+                token,
+                client: reqwest::Client::new(),
+            }
+        }
+
+        /// Source for the Vault at `VAULT_ADDR`, authenticated with `VAULT_TOKEN`
+        pub fn from_env() -> Result<Self, Error> {
+            Ok(VaultSource::new(env("VAULT_ADDR")?, env("VAULT_TOKEN")?))
+        }
+
+        async fn read(&self, reference: &str) -> Result<String, Error> {
+            let (path, field) = split_field(reference);
+            let field = field.ok_or(Error::Config("Vault secret without field"))?;
+            let url = format!(
+                "{}/v1/{}",
+                self.address.trim_end_matches('/'),
+                path.trim_start_matches('/')
+            );
+            let response: Value = self
+                .client
+                .get(url)
+                .header("X-Vault-Token", &self.token)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            // Version 2 of the KV engine nests the secret in another data object
+            let data = match &response["data"]["data"] {
+                Value::Object(_) => &response["data"]["data"],
+                _ => &response["data"],
+            };
+            data[field]
+                .as_str()
+                .map(String::from)
+                .ok_or(Error::Config("Vault secret field not found"))
+        }
+    }
+
+    impl SecretSource for VaultSource {
+        fn fetch<'a>(&'a self, reference: &'a str) -> SecretFuture<'a> {
+            Box::pin(self.read(reference))
+        }
+    }
+}
+
+#[cfg(feature = "vault_secrets")]
+pub use vault::VaultSource;
+
+#[cfg(feature = "aws_secrets")]
+mod aws {
+    use super::{split_field, SecretFuture, SecretSource};
+    use crate::{aws::AwsCredentials, error::Error};
+    use serde_json::{json, Value};
+
+    const TARGET: &str = "secretsmanager.GetSecretValue";
+    const CONTENT_TYPE: &str = "application/x-amz-json-1.1";
+
+    /// Secrets in AWS Secrets Manager, referred to by their id or ARN. For
+    /// secrets holding JSON, a key can be selected with `<id>#<key>`.
+    pub struct AwsSecretsSource {
+        credentials: AwsCredentials,
+        client: reqwest::Client,
+    }
+
+    impl AwsSecretsSource {
+        pub fn new(
+            region: String,
+            access_key_id: String,
+            secret_access_key: String,
+            session_token: Option<String>,
+        ) -> Self {
+            AwsSecretsSource {
+                credentials: AwsCredentials::new(
+                    region,
+                    access_key_id,
+                    secret_access_key,
+                    session_token,
+                ),
+                client: reqwest::Client::new(),
+            }
+        }
+
+        /// Source using the region and credentials in the standard
+        /// `AWS_REGION`, `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and
+        /// `AWS_SESSION_TOKEN` environment variables
+        pub fn from_env() -> Result<Self, Error> {
+            Ok(AwsSecretsSource {
+                credentials: AwsCredentials::from_env()?,
+                client: reqwest::Client::new(),
+            })
+        }
+
+        async fn read(&self, reference: &str) -> Result<String, Error> {
+            let (secret_id, key) = split_field(reference);
+            let host = format!("secretsmanager.{}.amazonaws.com", self.credentials.region);
+            let body = json!({ "SecretId": secret_id }).to_string();
+
+            let headers = self.credentials.sign(
+                "POST",
+                "secretsmanager",
+                &host,
+                "/",
+                vec![
+                    ("content-type", CONTENT_TYPE.to_string()),
+                    ("x-amz-target", TARGET.to_string()),
+                ],
+                body.as_bytes(),
+            );
+            let mut request = self.client.post(format!("https://{}/", host)).body(body);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            let response: Value = request.send().await?.error_for_status()?.json().await?;
+
+            let secret = response["SecretString"]
+                .as_str()
+                .ok_or(Error::Config("AWS secret is not a string"))?;
+            match key {
+                Some(key) => serde_json::from_str::<Value>(secret)?[key]
+                    .as_str()
+                    .map(String::from)
+                    .ok_or(Error::Config("AWS secret key not found")),
+                None => Ok(secret.to_string()),
+            }
+        }
+    }
+
+    impl SecretSource for AwsSecretsSource {
+        fn fetch<'a>(&'a self, reference: &'a str) -> SecretFuture<'a> {
+            Box::pin(self.read(reference))
+        }
+    }
+}
+
+#[cfg(feature = "aws_secrets")]
+pub use aws::AwsSecretsSource;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StaticSource;
+
+    impl SecretSource for StaticSource {
+        fn fetch<'a>(&'a self, reference: &'a str) -> SecretFuture<'a> {
+            Box::pin(async move {
+                match reference {
+                    "guest" => Ok("guest secret".to_string()),
+                    _ => Err(Error::NotFound),
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn test_resolve_secrets() {
+        register_secret_source("test-static", StaticSource);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+
+        let mut config = serde_json::json!({
+            "guest_signature_secret": "test-static:guest",
+            "instances": {"tenant": {"display_name": "unknown:guest"}},
+            "keys": ["test-static:guest", 3],
+        });
+        runtime.block_on(resolve_secrets(&mut config)).unwrap();
+        assert_eq!(
+            config,
+            serde_json::json!({
+                "guest_signature_secret": "guest secret",
+                "instances": {"tenant": {"display_name": "unknown:guest"}},
+                "keys": ["guest secret", 3],
+            })
+        );
+
+        let mut config = serde_json::json!({"host_signature_secret": "test-static:host"});
+        assert!(runtime.block_on(resolve_secrets(&mut config)).is_err());
+    }
+}
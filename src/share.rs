@@ -0,0 +1,273 @@
+use crate::{error::Error, types::platform_token::HostToken, util::join_url};
+use josekit::{
+    jws::{JwsHeader, HS256},
+    jwt::JwtPayload,
+};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    convert::TryFrom,
+    fmt::Debug,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use zeroize::Zeroizing;
+
+const SHARE_SUBJECT: &str = "id-contact-room-share";
+
+/// Scope of share tokens: reading the credentials of a single room
+pub const SHARE_SCOPE: &str = "credentials:read";
+
+/// Signs and verifies share tokens, which give read-only access to the
+/// credentials of a room, e.g. for a colleague joining the call late.
+/// Unlike host tokens they cannot be used to close the room or remove guests.
+#[derive(Deserialize, Clone)]
+#[serde(try_from = "String")]
+pub struct ShareSigner {
+    secret: Zeroizing<Vec<u8>>,
+}
+
+/// Minimum length of the share secret, the output size of SHA-256
+const MIN_SECRET_LENGTH: usize = 32;
+
+impl TryFrom<String> for ShareSigner {
+    type Error = String;
+    fn try_from(secret: String) -> Result<Self, String> {
+        if secret.len() < MIN_SECRET_LENGTH {
+            return Err(format!(
+                "Share secret must be at least {} bytes",
+                MIN_SECRET_LENGTH
+            ));
+        }
+        Ok(ShareSigner {
+            secret: Zeroizing::new(secret.into_bytes()),
+        })
+    }
+}
+
+impl Debug for ShareSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShareSigner").finish()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ShareClaims {
+    sub: String,
+    scope: String,
+    room_id: String,
+    instance: String,
+    iat: u64,
+    exp: u64,
+}
+
+/// Verified share token, granting read-only access to the credentials of a room
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShareToken {
+    pub room_id: String,
+    pub instance: String,
+    /// Seconds since the epoch after which the token is no longer accepted
+    pub expires_at: u64,
+}
+
+/// Link to the credentials of a room, as handed to the host
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ShareLink {
+    pub url: String,
+    pub expires_at: u64,
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn invalid(_: impl Debug) -> Error {
+    Error::Forbidden("Invalid share token")
+}
+
+impl ShareToken {
+    /// Token for the room of the given host, valid for the given duration
+    pub fn for_host(host_token: &HostToken, valid_for: Duration) -> Self {
+        ShareToken {
+            room_id: host_token.room_id.clone(),
+            instance: host_token.instance.clone(),
+            expires_at: unix_time(SystemTime::now() + valid_for),
+        }
+    }
+}
+
+impl ShareSigner {
+    /// Sign a share token as a JWS, to be put in a share link
+    pub fn sign(&self, token: &ShareToken) -> Result<String, Error> {
+        self.encode(&ShareClaims {
+            sub: SHARE_SUBJECT.to_string(),
+            scope: SHARE_SCOPE.to_string(),
+            room_id: token.room_id.clone(),
+            instance: token.instance.clone(),
+            iat: unix_time(SystemTime::now()),
+            exp: token.expires_at,
+        })
+    }
+
+    /// Signed link to the shared credentials route below `base_url`
+    pub fn share_link(&self, token: &ShareToken, base_url: &str) -> Result<ShareLink, Error> {
+        let mut url = Url::parse(&join_url(base_url, "/shared_credentials")?)
+            .map_err(|_| Error::Config("Invalid share URL"))?;
+        url.query_pairs_mut()
+            .append_pair("share_token", &self.sign(token)?);
+        Ok(ShareLink {
+            url: url.to_string(),
+            expires_at: token.expires_at,
+        })
+    }
+
+    fn encode(&self, claims: &ShareClaims) -> Result<String, Error> {
+        let mut header = JwsHeader::new();
+        header.set_token_type("JWT");
+        let claims = match serde_json::to_value(claims)? {
+            Value::Object(claims) => claims,
+            _ => return Err(Error::Config("Invalid share claims")),
+        };
+        let signer = HS256
+            .signer_from_bytes(&self.secret)
+            .map_err(|_| Error::Config("Invalid share secret"))?;
+        JwtPayload::from_map(claims)
+            .and_then(|payload| josekit::jwt::encode_with_signer(&payload, &header, &signer))
+            .map_err(|_| Error::Config("Could not sign share token"))
+    }
+
+    /// Check the signature, scope and expiry of a share token
+    pub fn verify(&self, jws: &str) -> Result<ShareToken, Error> {
+        let verifier = HS256.verifier_from_bytes(&self.secret).map_err(invalid)?;
+        let (payload, _) = josekit::jwt::decode_with_verifier(jws, &verifier).map_err(invalid)?;
+        let claims: ShareClaims =
+            serde_json::from_value(Value::Object(payload.claims_set().clone())).map_err(invalid)?;
+
+        if claims.sub != SHARE_SUBJECT || claims.scope != SHARE_SCOPE {
+            return Err(Error::Forbidden("Invalid share token"));
+        }
+        if claims.exp < unix_time(SystemTime::now()) {
+            return Err(Error::Forbidden("Share token expired"));
+        }
+        Ok(ShareToken {
+            room_id: claims.room_id,
+            instance: claims.instance,
+            expires_at: claims.exp,
+        })
+    }
+}
+
+#[cfg(feature = "rocket")]
+mod guard {
+    use super::ShareToken;
+    use crate::{config::Config, error::Error};
+    use rocket::{
+        http::Status,
+        request::{FromRequest, Outcome},
+        Request,
+    };
+
+    /// Request guard accepting requests with a valid share token in the
+    /// `share_token` query parameter
+    #[rocket::async_trait]
+    impl<'r> FromRequest<'r> for ShareToken {
+        type Error = Error;
+
+        async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+            let signer = request
+                .rocket()
+                .state::<Config>()
+                .and_then(Config::share_signer);
+            let token = request
+                .query_value::<&str>("share_token")
+                .and_then(Result::ok);
+            let result = match (signer, token) {
+                (Some(signer), Some(token)) => signer.verify(token),
+                (None, _) => Err(Error::Forbidden("Room sharing not configured")),
+                (_, None) => Err(Error::Forbidden("Missing share token")),
+            };
+
+            match result {
+                Ok(token) => Outcome::Success(token),
+                Err(e) => Outcome::Failure((Status::Forbidden, e)),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "axum")]
+mod extractor {
+    use super::ShareToken;
+    use crate::{config::Config, error::Error, util::query_pairs};
+    use axum::extract::{FromRequest, RequestParts};
+    use std::sync::Arc;
+
+    /// Extracts a valid share token from the `share_token` query parameter
+    #[axum::async_trait]
+    impl<B: Send> FromRequest<B> for ShareToken {
+        type Rejection = Error;
+
+        async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+            let config = request.extensions().get::<Arc<Config>>().cloned();
+            let signer = config.as_deref().and_then(Config::share_signer);
+            let token = query_pairs(request)
+                .await
+                .into_iter()
+                .find(|(key, _)| key == "share_token")
+                .map(|(_, token)| token);
+            match (signer, token) {
+                (Some(signer), Some(token)) => signer.verify(&token),
+                (None, _) => Err(Error::Forbidden("Room sharing not configured")),
+                (_, None) => Err(Error::Forbidden("Missing share token")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn test_share_token() {
+        assert!(ShareSigner::try_from("short".to_string()).is_err());
+        let signer = ShareSigner::try_from(SECRET.to_string()).unwrap();
+        assert!(!format!("{:?}", signer).contains(SECRET));
+
+        let host_token = HostToken::new("host", "room", "instance");
+        let token = ShareToken::for_host(&host_token, Duration::from_secs(60));
+        let jws = signer.sign(&token).unwrap();
+        assert_eq!(signer.verify(&jws).unwrap(), token);
+
+        let link = signer
+            .share_link(&token, "https://comm.example.com/base")
+            .unwrap();
+        assert!(link
+            .url
+            .starts_with("https://comm.example.com/base/shared_credentials?share_token="));
+        assert_eq!(link.expires_at, token.expires_at);
+
+        let expired = ShareToken {
+            expires_at: 1,
+            ..token.clone()
+        };
+        assert!(signer.verify(&signer.sign(&expired).unwrap()).is_err());
+
+        let out_of_scope = signer
+            .encode(&ShareClaims {
+                sub: SHARE_SUBJECT.to_string(),
+                scope: "credentials:write".to_string(),
+                room_id: token.room_id.clone(),
+                instance: token.instance.clone(),
+                iat: 0,
+                exp: token.expires_at,
+            })
+            .unwrap();
+        assert!(signer.verify(&out_of_scope).is_err());
+        assert!(signer.verify("not-a-jws").is_err());
+    }
+}
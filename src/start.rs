@@ -0,0 +1,321 @@
+use crate::{
+    attr_id::{AttrIdGenerator, RandomAttrIds},
+    config::Config,
+    error::Error,
+    jwt::sign_start_auth_request,
+    request_id::{RequestId, WithRequestId},
+    retry::send_with_retry,
+    routes::verify_guest_token,
+    session::{Session, SessionDBConn},
+    stats::record_auth_method,
+    types::StartRequest,
+    util::join_url,
+};
+use id_contact_proto::{ClientUrlResponse, StartRequestAuthOnly};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Time the core has to deliver the attributes of a started session
+const ATTR_URL_VALIDITY: Duration = Duration::from_secs(60 * 60);
+
+/// Response to a guest starting a session
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StartResponse {
+    /// URL the guest continues authentication at
+    pub client_url: String,
+}
+
+/// Start an authentication session for a guest: validate their guest token
+/// and the start request, ask the core to start authentication with a fresh
+/// attr_id, delivering attributes to the plugin's `auth_result/<attr_id>`
+/// route, and persist a session once the core accepted. The request id is
+/// forwarded to the core, to correlate its logs with ours.
+pub async fn start_guest_session(
+    guest_jwt: &str,
+    start_request: StartRequest,
+    request_id: &RequestId,
+    config: &Config,
+    db: &SessionDBConn,
+) -> Result<StartResponse, Error> {
+    start_request.validate(config)?;
+    let guest_token = verify_guest_token(guest_jwt, config)?;
+    if guest_token.purpose != start_request.purpose {
+        return Err(Error::BadRequest("Purpose does not match the guest token"));
+    }
+
+    let attr_id = config.attr_id_generator().generate(&guest_token.id);
+    let auth_method = start_request.auth_method.clone();
+    // Only persisted once the core accepted the request, so a guest can retry
+    // after a failing core without running into an existing session
+    let response = start_at_core(
+        start_request,
+        guest_token.redirect_url.clone(),
+        &attr_id,
+        request_id,
+        config,
+    )
+    .await?;
+    Session::new(guest_token, attr_id).persist(db).await?;
+    record_auth_method(&auth_method);
+
+    Ok(response)
+}
+
+/// Start authentication again for a guest whose result went stale, as
+/// standing rooms may require after some time. The session keeps its id,
+/// and its old result is removed so hosts no longer see it.
+pub async fn renew_guest_session(
+    guest_jwt: &str,
+    start_request: StartRequest,
+    request_id: &RequestId,
+    config: &Config,
+    db: &SessionDBConn,
+) -> Result<StartResponse, Error> {
+    start_request.validate(config)?;
+    let guest_token = verify_guest_token(guest_jwt, config)?;
+    if guest_token.purpose != start_request.purpose {
+        return Err(Error::BadRequest("Purpose does not match the guest token"));
+    }
+
+    let session = Session::find_by_id(guest_token.id.clone(), db).await?;
+    if session.guest_token.room_id != guest_token.room_id
+        || session.guest_token.instance != guest_token.instance
+    {
+        return Err(Error::SessionNotFound);
+    }
+    if !session.is_stale(config.retention_config()) {
+        return Err(Error::BadRequest("Session does not need to be renewed"));
+    }
+
+    // Never derived: that would be the attr_id the stale result was delivered to
+    let attr_id = RandomAttrIds.generate(&guest_token.id);
+    let auth_method = start_request.auth_method.clone();
+    // The stale result is only removed once the core accepted the request, so
+    // a guest can retry after a failing core
+    let response = start_at_core(
+        start_request,
+        guest_token.redirect_url,
+        &attr_id,
+        request_id,
+        config,
+    )
+    .await?;
+    Session::restart_authentication(guest_token.id, attr_id, db).await?;
+    record_auth_method(&auth_method);
+
+    Ok(response)
+}
+
+/// Ask the core to start authentication, returning the URL the guest
+/// continues at
+async fn start_at_core(
+    start_request: StartRequest,
+    comm_url: String,
+    attr_id: &str,
+    request_id: &RequestId,
+    config: &Config,
+) -> Result<StartResponse, Error> {
+    let auth_during_comm_config = config.auth_during_comm_config();
+    let start_auth_request = sign_start_auth_request(
+        StartRequestAuthOnly {
+            purpose: start_request.purpose,
+            auth_method: start_request.auth_method,
+            comm_url,
+            attr_url: Some(attr_url(config, attr_id)?),
+        },
+        auth_during_comm_config.start_auth_key_id(),
+        auth_during_comm_config.start_auth_signer(),
+    )?;
+
+    let request = reqwest::Client::new()
+        .post(join_url(auth_during_comm_config.core_url(), "start")?)
+        .header(reqwest::header::CONTENT_TYPE, "application/jwt")
+        .request_id(request_id)
+        .body(start_auth_request);
+    let ClientUrlResponse { client_url } = send_with_retry(request, config.retry_config())
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(StartResponse { client_url })
+}
+
+/// URL the core delivers the attributes for `attr_id` to, signed if a
+/// callback secret is configured
+fn attr_url(config: &Config, attr_id: &str) -> Result<String, Error> {
+    let path = format!("auth_result/{}", attr_id);
+    match config.callback_signer() {
+        Some(signer) => signer.sign_url(config.internal_url(), &path, ATTR_URL_VALIDITY),
+        None => join_url(config.internal_url(), &path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{test_raw_config, MockServer};
+
+    #[test]
+    fn test_attr_url() {
+        let mut raw_config = test_raw_config();
+        raw_config.insert("internal_url".into(), "https://plugin.internal".into());
+        let config: Config =
+            serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config.clone())).unwrap();
+        assert_eq!(
+            attr_url(&config, "attr").unwrap(),
+            "https://plugin.internal/auth_result/attr"
+        );
+
+        raw_config.insert(
+            "callback_secret".into(),
+            "a callback secret of at least 32 bytes".into(),
+        );
+        let config: Config =
+            serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+        let url = attr_url(&config, "attr").unwrap();
+        assert!(url.starts_with("https://plugin.internal/auth_result/attr?expires="));
+        assert!(url.contains("&signature="));
+    }
+
+    #[test]
+    fn test_request_id_forwarded() {
+        let core = MockServer::start(vec![(
+            200,
+            r#"{"client_url":"https://core.example.com/continue"}"#.to_string(),
+        )]);
+        let mut raw_config = test_raw_config();
+        raw_config.insert("core_url".into(), core.url().into());
+        let config: Config =
+            serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+        let request_id = RequestId::generate();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let response = runtime
+            .block_on(start_at_core(
+                StartRequest::new("test_purpose", "irma"),
+                "https://comm.example.com/room".to_string(),
+                "attr",
+                &request_id,
+                &config,
+            ))
+            .unwrap();
+        assert_eq!(response.client_url, "https://core.example.com/continue");
+
+        let requests = core.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].headers.get("x-request-id").map(String::as_str),
+            Some(request_id.as_str())
+        );
+    }
+}
+
+#[cfg(all(test, feature = "test_db"))]
+mod db_tests {
+    use super::*;
+    use crate::test_helpers::{sign_guest_token, test_guest_token, test_raw_config, MockServer};
+
+    #[rocket::async_test]
+    async fn test_retry_after_core_failure() {
+        let db = crate::session::test_db().await;
+        let core = MockServer::start(vec![
+            (400, "{}".to_string()),
+            (
+                200,
+                r#"{"client_url":"https://core.example.com/continue"}"#.to_string(),
+            ),
+        ]);
+        let mut raw_config = test_raw_config();
+        raw_config.insert("core_url".into(), core.url().into());
+        let config: Config =
+            serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+
+        let guest_token = test_guest_token("start_room");
+        let guest_jwt = sign_guest_token(&guest_token);
+        let start_request = || StartRequest::new("test_purpose", "irma");
+        let request_id = RequestId::generate();
+
+        assert!(
+            start_guest_session(&guest_jwt, start_request(), &request_id, &config, &db)
+                .await
+                .is_err()
+        );
+        assert!(matches!(
+            Session::find_by_id(guest_token.id.clone(), &db).await,
+            Err(Error::SessionNotFound)
+        ));
+
+        let response = start_guest_session(&guest_jwt, start_request(), &request_id, &config, &db)
+            .await
+            .unwrap();
+        assert_eq!(response.client_url, "https://core.example.com/continue");
+        let requests = core.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|request| request
+            .headers
+            .get("x-request-id")
+            .map(String::as_str)
+            == Some(request_id.as_str())));
+        Session::find_by_id(guest_token.id, &db).await.unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn test_renew_after_core_failure() {
+        let db = crate::session::test_db().await;
+        let core = MockServer::start(vec![
+            (500, "{}".to_string()),
+            (
+                200,
+                r#"{"client_url":"https://core.example.com/continue"}"#.to_string(),
+            ),
+        ]);
+        let mut raw_config = test_raw_config();
+        raw_config.insert("core_url".into(), core.url().into());
+        raw_config.insert("retry".into(), serde_yaml::from_str("attempts: 1").unwrap());
+        raw_config.insert(
+            "retention".into(),
+            serde_yaml::from_str("rooms: {renew_room: {reverify_after_secs: 0}}").unwrap(),
+        );
+        let config: Config =
+            serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+
+        let guest_token = test_guest_token("renew_room");
+        let guest_jwt = sign_guest_token(&guest_token);
+        let attr_id = "attr_id_of_the_renewed_session";
+        Session::new(guest_token.clone(), attr_id.to_string())
+            .persist(&db)
+            .await
+            .unwrap();
+        Session::register_auth_result(attr_id.to_string(), "result".to_string(), &db)
+            .await
+            .unwrap();
+        let start_request = || StartRequest::new("test_purpose", "irma");
+        let request_id = RequestId::generate();
+
+        // A failing core leaves the stale session as it was, so the guest can retry
+        assert!(
+            renew_guest_session(&guest_jwt, start_request(), &request_id, &config, &db)
+                .await
+                .is_err()
+        );
+        let session = Session::find_by_id(guest_token.id.clone(), &db)
+            .await
+            .unwrap();
+        assert_eq!(session.attr_id, attr_id);
+        assert_eq!(session.auth_result.as_deref(), Some("result"));
+        assert!(session.authenticated_at.is_some());
+
+        let response = renew_guest_session(&guest_jwt, start_request(), &request_id, &config, &db)
+            .await
+            .unwrap();
+        assert_eq!(response.client_url, "https://core.example.com/continue");
+        let session = Session::find_by_id(guest_token.id, &db).await.unwrap();
+        assert_ne!(session.attr_id, attr_id);
+        assert_eq!(session.auth_result, None);
+        assert_eq!(session.authenticated_at, None);
+    }
+}
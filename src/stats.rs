@@ -0,0 +1,325 @@
+use crate::{error::Error, session::SessionDBConn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often counters are written to the database
+const STATS_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Upper bounds, in seconds, of the time-to-authenticate histogram buckets
+pub const TIME_TO_AUTHENTICATE_BUCKETS: &[u64] = &[10, 30, 60, 120, 300, 600, 1800, 3600];
+
+/// Label of the bucket for durations beyond the last bound
+const OVERFLOW_BUCKET: &str = "+Inf";
+
+/// Kinds of usage counted. None of them involve personal data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Metric {
+    /// Sessions started, labeled by purpose
+    Sessions,
+    /// Authentications started, labeled by auth method
+    AuthMethod,
+    /// Time between starting a session and receiving its authentication
+    /// result, labeled by histogram bucket
+    TimeToAuthenticate,
+}
+
+impl Metric {
+    fn as_str(self) -> &'static str {
+        match self {
+            Metric::Sessions => "sessions",
+            Metric::AuthMethod => "auth_method",
+            Metric::TimeToAuthenticate => "time_to_authenticate",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    /// Counts not yet written to the database
+    pending: BTreeMap<(Metric, String), i64>,
+    /// Counts since startup, for Prometheus
+    totals: BTreeMap<(Metric, String), u64>,
+    time_to_authenticate_sum: f64,
+    last_flush: Option<Instant>,
+}
+
+lazy_static! {
+    static ref COUNTERS: Mutex<Counters> = Mutex::new(Counters::default());
+}
+
+fn record(metric: Metric, label: &str) {
+    let mut counters = COUNTERS.lock().expect("Usage counters lock poisoned");
+    *counters
+        .pending
+        .entry((metric, label.to_string()))
+        .or_default() += 1;
+    *counters
+        .totals
+        .entry((metric, label.to_string()))
+        .or_default() += 1;
+}
+
+/// Count a session started for a purpose
+pub(crate) fn record_session(purpose: &str) {
+    record(Metric::Sessions, purpose);
+}
+
+/// Count an authentication started with an auth method
+pub(crate) fn record_auth_method(auth_method: &str) {
+    record(Metric::AuthMethod, auth_method);
+}
+
+/// Note how long a guest took to authenticate
+pub(crate) fn record_time_to_authenticate(seconds: f64) {
+    let bucket = TIME_TO_AUTHENTICATE_BUCKETS
+        .iter()
+        .find(|bound| seconds <= **bound as f64)
+        .map(|bound| bound.to_string())
+        .unwrap_or_else(|| OVERFLOW_BUCKET.to_string());
+    record(Metric::TimeToAuthenticate, &bucket);
+    COUNTERS
+        .lock()
+        .expect("Usage counters lock poisoned")
+        .time_to_authenticate_sum += seconds;
+}
+
+/// Add the counts noted since the last flush to today's totals in the database
+pub async fn flush_stats(db: &SessionDBConn) -> Result<(), Error> {
+    let pending = {
+        let mut counters = COUNTERS.lock().expect("Usage counters lock poisoned");
+        counters.last_flush = Some(Instant::now());
+        std::mem::take(&mut counters.pending)
+    };
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let metrics: Vec<&str> = pending.keys().map(|(metric, _)| metric.as_str()).collect();
+    let labels: Vec<String> = pending.keys().map(|(_, label)| label.clone()).collect();
+    let counts: Vec<i64> = pending.values().copied().collect();
+    let result = db
+        .timed_run("flush_stats", move |c| {
+            c.execute(
+                "INSERT INTO usage_stats (day, metric, label, count)
+                SELECT CURRENT_DATE, metric, label, count
+                FROM unnest($1::TEXT[], $2::TEXT[], $3::BIGINT[]) AS t(metric, label, count)
+                ON CONFLICT (day, metric, label)
+                DO UPDATE SET count = usage_stats.count + EXCLUDED.count",
+                &[&metrics, &labels, &counts],
+            )
+        })
+        .await;
+
+    if let Err(e) = result {
+        // Keep the counts for the next attempt
+        let mut counters = COUNTERS.lock().expect("Usage counters lock poisoned");
+        for (key, count) in pending {
+            *counters.pending.entry(key).or_default() += count;
+        }
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Flush the counters if they were last flushed a while ago. Failures are
+/// only logged, as statistics should never get in the way of sessions.
+pub(crate) async fn flush_stats_if_due(db: &SessionDBConn) {
+    let last_flush = COUNTERS
+        .lock()
+        .expect("Usage counters lock poisoned")
+        .last_flush;
+    let due =
+        !matches!(last_flush, Some(last_flush) if last_flush.elapsed() < STATS_FLUSH_INTERVAL);
+    if due {
+        if let Err(e) = flush_stats(db).await {
+            log::warn!("Could not write usage statistics: {}", e);
+        }
+    }
+}
+
+/// Aggregate usage of a single day
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DayStats {
+    /// Date in `YYYY-MM-DD` format
+    pub day: String,
+    /// Number of sessions started, by purpose
+    pub sessions: BTreeMap<String, i64>,
+    /// Number of authentications started, by auth method
+    pub auth_methods: BTreeMap<String, i64>,
+    /// Estimated median number of seconds between starting a session and
+    /// receiving its authentication result
+    pub median_time_to_authenticate_secs: Option<f64>,
+}
+
+/// Usage statistics of the last `days` days, including today, oldest first.
+/// Counters of this process are flushed first, so they are included.
+pub async fn usage_stats(days: u32, db: &SessionDBConn) -> Result<Vec<DayStats>, Error> {
+    flush_stats(db).await?;
+    let days = i32::try_from(days).unwrap_or(i32::MAX);
+    let rows = db
+        .timed_run("usage_stats", move |c| {
+            c.query(
+                "SELECT day::TEXT AS day, metric, label, count
+                FROM usage_stats
+                WHERE day > CURRENT_DATE - $1::INTEGER
+                ORDER BY day",
+                &[&days],
+            )
+        })
+        .await?;
+
+    let mut stats: Vec<(DayStats, BTreeMap<String, i64>)> = vec![];
+    for row in rows {
+        let day: String = row.get("day");
+        if stats.last().map(|(day_stats, _)| &day_stats.day) != Some(&day) {
+            let day_stats = DayStats {
+                day,
+                ..DayStats::default()
+            };
+            stats.push((day_stats, BTreeMap::new()));
+        }
+        let (day_stats, histogram) = stats.last_mut().expect("No stats for the current day");
+        let (label, count): (String, i64) = (row.get("label"), row.get("count"));
+        match row.get::<_, &str>("metric") {
+            "sessions" => {
+                day_stats.sessions.insert(label, count);
+            }
+            "auth_method" => {
+                day_stats.auth_methods.insert(label, count);
+            }
+            "time_to_authenticate" => {
+                histogram.insert(label, count);
+            }
+            _ => {}
+        }
+    }
+    Ok(stats
+        .into_iter()
+        .map(|(day_stats, histogram)| DayStats {
+            median_time_to_authenticate_secs: histogram_median(&histogram),
+            ..day_stats
+        })
+        .collect())
+}
+
+/// Estimate the median of a time-to-authenticate histogram by interpolating
+/// within the bucket containing it, like Prometheus' `histogram_quantile`.
+/// Medians beyond the last bound are reported as that bound.
+fn histogram_median(histogram: &BTreeMap<String, i64>) -> Option<f64> {
+    let total: i64 = histogram.values().sum();
+    if total <= 0 {
+        return None;
+    }
+    let rank = total as f64 / 2.0;
+    let mut below = 0i64;
+    let mut lower = 0.0;
+    for bound in TIME_TO_AUTHENTICATE_BUCKETS {
+        let count = histogram.get(&bound.to_string()).copied().unwrap_or(0);
+        let upper = *bound as f64;
+        if count > 0 && (below + count) as f64 >= rank {
+            return Some(lower + (upper - lower) * (rank - below as f64) / count as f64);
+        }
+        below += count;
+        lower = upper;
+    }
+    Some(lower)
+}
+
+/// Render the usage counters of this process in the Prometheus text
+/// exposition format
+pub fn render_prometheus() -> String {
+    let counters = COUNTERS.lock().expect("Usage counters lock poisoned");
+    let mut out = String::new();
+
+    out.push_str("# TYPE usage_sessions_total counter\n");
+    for ((metric, label), count) in counters.totals.iter() {
+        if *metric == Metric::Sessions {
+            out.push_str(&format!(
+                "usage_sessions_total{{purpose=\"{}\"}} {}\n",
+                escape_label(label),
+                count
+            ));
+        }
+    }
+
+    out.push_str("# TYPE usage_auth_method_total counter\n");
+    for ((metric, label), count) in counters.totals.iter() {
+        if *metric == Metric::AuthMethod {
+            out.push_str(&format!(
+                "usage_auth_method_total{{auth_method=\"{}\"}} {}\n",
+                escape_label(label),
+                count
+            ));
+        }
+    }
+
+    out.push_str("# TYPE usage_time_to_authenticate_seconds histogram\n");
+    let bucket_count = |label: &str| {
+        counters
+            .totals
+            .get(&(Metric::TimeToAuthenticate, label.to_string()))
+            .copied()
+            .unwrap_or(0)
+    };
+    let mut cumulative = 0;
+    for bound in TIME_TO_AUTHENTICATE_BUCKETS {
+        cumulative += bucket_count(&bound.to_string());
+        out.push_str(&format!(
+            "usage_time_to_authenticate_seconds_bucket{{le=\"{}\"}} {}\n",
+            bound, cumulative
+        ));
+    }
+    cumulative += bucket_count(OVERFLOW_BUCKET);
+    out.push_str(&format!(
+        "usage_time_to_authenticate_seconds_bucket{{le=\"{}\"}} {}\n",
+        OVERFLOW_BUCKET, cumulative
+    ));
+    out.push_str(&format!(
+        "usage_time_to_authenticate_seconds_sum {}\n",
+        counters.time_to_authenticate_sum
+    ));
+    out.push_str(&format!(
+        "usage_time_to_authenticate_seconds_count {}\n",
+        cumulative
+    ));
+    out
+}
+
+/// Escape a label value for the Prometheus text format
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_counters() {
+        let histogram: BTreeMap<String, i64> = [("10", 1), ("30", 2), ("60", 1)]
+            .iter()
+            .map(|(label, count)| (label.to_string(), *count))
+            .collect();
+        assert_eq!(histogram_median(&histogram), Some(20.0));
+        assert_eq!(histogram_median(&BTreeMap::new()), None);
+        let overflow: BTreeMap<String, i64> =
+            [(OVERFLOW_BUCKET.to_string(), 3)].iter().cloned().collect();
+        assert_eq!(histogram_median(&overflow), Some(3600.0));
+
+        record_session("stats_test_purpose");
+        record_auth_method("stats_test\"method");
+        record_time_to_authenticate(5.0);
+        let rendered = render_prometheus();
+        assert!(rendered.contains("usage_sessions_total{purpose=\"stats_test_purpose\"} 1\n"));
+        assert!(
+            rendered.contains("usage_auth_method_total{auth_method=\"stats_test\\\"method\"} 1\n")
+        );
+        assert!(rendered.contains("usage_time_to_authenticate_seconds_bucket{le=\"+Inf\"}"));
+    }
+}
@@ -0,0 +1,737 @@
+use crate::translations::{interpolate_filter, localdatetime_filter, Translations};
+#[cfg(feature = "platform_token")]
+use crate::{
+    config::Config,
+    credentials::RoomState,
+    error::Error,
+    translations::{available_locales, strict_translations, template_translation_keys},
+    types::{Credentials, SortedCredentials},
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use tera::{ast::Node, Context, Tera, Value};
+
+lazy_static! {
+    static ref TEMPLATE_DIR: RwLock<PathBuf> = RwLock::new(PathBuf::from("templates"));
+    static ref TEMPLATE_SETTINGS: RwLock<TemplateSettings> =
+        RwLock::new(TemplateSettings::default());
+    static ref INLINE_CSS: RwLock<Option<String>> = RwLock::new(None);
+    static ref CONTEXT_EXTENDERS: RwLock<Vec<Box<dyn ContextExtender>>> = RwLock::new(vec![]);
+    pub static ref TEMPLATES: Tera = build_templates(
+        &TEMPLATE_SETTINGS
+            .read()
+            .expect("Template settings lock poisoned"),
+        template_sources(),
+        &operator_templates(),
+    )
+    .expect("Error loading templates");
+}
+
+/// Functions Tera provides out of the box
+const BUILTIN_FUNCTIONS: [&str; 5] = ["range", "now", "throw", "get_random", "get_env"];
+
+/// Functions operator-provided templates may call in sandbox mode, unless
+/// configured otherwise
+const SANDBOX_FUNCTIONS: [&str; 2] = ["range", "throw"];
+
+/// Settings of the shared Tera instance
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TemplateSettings {
+    /// Suffixes of the templates whose output is HTML-escaped
+    #[serde(default = "default_autoescape")]
+    autoescape: Vec<String>,
+    /// Builtin functions templates may call. All of them if not set, or
+    /// only `range` and `throw` in sandbox mode.
+    #[serde(default)]
+    allowed_functions: Option<Vec<String>>,
+    /// Restrict operator-provided templates, those in the template
+    /// directory, to including and importing the templates of the library
+    #[serde(default)]
+    sandbox: bool,
+}
+
+fn default_autoescape() -> Vec<String> {
+    vec![".html".into(), ".htm".into(), ".xml".into()]
+}
+
+impl Default for TemplateSettings {
+    fn default() -> Self {
+        TemplateSettings {
+            autoescape: default_autoescape(),
+            allowed_functions: None,
+            sandbox: false,
+        }
+    }
+}
+
+impl TemplateSettings {
+    /// HTML-escape the output of templates with the given suffixes only
+    pub fn with_autoescape(
+        mut self,
+        suffixes: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.autoescape = suffixes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Allow templates to call the given builtin functions only
+    pub fn with_allowed_functions(
+        mut self,
+        functions: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.allowed_functions = Some(functions.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Enable or disable sandbox mode for operator-provided templates
+    pub fn with_sandbox(mut self, sandbox: bool) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    fn function_allowed(&self, function: &str) -> bool {
+        match &self.allowed_functions {
+            Some(allowed) => allowed.iter().any(|f| f == function),
+            None => !self.sandbox || SANDBOX_FUNCTIONS.contains(&function),
+        }
+    }
+}
+
+/// Change the settings of the shared Tera instance. This only has an
+/// effect when called before the first template is rendered.
+pub fn set_template_settings(settings: TemplateSettings) {
+    *TEMPLATE_SETTINGS
+        .write()
+        .expect("Template settings lock poisoned") = settings;
+}
+
+fn build_templates(
+    settings: &TemplateSettings,
+    sources: Vec<(String, String)>,
+    operator_templates: &[String],
+) -> Result<Tera, tera::Error> {
+    let mut tera = Tera::default();
+    // Set once for the lifetime of the process, so leaking is harmless
+    tera.autoescape_on(
+        settings
+            .autoescape
+            .iter()
+            .map(|suffix| &*Box::leak(suffix.clone().into_boxed_str()))
+            .collect(),
+    );
+    for function in BUILTIN_FUNCTIONS
+        .iter()
+        .filter(|function| !settings.function_allowed(function))
+    {
+        let message = format!("Function {} is not allowed in templates", function);
+        tera.register_function(function, move |_: &HashMap<String, Value>| {
+            Err(tera::Error::msg(&message))
+        });
+    }
+    tera.register_filter("interpolate", interpolate_filter);
+    tera.register_filter("localdatetime", localdatetime_filter);
+    tera.add_raw_templates(sources)?;
+
+    if settings.sandbox {
+        let embedded: Vec<&str> = embedded_templates().iter().map(|(name, _)| *name).collect();
+        for name in operator_templates {
+            let template = tera.get_template(name)?;
+            if let Some(target) = referenced_templates(&template.ast)
+                .into_iter()
+                .find(|target| !embedded.contains(&target.as_str()))
+            {
+                return Err(tera::Error::msg(format!(
+                    "Template {} references {}, which sandbox mode does not allow",
+                    name, target
+                )));
+            }
+        }
+    }
+    Ok(tera)
+}
+
+/// Names of the templates included or imported anywhere in the nodes
+fn referenced_templates(nodes: &[Node]) -> Vec<String> {
+    let mut referenced = vec![];
+    for node in nodes {
+        match node {
+            Node::Include(_, names, _) => referenced.extend(names.iter().cloned()),
+            Node::ImportMacro(_, name, _) => referenced.push(name.clone()),
+            Node::MacroDefinition(_, definition, _) => {
+                referenced.extend(referenced_templates(&definition.body))
+            }
+            Node::FilterSection(_, section, _) => {
+                referenced.extend(referenced_templates(&section.body))
+            }
+            Node::Block(_, block, _) => referenced.extend(referenced_templates(&block.body)),
+            Node::Forloop(_, forloop, _) => {
+                referenced.extend(referenced_templates(&forloop.body));
+                if let Some(body) = &forloop.empty_body {
+                    referenced.extend(referenced_templates(body));
+                }
+            }
+            Node::If(condition, _) => {
+                for (_, _, body) in &condition.conditions {
+                    referenced.extend(referenced_templates(body));
+                }
+                if let Some((_, body)) = &condition.otherwise {
+                    referenced.extend(referenced_templates(body));
+                }
+            }
+            _ => {}
+        }
+    }
+    referenced
+}
+
+/// Templates embedded in the library, by name
+fn embedded_templates() -> Vec<(&'static str, &'static str)> {
+    #[allow(unused_mut)]
+    let mut templates = vec![
+        ("base.html", include_str!("templates/base.html")),
+        (
+            "credentials.html",
+            include_str!("templates/credentials.html"),
+        ),
+        (
+            "room_pending.html",
+            include_str!("templates/room_pending.html"),
+        ),
+    ];
+    #[cfg(feature = "notify")]
+    templates.push((
+        "notify_email.txt",
+        include_str!("templates/notify_email.txt"),
+    ));
+    #[cfg(feature = "oauth")]
+    templates.extend(vec![
+        (
+            "login_required.html",
+            include_str!("templates/login_required.html"),
+        ),
+        (
+            "login_result.html",
+            include_str!("templates/login_result.html"),
+        ),
+        (
+            "logout_result.html",
+            include_str!("templates/logout_result.html"),
+        ),
+    ]);
+    templates
+}
+
+/// Source of every template by name, taken from the template directory
+/// when present there and from the embedded version otherwise. Additional
+/// templates in the template directory, such as alternative templates for
+/// purposes, are included as well.
+pub(crate) fn template_sources() -> Vec<(String, String)> {
+    let dir = template_dir();
+    let embedded = embedded_templates();
+    let mut sources: Vec<(String, String)> = embedded
+        .iter()
+        .map(|(name, embedded)| {
+            let path = dir.join(name);
+            let source = if path.exists() {
+                std::fs::read_to_string(&path)
+                    .unwrap_or_else(|e| panic!("Error loading custom {} template: {}", name, e))
+            } else {
+                embedded.to_string()
+            };
+            (name.to_string(), source)
+        })
+        .collect();
+
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(_) => return sources,
+    };
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if path.is_file() && !embedded.iter().any(|(e, _)| *e == name) => {
+                name.to_string()
+            }
+            _ => continue,
+        };
+        let source = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Error loading custom {} template: {}", name, e));
+        sources.push((name, source));
+    }
+    sources
+}
+
+/// Names of the templates loaded from the template directory
+pub(crate) fn operator_templates() -> Vec<String> {
+    let dir = template_dir();
+    template_sources()
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| dir.join(name).is_file())
+        .collect()
+}
+
+/// Name of the template to render: `alternative` if such a template was
+/// loaded, and `default` otherwise
+pub fn select_template<'a>(default: &'a str, alternative: Option<&'a str>) -> &'a str {
+    match alternative {
+        Some(alternative) if TEMPLATES.get_template_names().any(|n| n == alternative) => {
+            alternative
+        }
+        Some(alternative) => {
+            log::warn!(
+                "Template {} not found, falling back to {}",
+                alternative,
+                default
+            );
+            default
+        }
+        None => default,
+    }
+}
+
+/// Directory custom templates are loaded from, `templates` by default
+pub fn template_dir() -> PathBuf {
+    TEMPLATE_DIR
+        .read()
+        .expect("Template dir lock poisoned")
+        .clone()
+}
+
+/// Change the directory custom templates are loaded from. This only has an
+/// effect when called before the first template is rendered.
+pub fn set_template_dir(dir: impl Into<PathBuf>) {
+    *TEMPLATE_DIR.write().expect("Template dir lock poisoned") = dir.into();
+}
+
+/// Stylesheet embedded in the library, for pages that cannot load external assets
+pub const DEFAULT_CSS: &str = include_str!("templates/default.css");
+
+/// Stylesheet to inline into rendered pages, if any
+fn inline_css(include_default: bool, custom_css: Option<&str>) -> Option<String> {
+    let css: Vec<&str> = include_default
+        .then_some(DEFAULT_CSS)
+        .into_iter()
+        .chain(custom_css)
+        .collect();
+    match css.is_empty() {
+        true => None,
+        // Keep the stylesheet from closing the style element
+        false => Some(css.join("\n").replace("</", "<\\/")),
+    }
+}
+
+/// Inline the default stylesheet and/or a custom stylesheet into `base.html`
+/// output, for pages shown in sandboxed iframes that block external requests
+pub fn set_inline_css(include_default: bool, custom_css: Option<&str>) {
+    *INLINE_CSS.write().expect("Inline CSS lock poisoned") =
+        inline_css(include_default, custom_css);
+}
+
+/// Hook through which plugins add their own values, such as platform branding
+/// or the room title, to the context of every template rendered by the library
+pub trait ContextExtender: Send + Sync {
+    /// Add values to the context used for rendering the named template
+    fn extend(&self, template: &str, context: &mut Context);
+}
+
+impl<F> ContextExtender for F
+where
+    F: Fn(&str, &mut Context) + Send + Sync,
+{
+    fn extend(&self, template: &str, context: &mut Context) {
+        self(template, context)
+    }
+}
+
+/// Register a context extender, applied after any registered before
+pub fn register_context_extender(extender: impl ContextExtender + 'static) {
+    CONTEXT_EXTENDERS
+        .write()
+        .expect("Context extenders lock poisoned")
+        .push(Box::new(extender));
+}
+
+#[cfg(any(test, feature = "platform_token"))]
+fn extend_context(template: &str, context: &mut Context) {
+    for extender in CONTEXT_EXTENDERS
+        .read()
+        .expect("Context extenders lock poisoned")
+        .iter()
+    {
+        extender.extend(template, context);
+    }
+}
+
+/// Context for rendering a template in the language of the given
+/// translations, available as `translations` and `locale`
+pub fn localized_context(translations: &Translations) -> Context {
+    let mut context = Context::new();
+    context.insert("translations", translations);
+    context.insert("locale", translations.locale());
+    context
+}
+
+/// Render the named template, after applying the registered context extenders
+#[cfg(feature = "platform_token")]
+pub(crate) fn render(template: &str, mut context: Context) -> Result<String, tera::Error> {
+    if let Some(css) = INLINE_CSS
+        .read()
+        .expect("Inline CSS lock poisoned")
+        .as_deref()
+    {
+        context.insert("inline_css", css);
+    }
+    extend_context(template, &mut context);
+    if strict_translations() {
+        check_translated(template, &context)?;
+    }
+    TEMPLATES.render(template, &context)
+}
+
+/// Fail if the template uses keys the locale of the context does not
+/// translate itself, listing all of them
+#[cfg(feature = "platform_token")]
+fn check_translated(template: &str, context: &Context) -> Result<(), tera::Error> {
+    let locale = match context.get("locale").and_then(|locale| locale.as_str()) {
+        Some(locale) => locale,
+        None => return Ok(()),
+    };
+    let untranslated =
+        Translations::for_locale(locale).untranslated(template_translation_keys(template).iter());
+    if untranslated.is_empty() {
+        return Ok(());
+    }
+    Err(tera::Error::msg(format!(
+        "Template {} uses keys without {} translation: {}",
+        template,
+        locale,
+        untranslated.join(", ")
+    )))
+}
+
+/// Sample credentials and states of rooms, covering the shapes templates
+/// have to handle
+#[cfg(feature = "platform_token")]
+fn sample_rooms() -> Vec<(Vec<SortedCredentials>, Option<RoomState>)> {
+    let attributes = |values: &[(&str, &str)]| {
+        values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    };
+    let mut flagged = Credentials::new(attributes(&[
+        ("email", "guest@example.com"),
+        ("attribute_without_translation", "value"),
+    ]))
+    .with_purpose("sample")
+    .with_name("Guest");
+    flagged.unexpected_attributes = vec!["bsn".to_string()];
+    flagged.stale = true;
+    let expired = Credentials {
+        expired: true,
+        reverify_url: Some("https://comm.example.com/reverify".to_string()),
+        ..Credentials::default().with_name("Guest")
+    };
+    let long_name = "Guest With A Very Long Name ".repeat(10);
+    let long = Credentials::new(attributes(&[("email", &long_name)])).with_name(&long_name);
+
+    vec![
+        (vec![], Some(RoomState::NoGuests)),
+        (vec![], Some(RoomState::AllPending)),
+        (
+            vec![flagged.into(), expired.into()],
+            Some(RoomState::Available),
+        ),
+        (vec![Credentials::default().into(), long.into()], None),
+    ]
+}
+
+/// Sample contexts for the named template, without translations
+#[cfg(feature = "platform_token")]
+fn sample_contexts(template: &str) -> Vec<Context> {
+    let contexts = |values: Vec<serde_json::Value>| {
+        values
+            .into_iter()
+            .map(|value| Context::from_value(value).expect("Sample context is an object"))
+            .collect()
+    };
+    match template {
+        "login_required.html" => contexts(vec![
+            serde_json::json!({"login_url": "https://comm.example.com/login"}),
+        ]),
+        "login_result.html" => contexts(vec![
+            serde_json::json!({
+                "host": {"subject": "host", "name": "Host", "provider": "sample"},
+                "next": "https://comm.example.com/",
+            }),
+            serde_json::json!({
+                "host": {"subject": "host", "name": null, "provider": "sample"},
+                "next": null,
+            }),
+        ]),
+        "logout_result.html" => contexts(vec![
+            serde_json::json!({"end_session_url": "https://idp.example.com/logout"}),
+            serde_json::json!({"end_session_url": null}),
+        ]),
+        "notify_email.txt" => contexts(vec![
+            serde_json::json!({"room_id": "room", "display_name": "Platform"}),
+            serde_json::json!({"room_id": "room", "display_name": null}),
+        ]),
+        _ => sample_rooms()
+            .into_iter()
+            .map(|(credentials, room_state)| {
+                let mut context = Context::new();
+                context.insert("credentials", &credentials);
+                context.insert("room_state", &room_state);
+                context
+            })
+            .collect(),
+    }
+}
+
+/// Names of the templates the library renders, including the alternatives
+/// configured for purposes, with the name of the template each replaces
+#[cfg(feature = "platform_token")]
+fn rendered_templates(config: &Config) -> Vec<(String, String)> {
+    let mut templates: Vec<(String, String)> = embedded_templates()
+        .into_iter()
+        .map(|(name, _)| (name.to_string(), name.to_string()))
+        .collect();
+    for purpose in config.purposes.values() {
+        for (replaced, alternative) in &purpose.templates {
+            if !templates.iter().any(|(name, _)| name == alternative) {
+                templates.push((alternative.clone(), replaced.clone()));
+            }
+        }
+    }
+    templates
+}
+
+/// Description of an error with its causes, which Tera puts the details in
+#[cfg(feature = "platform_token")]
+fn describe(error: &tera::Error) -> String {
+    let mut description = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        description.push_str(": ");
+        description.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    description
+}
+
+#[cfg(feature = "platform_token")]
+fn lint_templates(
+    templates: &[(String, String)],
+    render: impl Fn(&str, Context) -> Result<String, tera::Error>,
+) -> Result<(), Error> {
+    let mut errors = vec![];
+    for (name, replaced) in templates {
+        for locale in available_locales() {
+            for (i, mut context) in sample_contexts(replaced).into_iter().enumerate() {
+                context.extend(localized_context(&Translations::for_locale(locale)));
+                if let Err(e) = render(name, context) {
+                    errors.push(format!(
+                        "{} (locale {}, sample {}): {}",
+                        name,
+                        locale,
+                        i + 1,
+                        describe(&e)
+                    ));
+                }
+            }
+        }
+    }
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(Error::Template(tera::Error::msg(format!(
+        "{} failed template renders:\n{}",
+        errors.len(),
+        errors.join("\n")
+    ))))
+}
+
+/// Render every template the library uses, including custom and purpose
+/// templates, in every locale with sample contexts: empty rooms, all shapes
+/// of credentials and long names. Fails with all errors found, so broken
+/// custom templates are caught at startup rather than when a host first
+/// views a room.
+#[cfg(feature = "platform_token")]
+pub fn check_templates(config: &Config) -> Result<(), Error> {
+    lint_templates(&rendered_templates(config), render)
+}
+
+/// Fairing aborting launch if any template fails to render, see
+/// [`check_templates`]. Requires the [`Config`] to be managed.
+#[cfg(all(feature = "platform_token", feature = "rocket"))]
+pub fn template_check_fairing() -> rocket::fairing::AdHoc {
+    rocket::fairing::AdHoc::try_on_ignite("Template check", |rocket| async move {
+        let result = match rocket.state::<Config>() {
+            Some(config) => check_templates(config),
+            None => {
+                log::error!("No configuration available for the template check");
+                return Err(rocket);
+            }
+        };
+        match result {
+            Ok(()) => Ok(rocket),
+            Err(e) => {
+                log::error!("{}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline_css() {
+        assert_eq!(inline_css(false, None), None);
+        assert_eq!(inline_css(true, None).as_deref(), Some(DEFAULT_CSS));
+        assert_eq!(
+            inline_css(false, Some("p { color: red }</style><script>")).as_deref(),
+            Some("p { color: red }<\\/style><script>")
+        );
+        assert!(inline_css(true, Some("p {}")).unwrap().ends_with("\np {}"));
+    }
+
+    #[test]
+    fn test_template_settings() {
+        let sources = |template: &str| {
+            vec![
+                ("credentials.html".to_string(), "{{ value }}".to_string()),
+                ("custom.html".to_string(), template.to_string()),
+            ]
+        };
+        let mut context = Context::new();
+        context.insert("value", "<b>");
+
+        let tera = build_templates(
+            &TemplateSettings::default(),
+            sources("{{ get_env(name=\"HOME\", default=\"\") }}"),
+            &["custom.html".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            tera.render("credentials.html", &context).unwrap(),
+            "&lt;b&gt;"
+        );
+        assert!(tera.render("custom.html", &context).is_ok());
+
+        let settings = TemplateSettings::default()
+            .with_autoescape(Vec::<String>::new())
+            .with_sandbox(true);
+        let tera = build_templates(
+            &settings,
+            sources("{{ get_env(name=\"HOME\", default=\"\") }}"),
+            &["custom.html".to_string()],
+        )
+        .unwrap();
+        assert_eq!(tera.render("credentials.html", &context).unwrap(), "<b>");
+        assert!(tera.render("custom.html", &context).is_err());
+
+        let tera = build_templates(
+            &settings.clone().with_allowed_functions(vec!["get_env"]),
+            sources("{{ get_env(name=\"HOME\", default=\"\") }}"),
+            &["custom.html".to_string()],
+        )
+        .unwrap();
+        assert!(tera.render("custom.html", &context).is_ok());
+
+        assert!(build_templates(
+            &settings,
+            sources("{% if value %}{% include \"credentials.html\" %}{% endif %}"),
+            &["custom.html".to_string()],
+        )
+        .is_ok());
+        let error = build_templates(
+            &settings,
+            sources("{% for x in [1] %}{% include \"custom.html\" %}{% endfor %}"),
+            &["custom.html".to_string()],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("sandbox mode"));
+    }
+
+    #[cfg(feature = "platform_token")]
+    #[test]
+    fn test_lint_templates() {
+        let templates = vec![
+            (
+                "credentials.html".to_string(),
+                "credentials.html".to_string(),
+            ),
+            (
+                "login_result.html".to_string(),
+                "login_result.html".to_string(),
+            ),
+        ];
+        let sources = |credentials: &str| {
+            vec![
+                ("credentials.html".to_string(), credentials.to_string()),
+                (
+                    "login_result.html".to_string(),
+                    "{{ host.name | default(value=host.subject) }}".to_string(),
+                ),
+            ]
+        };
+
+        let tera = build_templates(
+            &TemplateSettings::default(),
+            sources("{% for c in credentials %}{{ c.name | default(value='') }}{% endfor %}"),
+            &[],
+        )
+        .unwrap();
+        assert!(lint_templates(&templates, |name, context| tera.render(name, &context)).is_ok());
+
+        // Fails on the credentials without a name only
+        let tera = build_templates(
+            &TemplateSettings::default(),
+            sources("{% for c in credentials %}{{ c.name | upper }}{% endfor %}"),
+            &[],
+        )
+        .unwrap();
+        let error = lint_templates(&templates, |name, context| tera.render(name, &context))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("credentials.html (locale en, sample 4)"));
+        assert!(!error.contains("sample 1"));
+        assert!(!error.contains("login_result.html"));
+    }
+
+    #[cfg(feature = "auth_during_comm")]
+    #[test]
+    fn test_check_templates() {
+        let config: Config = serde_yaml::from_value(serde_yaml::Value::Mapping(
+            crate::test_helpers::test_raw_config(),
+        ))
+        .unwrap();
+        check_templates(&config).unwrap();
+    }
+
+    #[test]
+    fn test_context_extender() {
+        register_context_extender(|template: &str, context: &mut Context| {
+            if template == "test_context_extender" {
+                context.insert("platform", "Example");
+            }
+        });
+
+        let mut context = Context::new();
+        extend_context("test_context_extender", &mut context);
+        assert_eq!(context.get("platform"), Some(&serde_json::json!("Example")));
+
+        let mut context = Context::new();
+        extend_context("credentials.html", &mut context);
+        assert_eq!(context.get("platform"), None);
+    }
+}
@@ -0,0 +1,172 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Post-processing step applied to the attributes of an auth result before rendering
+pub trait AttributeTransformer: Send + Sync {
+    fn transform(&self, attributes: &mut HashMap<String, String>);
+}
+
+lazy_static! {
+    static ref TRANSFORMERS: RwLock<Vec<Arc<dyn AttributeTransformer>>> = RwLock::new(vec![]);
+}
+
+/// Register a custom transformer, applied after those from the configuration
+pub fn register_transformer(transformer: Arc<dyn AttributeTransformer>) {
+    TRANSFORMERS
+        .write()
+        .expect("Attribute transformers lock poisoned")
+        .push(transformer);
+}
+
+/// Apply the configured transformers, followed by the registered ones
+pub fn apply_transformers(
+    configured: &[TransformerConfig],
+    attributes: &mut HashMap<String, String>,
+) {
+    for transformer in configured {
+        transformer.transform(attributes);
+    }
+    for transformer in TRANSFORMERS
+        .read()
+        .expect("Attribute transformers lock poisoned")
+        .iter()
+    {
+        transformer.transform(attributes);
+    }
+}
+
+/// Built-in transformers, as configured in the `transformers` list
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransformerConfig {
+    /// Remove leading and trailing whitespace. Applies to all attributes if none are given
+    Trim {
+        #[serde(default)]
+        attributes: Vec<String>,
+    },
+    /// Write dates as `YYYY-MM-DD`. Recognizes `YYYY-MM-DD`, `YYYYMMDD` and
+    /// `DD-MM-YYYY` with `-`, `/` or `.` as separator. Other values are left as is.
+    Date { attributes: Vec<String> },
+    /// Mask all but the last three digits of a BSN
+    MaskBsn { attributes: Vec<String> },
+    /// Split a full name at its first space into a given and a family name,
+    /// keeping Dutch prefixes such as "van der" with the family name
+    SplitName {
+        attribute: String,
+        given_name_attribute: String,
+        family_name_attribute: String,
+    },
+}
+
+impl AttributeTransformer for TransformerConfig {
+    fn transform(&self, attributes: &mut HashMap<String, String>) {
+        match self {
+            TransformerConfig::Trim { attributes: keys } => {
+                for (key, value) in attributes.iter_mut() {
+                    if keys.is_empty() || keys.contains(key) {
+                        *value = value.trim().to_string();
+                    }
+                }
+            }
+            TransformerConfig::Date { attributes: keys } => {
+                for key in keys {
+                    if let Some(value) = attributes.get_mut(key) {
+                        if let Some(date) = canonical_date(value) {
+                            *value = date;
+                        }
+                    }
+                }
+            }
+            TransformerConfig::MaskBsn { attributes: keys } => {
+                for key in keys {
+                    if let Some(value) = attributes.get_mut(key) {
+                        *value = mask(value, 3);
+                    }
+                }
+            }
+            TransformerConfig::SplitName {
+                attribute,
+                given_name_attribute,
+                family_name_attribute,
+            } => {
+                let name = match attributes.get(attribute) {
+                    Some(name) => name.trim().to_string(),
+                    None => return,
+                };
+                let (given_name, family_name) = match name.split_once(' ') {
+                    Some((given_name, family_name)) => (given_name, family_name.trim()),
+                    None => ("", name.as_str()),
+                };
+                attributes.insert(given_name_attribute.clone(), given_name.to_string());
+                attributes.insert(family_name_attribute.clone(), family_name.to_string());
+            }
+        }
+    }
+}
+
+fn canonical_date(value: &str) -> Option<String> {
+    let value = value.trim();
+    let parts: Vec<&str> = value.split(&['-', '/', '.'][..]).collect();
+    let (year, month, day) = match parts.as_slice() {
+        [year, month, day] if year.len() == 4 => (*year, *month, *day),
+        [day, month, year] if year.len() == 4 => (*year, *month, *day),
+        [compact] if compact.len() == 8 => (&compact[0..4], &compact[4..6], &compact[6..8]),
+        _ => return None,
+    };
+    let year: u32 = year.parse().ok()?;
+    let month: u32 = month.parse().ok()?;
+    let day: u32 = day.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+fn mask(value: &str, visible: usize) -> String {
+    let len = value.chars().count();
+    value
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if i + visible < len { '*' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transformers() {
+        let transformers: Vec<TransformerConfig> = serde_yaml::from_str(
+            r"
+            - type: trim
+            - type: date
+              attributes: [birthdate]
+            - type: mask_bsn
+              attributes: [bsn]
+            - type: split_name
+              attribute: fullname
+              given_name_attribute: firstname
+              family_name_attribute: lastname
+            ",
+        )
+        .unwrap();
+
+        let mut attributes: HashMap<String, String> = vec![
+            ("birthdate", "01/02/1990 "),
+            ("bsn", "999999990"),
+            ("fullname", " Henk van der Dieter"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        apply_transformers(&transformers, &mut attributes);
+
+        assert_eq!(attributes["birthdate"], "1990-02-01");
+        assert_eq!(attributes["bsn"], "******990");
+        assert_eq!(attributes["firstname"], "Henk");
+        assert_eq!(attributes["lastname"], "van der Dieter");
+        assert_eq!(canonical_date("1990-13-01"), None);
+    }
+}
@@ -0,0 +1,821 @@
+use crate::{error::Error, templates::template_sources};
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+/// Locale used when none of the requested locales are available, unless
+/// another one is configured with [`set_default_locale`]
+pub const DEFAULT_LOCALE: &str = "nl";
+
+/// Translation keys looked up from code rather than from templates
+const CODE_KEYS: &[&str] = &[
+    "unknown_purpose",
+    "auth_method_not_permitted",
+    "too_many_requests",
+    "session_limit_reached",
+    "error",
+    "invalid_token",
+    "service_unavailable",
+    "timeout",
+    "datetime_format",
+    #[cfg(feature = "notify")]
+    "notify_subject",
+];
+
+/// Translation files embedded in the library, by locale
+const EMBEDDED: &[(&str, &str)] = &[
+    ("nl", include_str!("translations/nl.yml")),
+    ("en", include_str!("translations/en.yml")),
+];
+
+/// A translated message, which may contain `{name}` placeholders
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Message {
+    Text(String),
+    Plural(PluralForms),
+}
+
+/// Plural forms of a message. `{count}` is replaced by the count the form was selected for.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PluralForms {
+    zero: Option<String>,
+    one: Option<String>,
+    other: String,
+}
+
+impl PluralForms {
+    /// Select the form for a count. Uses the rule shared by Dutch and English:
+    /// `one` for exactly one, `other` otherwise, with an optional `zero` form.
+    pub fn select(&self, count: i64) -> &str {
+        let form = match count {
+            0 => self.zero.as_ref(),
+            1 | -1 => self.one.as_ref(),
+            _ => None,
+        };
+        form.unwrap_or(&self.other)
+    }
+}
+
+impl Message {
+    fn as_str(&self) -> &str {
+        match self {
+            Message::Text(text) => text,
+            Message::Plural(forms) => &forms.other,
+        }
+    }
+}
+
+/// Replace `{name}` placeholders in a message by the given values.
+/// Unknown placeholders are left as is.
+pub fn interpolate(message: &str, args: &[(&str, &str)]) -> String {
+    let mut result = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        let name = &rest[1..end];
+        match args.iter().find(|(arg, _)| *arg == name) {
+            Some((_, value)) => result.push_str(value),
+            None => result.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(transparent)]
+pub struct Translations {
+    messages: HashMap<String, Message>,
+    /// Locale the messages were selected for, if any
+    #[serde(skip)]
+    locale: Option<String>,
+}
+
+impl Translations {
+    /// Parse a translations file. Malformed input results in an error, never a panic.
+    pub fn parse(source: &str) -> Result<Translations, Error> {
+        serde_yaml::from_str(source).map_err(|e| {
+            log::error!("Could not parse translations file: {}", e);
+            Error::Config("Invalid translations file")
+        })
+    }
+
+    /// Look up the translation for a key, falling back to the key itself
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.messages.get(key).map(Message::as_str).unwrap_or(key)
+    }
+
+    /// Look up the translation for a key and fill in its placeholders
+    pub fn format(&self, key: &str, args: &[(&str, &str)]) -> String {
+        interpolate(self.get(key), args)
+    }
+
+    /// Look up the plural form of a key for a count and fill in its
+    /// placeholders, including `{count}`
+    pub fn plural(&self, key: &str, count: i64, args: &[(&str, &str)]) -> String {
+        let message = match self.messages.get(key) {
+            Some(Message::Plural(forms)) => forms.select(count),
+            Some(Message::Text(text)) => text,
+            None => key,
+        };
+        let count = count.to_string();
+        let mut args = args.to_vec();
+        args.push(("count", &count));
+        interpolate(message, &args)
+    }
+
+    /// Translations for a locale such as `en` or `en-GB`. Keys missing for
+    /// the exact locale fall back to its language, then to the configured
+    /// [`default_locale`] and finally to [`DEFAULT_LOCALE`].
+    pub fn for_locale(locale: &str) -> Translations {
+        let default_locale = default_locale();
+        let mut messages = LOCALES
+            .get(DEFAULT_LOCALE)
+            .map(|t| t.messages.clone())
+            .unwrap_or_default();
+        if default_locale != DEFAULT_LOCALE {
+            if let Some(translations) = LOCALES.get(&default_locale) {
+                messages.extend(translations.messages.clone());
+            }
+        }
+
+        let language = language_of(locale);
+        if language != locale {
+            if let Some(translations) = LOCALES.get(language) {
+                messages.extend(translations.messages.clone());
+            }
+        }
+        if let Some(translations) = LOCALES.get(locale) {
+            messages.extend(translations.messages.clone());
+        }
+
+        Translations {
+            messages,
+            locale: Some(resolve_locale(locale).unwrap_or(default_locale)),
+        }
+    }
+
+    /// Locale these translations were selected for, [`DEFAULT_LOCALE`] if
+    /// they were not selected for one
+    pub fn locale(&self) -> &str {
+        self.locale.as_deref().unwrap_or(DEFAULT_LOCALE)
+    }
+
+    /// Keys, out of the given ones, that this locale does not translate
+    /// itself and would be shown from a fallback locale, or not at all
+    pub fn untranslated<'a>(&self, keys: impl IntoIterator<Item = &'a String>) -> Vec<String> {
+        let locale = self.locale();
+        let own: Vec<&Translations> = [locale, language_of(locale)]
+            .iter()
+            .filter_map(|locale| LOCALES.get(*locale))
+            .collect();
+        keys.into_iter()
+            .filter(|key| !own.iter().any(|t| t.messages.contains_key(*key)))
+            .cloned()
+            .collect()
+    }
+
+    /// Pick the best available locale for an `Accept-Language` header value
+    pub fn negotiate(accept_language: &str) -> Translations {
+        Translations::for_locale(&negotiate_locale(accept_language))
+    }
+}
+
+/// All locales for which translations are available
+pub fn available_locales() -> Vec<&'static str> {
+    let mut locales: Vec<&str> = LOCALES.keys().map(String::as_str).collect();
+    locales.sort_unstable();
+    locales
+}
+
+fn language_of(locale: &str) -> &str {
+    locale.split(&['-', '_'][..]).next().unwrap_or(locale)
+}
+
+/// Pick the best available locale for an `Accept-Language` header value,
+/// falling back to the configured [`default_locale`]
+pub fn negotiate_locale(accept_language: &str) -> String {
+    let mut preferences: Vec<(&str, f32)> = accept_language
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let locale = parts.next().filter(|l| !l.is_empty() && *l != "*")?;
+            let quality = parts
+                .find_map(|p| p.strip_prefix("q="))
+                .map(|q| q.parse().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            Some((locale, quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+    // Stable sort, so equally preferred locales keep their order
+    preferences.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    preferences
+        .into_iter()
+        .find_map(|(locale, _)| resolve_locale(locale))
+        .unwrap_or_else(default_locale)
+}
+
+/// The available locale matching a requested locale or its language, if any
+pub fn resolve_locale(locale: &str) -> Option<String> {
+    if LOCALES.contains_key(locale) {
+        Some(locale.to_string())
+    } else if LOCALES.contains_key(language_of(locale)) {
+        Some(language_of(locale).to_string())
+    } else {
+        None
+    }
+}
+
+/// Whether a file name looks like a translation file, e.g. `nl.yml` or `en-GB.yml`
+fn locale_of_file(file_name: &str) -> Option<&str> {
+    let locale = file_name.strip_suffix(".yml")?;
+    let language = language_of(locale);
+    let valid = (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_lowercase())
+        && locale[language.len()..]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid {
+        Some(locale)
+    } else {
+        None
+    }
+}
+
+/// Translation coverage of a single locale
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TranslationCoverage {
+    pub locale: String,
+    /// Keys used by templates or code, but missing from this locale
+    pub missing: Vec<String>,
+    /// Keys in this locale not used by templates or code. Note that keys
+    /// looked up dynamically, such as attribute names, also end up here.
+    pub unused: Vec<String>,
+}
+
+impl TranslationCoverage {
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+/// Collect the keys referenced as `translations.<key>` in a template
+fn template_keys(source: &str, keys: &mut BTreeSet<String>) {
+    const PREFIX: &str = "translations.";
+    let mut rest = source;
+    while let Some(start) = rest.find(PREFIX) {
+        rest = &rest[start + PREFIX.len()..];
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if end > 0 {
+            keys.insert(rest[..end].to_string());
+        }
+        rest = &rest[end..];
+    }
+}
+
+/// Translation keys referenced by a template and the templates it includes
+#[cfg(feature = "platform_token")]
+pub(crate) fn template_translation_keys(name: &str) -> BTreeSet<String> {
+    let sources: HashMap<String, String> = template_sources().into_iter().collect();
+    let mut keys = BTreeSet::new();
+    let mut pending = vec![name.to_string()];
+    let mut seen = BTreeSet::new();
+    while let Some(name) = pending.pop() {
+        let source = match sources.get(&name) {
+            Some(source) if seen.insert(name.clone()) => source,
+            _ => continue,
+        };
+        template_keys(source, &mut keys);
+        for include in source.split("include \"").skip(1) {
+            if let Some(end) = include.find('"') {
+                pending.push(include[..end].to_string());
+            }
+        }
+    }
+    keys
+}
+
+/// All translation keys used by the templates in use and by this library's code
+pub fn referenced_keys() -> BTreeSet<String> {
+    let mut keys: BTreeSet<String> = CODE_KEYS.iter().map(|k| k.to_string()).collect();
+    for (_, source) in template_sources() {
+        template_keys(&source, &mut keys);
+    }
+    keys
+}
+
+/// Compare every loaded translation file against the referenced keys,
+/// so missing translations can be reported at startup
+pub fn check_coverage() -> Vec<TranslationCoverage> {
+    let referenced = referenced_keys();
+    let mut coverage: Vec<TranslationCoverage> = LOCALES
+        .iter()
+        .map(|(locale, translations)| {
+            let defined: BTreeSet<String> = translations.messages.keys().cloned().collect();
+            TranslationCoverage {
+                locale: locale.clone(),
+                missing: referenced.difference(&defined).cloned().collect(),
+                unused: defined.difference(&referenced).cloned().collect(),
+            }
+        })
+        .collect();
+    coverage.sort_by(|a, b| a.locale.cmp(&b.locale));
+    coverage
+}
+
+lazy_static! {
+    static ref TRANSLATIONS_DIR: RwLock<PathBuf> = RwLock::new(PathBuf::from("."));
+    static ref LOCALES: HashMap<String, Translations> = {
+        let mut locales = HashMap::new();
+        for (locale, embedded) in EMBEDDED {
+            let translations =
+                Translations::parse(embedded).expect("Could not load the translations file");
+            locales.insert(locale.to_string(), translations);
+        }
+
+        // Custom translation files replace the embedded ones, or add new locales
+        if let Ok(entries) = std::fs::read_dir(translations_dir()) {
+            for entry in entries.flatten() {
+                let file_name = entry.file_name();
+                let locale = match file_name.to_str().and_then(locale_of_file) {
+                    Some(locale) => locale.to_string(),
+                    None => continue,
+                };
+                // A broken custom file should not take the plugin down; the
+                // embedded translations (or other locales) are used instead
+                let translations = std::fs::read_to_string(entry.path())
+                    .map_err(|e| {
+                        log::error!("Could not read translation file {}: {}", entry.path().display(), e);
+                        Error::Config("Could not read translations file")
+                    })
+                    .and_then(|source| Translations::parse(&source));
+                if let Ok(translations) = translations {
+                    locales.insert(locale, translations);
+                }
+            }
+        }
+
+        locales
+    };
+    pub static ref TRANSLATIONS: Translations = Translations::for_locale(&default_locale());
+    static ref DEFAULT_LOCALE_OVERRIDE: RwLock<Option<String>> = RwLock::new(None);
+    static ref STRICT_TRANSLATIONS: RwLock<bool> = RwLock::new(false);
+}
+
+/// Locale used when none of the requested locales are available,
+/// [`DEFAULT_LOCALE`] unless configured otherwise
+pub fn default_locale() -> String {
+    DEFAULT_LOCALE_OVERRIDE
+        .read()
+        .expect("Default locale lock poisoned")
+        .clone()
+        .unwrap_or_else(|| DEFAULT_LOCALE.to_string())
+}
+
+/// Change the locale used when none of the requested locales are available.
+/// Fails if no translations are available for it. [`TRANSLATIONS`] only
+/// follows the change when called before translations are first used.
+pub fn set_default_locale(locale: &str) -> Result<(), Error> {
+    let locale = resolve_locale(locale).ok_or_else(|| {
+        log::error!("No translations available for default locale {}", locale);
+        Error::Config("Unknown default locale")
+    })?;
+    *DEFAULT_LOCALE_OVERRIDE
+        .write()
+        .expect("Default locale lock poisoned") = Some(locale);
+    Ok(())
+}
+
+/// Whether rendering fails on keys the requested locale does not translate
+/// itself, instead of falling back to another locale
+pub fn strict_translations() -> bool {
+    *STRICT_TRANSLATIONS
+        .read()
+        .expect("Strict translations lock poisoned")
+}
+
+/// Make rendering fail on untranslated keys, listing all of them, to catch
+/// missing translations in tests and development. Production should leave
+/// this off, so missing keys fall back to other locales.
+pub fn set_strict_translations(strict: bool) {
+    *STRICT_TRANSLATIONS
+        .write()
+        .expect("Strict translations lock poisoned") = strict;
+}
+
+/// Directory custom translation files are loaded from, the working directory by default
+pub fn translations_dir() -> PathBuf {
+    TRANSLATIONS_DIR
+        .read()
+        .expect("Translations dir lock poisoned")
+        .clone()
+}
+
+/// Change the directory custom translation files are loaded from. This only
+/// has an effect when called before translations are first used.
+pub fn set_translations_dir(dir: impl Into<PathBuf>) {
+    *TRANSLATIONS_DIR
+        .write()
+        .expect("Translations dir lock poisoned") = dir.into();
+}
+
+/// Tera filter filling in the placeholders of a translated message with its
+/// arguments, e.g. `{{ translations.greeting | interpolate(name=guest.name) }}`
+pub fn interpolate_filter(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    let message: Message = tera::from_value(value.clone())?;
+    let args: Vec<(String, String)> = args
+        .iter()
+        .map(|(name, value)| match value {
+            tera::Value::String(s) => (name.clone(), s.clone()),
+            other => (name.clone(), other.to_string()),
+        })
+        .collect();
+    let args: Vec<(&str, &str)> = args
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+
+    let count = args.iter().find(|(name, _)| *name == "count");
+    let text = match (&message, count) {
+        (Message::Plural(forms), Some((_, count))) => {
+            let count = count
+                .parse()
+                .map_err(|_| tera::Error::msg("The count argument must be an integer"))?;
+            forms.select(count)
+        }
+        _ => message.as_str(),
+    };
+    Ok(tera::Value::String(interpolate(text, &args)))
+}
+
+/// Format used for timestamps in locales without a `datetime_format` translation
+const FALLBACK_DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+lazy_static! {
+    static ref TIMEZONE: RwLock<Tz> = RwLock::new(chrono_tz::Europe::Amsterdam);
+}
+
+/// Timezone timestamps are rendered in, `Europe/Amsterdam` by default
+pub fn timezone() -> Tz {
+    *TIMEZONE.read().expect("Timezone lock poisoned")
+}
+
+/// Change the timezone timestamps are rendered in
+pub fn set_timezone(timezone: Tz) {
+    *TIMEZONE.write().expect("Timezone lock poisoned") = timezone;
+}
+
+/// Render a timestamp in the configured timezone, in the `datetime_format`
+/// (a `strftime` format) of the given locale
+pub fn format_datetime(timestamp: DateTime<Utc>, locale: &str) -> String {
+    let default_locale = default_locale();
+    let format = [locale, language_of(locale), &default_locale, DEFAULT_LOCALE]
+        .iter()
+        .filter_map(|locale| LOCALES.get(*locale))
+        .find_map(|translations| translations.messages.get("datetime_format"))
+        .map(Message::as_str)
+        .unwrap_or(FALLBACK_DATETIME_FORMAT);
+    timestamp
+        .with_timezone(&timezone())
+        .format(format)
+        .to_string()
+}
+
+/// Tera filter rendering a timestamp, given as Unix seconds or as an RFC 3339
+/// string, with [`format_datetime`], e.g.
+/// `{{ session.last_activity | localdatetime(locale=locale) }}`. Missing
+/// timestamps render as an empty string.
+pub fn localdatetime_filter(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    let timestamp = match value {
+        tera::Value::Null => return Ok(tera::Value::String(String::new())),
+        tera::Value::Number(seconds) => seconds
+            .as_i64()
+            .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single()),
+        tera::Value::String(timestamp) => DateTime::parse_from_rfc3339(timestamp)
+            .ok()
+            .map(|timestamp| timestamp.with_timezone(&Utc)),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        tera::Error::msg("The localdatetime filter expects Unix seconds or an RFC 3339 timestamp")
+    })?;
+    let locale = args
+        .get("locale")
+        .and_then(tera::Value::as_str)
+        .unwrap_or(DEFAULT_LOCALE);
+    Ok(tera::Value::String(format_datetime(timestamp, locale)))
+}
+
+/// Name of the cookie remembering the locale chosen with the `lang` query parameter
+pub const LANG_COOKIE: &str = "lang";
+
+/// Translations are selected by the `lang` query parameter, which is
+/// remembered in a cookie so that subsequent pages (e.g. within an embedded
+/// widget) keep the platform's language. Without either, the
+/// `Accept-Language` header is used.
+#[cfg(feature = "rocket")]
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for Translations {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(
+        request: &'r rocket::Request<'_>,
+    ) -> rocket::request::Outcome<Self, Self::Error> {
+        use rocket::http::{Cookie, SameSite};
+
+        if let Some(locale) = request
+            .query_value::<&str>("lang")
+            .and_then(Result::ok)
+            .and_then(resolve_locale)
+        {
+            // Widgets are embedded cross-site, so the cookie needs SameSite=None
+            request.cookies().add(
+                Cookie::build(LANG_COOKIE, locale.clone())
+                    .path("/")
+                    .same_site(SameSite::None)
+                    .secure(true)
+                    .permanent()
+                    .finish(),
+            );
+            return rocket::request::Outcome::Success(Translations::for_locale(&locale));
+        }
+
+        if let Some(locale) = request
+            .cookies()
+            .get(LANG_COOKIE)
+            .and_then(|cookie| resolve_locale(cookie.value()))
+        {
+            return rocket::request::Outcome::Success(Translations::for_locale(&locale));
+        }
+
+        let accept_language = request.headers().get_one("Accept-Language").unwrap_or("");
+        rocket::request::Outcome::Success(Translations::negotiate(accept_language))
+    }
+}
+
+/// Selects translations in axum handlers the same way, from the `lang` query
+/// parameter, the `lang` cookie or the `Accept-Language` header. Unlike the
+/// Rocket guard it does not set the cookie, as extractors cannot add headers
+/// to the response.
+#[cfg(feature = "axum")]
+#[axum::async_trait]
+impl<B: Send> axum::extract::FromRequest<B> for Translations {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request(
+        request: &mut axum::extract::RequestParts<B>,
+    ) -> Result<Self, Self::Rejection> {
+        use axum::http::header;
+
+        let requested = crate::util::query_pairs(request)
+            .await
+            .into_iter()
+            .find(|(key, _)| key == "lang")
+            .and_then(|(_, locale)| resolve_locale(&locale));
+        let remembered = || {
+            request
+                .headers()
+                .get_all(header::COOKIE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(';'))
+                .filter_map(|cookie| cookie.trim().split_once('='))
+                .find(|(name, _)| *name == LANG_COOKIE)
+                .and_then(|(_, locale)| resolve_locale(locale))
+        };
+        if let Some(locale) = requested.or_else(remembered) {
+            return Ok(Translations::for_locale(&locale));
+        }
+
+        let accept_language = request
+            .headers()
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("");
+        Ok(Translations::negotiate(accept_language))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_locale_fallback() {
+        assert_eq!(negotiate_locale("en-GB,en;q=0.9,nl;q=0.8"), "en");
+        assert_eq!(negotiate_locale("de, nl;q=0.5, en;q=0.7"), "en");
+        assert_eq!(negotiate_locale("de"), DEFAULT_LOCALE);
+        assert_eq!(negotiate_locale(""), DEFAULT_LOCALE);
+
+        let en = Translations::for_locale("en-US");
+        assert_eq!(en.get("room"), "Room");
+        assert_eq!(en.get("no_such_key"), "no_such_key");
+        assert_eq!(Translations::for_locale("fr").get("room"), "Kamer");
+
+        assert_eq!(locale_of_file("en-GB.yml"), Some("en-GB"));
+        assert_eq!(locale_of_file("config.yml"), None);
+    }
+
+    #[test]
+    fn test_plural_and_interpolation() {
+        let translations: Translations = serde_yaml::from_str(
+            r"
+            greeting: 'Hallo {name}, {unknown}'
+            guests_verified:
+                one: '{count} gast geverifieerd'
+                other: '{count} gasten geverifieerd'
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(
+            translations.format("greeting", &[("name", "Henk")]),
+            "Hallo Henk, {unknown}"
+        );
+        assert_eq!(
+            translations.plural("guests_verified", 1, &[]),
+            "1 gast geverifieerd"
+        );
+        assert_eq!(
+            translations.plural("guests_verified", 3, &[]),
+            "3 gasten geverifieerd"
+        );
+        assert_eq!(
+            translations.get("guests_verified"),
+            "{count} gasten geverifieerd"
+        );
+
+        let mut tera = tera::Tera::default();
+        tera.register_filter("interpolate", interpolate_filter);
+        tera.add_raw_template(
+            "test",
+            "{{ translations.guests_verified | interpolate(count=n) }}",
+        )
+        .unwrap();
+        let mut context = tera::Context::new();
+        context.insert("translations", &translations);
+        context.insert("n", &0);
+        assert_eq!(
+            tera.render("test", &context).unwrap(),
+            "0 gasten geverifieerd"
+        );
+    }
+
+    #[test]
+    fn test_localdatetime() {
+        assert_eq!(Translations::for_locale("en-US").locale(), "en");
+        assert_eq!(Translations::for_locale("fr").locale(), DEFAULT_LOCALE);
+
+        let mut tera = tera::Tera::default();
+        tera.register_filter("localdatetime", localdatetime_filter);
+        tera.add_raw_template("test", "{{ ts | localdatetime(locale=locale) }}")
+            .unwrap();
+        let render = |ts: serde_json::Value, locale: &str| {
+            let mut context = tera::Context::new();
+            context.insert("ts", &ts);
+            context.insert("locale", locale);
+            tera.render("test", &context)
+        };
+
+        // Rendered in Europe/Amsterdam, on summer time
+        assert_eq!(
+            render(serde_json::json!(1_600_000_000), "nl").unwrap(),
+            "13-09-2020 14:26"
+        );
+        assert_eq!(
+            render(serde_json::json!("2020-09-13T12:26:40Z"), "en-GB").unwrap(),
+            "13/09/2020 14:26"
+        );
+        assert_eq!(render(serde_json::Value::Null, "nl").unwrap(), "");
+        assert!(render(serde_json::json!("yesterday"), "nl").is_err());
+    }
+
+    #[cfg(feature = "rocket")]
+    #[rocket::get("/")]
+    fn room(translations: Translations) -> String {
+        translations.get("room").to_string()
+    }
+
+    #[cfg(feature = "rocket")]
+    #[test]
+    fn test_translations_guard() {
+        use rocket::{http::Header, local::blocking::Client};
+
+        let rocket = rocket::build().mount("/", rocket::routes![room]);
+        let client = Client::tracked(rocket).unwrap();
+        let accept_dutch = || Header::new("Accept-Language", "nl-NL");
+
+        let response = client.get("/").header(accept_dutch()).dispatch();
+        assert_eq!(response.into_string().unwrap(), "Kamer");
+
+        let response = client.get("/?lang=en").header(accept_dutch()).dispatch();
+        assert_eq!(response.cookies().get(LANG_COOKIE).unwrap().value(), "en");
+        assert_eq!(response.into_string().unwrap(), "Room");
+
+        let response = client.get("/").header(accept_dutch()).dispatch();
+        assert_eq!(response.into_string().unwrap(), "Room");
+
+        let response = client.get("/?lang=xx").header(accept_dutch()).dispatch();
+        assert_eq!(response.into_string().unwrap(), "Room");
+    }
+
+    #[cfg(all(feature = "axum", feature = "auth_during_comm"))]
+    #[test]
+    fn test_translations_extractor() {
+        use axum::{routing::get, Router};
+
+        async fn room(translations: Translations) -> String {
+            translations.get("room").to_string()
+        }
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let url = crate::test_helpers::serve_router(Router::new().route("/", get(room)));
+            let client = reqwest::Client::new();
+            let get = |query: &str, cookie: Option<&str>| {
+                let mut request = client
+                    .get(format!("{}/{}", url, query))
+                    .header("Accept-Language", "nl-NL");
+                if let Some(cookie) = cookie {
+                    request = request.header("Cookie", cookie);
+                }
+                async move { request.send().await.unwrap().text().await.unwrap() }
+            };
+
+            assert_eq!(get("", None).await, "Kamer");
+            assert_eq!(get("?lang=en", None).await, "Room");
+            assert_eq!(get("", Some("other=1; lang=en")).await, "Room");
+            assert_eq!(get("?lang=xx", None).await, "Kamer");
+        });
+    }
+
+    #[test]
+    fn test_coverage() {
+        let mut keys = BTreeSet::new();
+        template_keys(
+            "{{ translations.title }} {{ translations[kv.0] }} {{translations.room}}",
+            &mut keys,
+        );
+        assert_eq!(keys.into_iter().collect::<Vec<_>>(), vec!["room", "title"]);
+
+        for coverage in check_coverage() {
+            assert!(coverage.is_complete(), "{:?}", coverage);
+        }
+    }
+
+    #[cfg(feature = "platform_token")]
+    #[test]
+    fn test_strict_translations() {
+        let keys = template_translation_keys("base.html");
+        assert!(keys.contains("title"));
+        // Included from credentials.html
+        assert!(keys.contains("unexpected_attributes"));
+
+        let keys: Vec<String> = vec!["room".to_string(), "no_such_key".to_string()];
+        assert_eq!(
+            Translations::for_locale("en-GB").untranslated(&keys),
+            vec!["no_such_key"]
+        );
+        assert!(set_default_locale("xx").is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parse_translations(source in "\\PC*") {
+            // Only the absence of panics matters here
+            let _ = Translations::parse(&source);
+        }
+
+        #[test]
+        fn prop_interpolate(message in "\\PC*", value in "\\PC*") {
+            let result = interpolate(&message, &[("name", &value)]);
+            if !message.contains('{') {
+                prop_assert_eq!(result, message);
+            }
+        }
+    }
+}
@@ -1,16 +1,40 @@
 use core::str;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::{Deref, DerefMut};
 
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+use crate::{config::Config, error::Error};
 
 #[derive(Deserialize, Debug)]
+#[non_exhaustive]
 pub struct StartRequest {
     pub purpose: String,
     pub auth_method: String,
 }
 
+impl StartRequest {
+    pub fn new(purpose: impl Into<String>, auth_method: impl Into<String>) -> Self {
+        StartRequest {
+            purpose: purpose.into(),
+            auth_method: auth_method.into(),
+        }
+    }
+
+    /// Check the request against the configured purposes and their
+    /// permitted authentication methods, before contacting the core
+    pub fn validate(&self, config: &Config) -> Result<(), Error> {
+        config.validate_auth_method(&self.purpose, &self.auth_method)
+    }
+}
+
 /// Parameters expected by the auth-select widget
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct AuthSelectParams {
     /// The session purpose
     pub purpose: String,
@@ -20,18 +44,351 @@ pub struct AuthSelectParams {
     pub display_name: String,
 }
 
-#[derive(Serialize, Debug)]
+impl AuthSelectParams {
+    pub fn new(
+        purpose: impl Into<String>,
+        start_url: impl Into<String>,
+        display_name: impl Into<String>,
+    ) -> Self {
+        AuthSelectParams {
+            purpose: purpose.into(),
+            start_url: start_url.into(),
+            display_name: display_name.into(),
+        }
+    }
+}
+
+/// Authentication result of a guest as received, still encrypted, along
+/// with what is needed to render it
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct GuestAuthResult {
     pub purpose: Option<String>,
     pub name: Option<String>,
+    /// The encrypted auth result JWE, if the guest authenticated
     pub auth_result: Option<String>,
+    /// Whether the result is older than the room allows, so the guest
+    /// should verify again
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+}
+
+impl GuestAuthResult {
+    pub fn new(auth_result: impl Into<String>) -> Self {
+        GuestAuthResult {
+            auth_result: Some(auth_result.into()),
+            ..GuestAuthResult::default()
+        }
+    }
+
+    pub fn with_purpose(mut self, purpose: impl Into<String>) -> Self {
+        self.purpose = Some(purpose.into());
+        self
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn with_stale(mut self, stale: bool) -> Self {
+        self.stale = stale;
+        self
+    }
 }
 
-#[derive(Serialize, Debug)]
+#[cfg(feature = "session_db")]
+impl From<crate::session::Session> for GuestAuthResult {
+    fn from(session: crate::session::Session) -> Self {
+        GuestAuthResult {
+            purpose: Some(session.guest_token.purpose),
+            name: Some(session.guest_token.name),
+            auth_result: session.auth_result,
+            stale: false,
+        }
+    }
+}
+
+/// Version of the JSON representation of [`AuthResultSet`]
+pub const AUTH_RESULT_SET_VERSION: u32 = 1;
+
+/// Authentication results of the guests in one or more rooms, keyed by
+/// session id. Iterates and serializes in key order, so that output does not
+/// depend on the order sessions were read in. Serializes as
+/// `{"version": 1, "auth_results": {...}}`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(into = "VersionedAuthResultSet", try_from = "VersionedAuthResultSet")]
+pub struct AuthResultSet(BTreeMap<String, GuestAuthResult>);
+
+#[derive(Serialize, Deserialize)]
+struct VersionedAuthResultSet {
+    version: u32,
+    auth_results: BTreeMap<String, GuestAuthResult>,
+}
+
+impl From<AuthResultSet> for VersionedAuthResultSet {
+    fn from(set: AuthResultSet) -> Self {
+        VersionedAuthResultSet {
+            version: AUTH_RESULT_SET_VERSION,
+            auth_results: set.0,
+        }
+    }
+}
+
+impl TryFrom<VersionedAuthResultSet> for AuthResultSet {
+    type Error = String;
+    fn try_from(versioned: VersionedAuthResultSet) -> Result<Self, String> {
+        if versioned.version != AUTH_RESULT_SET_VERSION {
+            return Err(format!(
+                "Unsupported auth result set version {}",
+                versioned.version
+            ));
+        }
+        Ok(AuthResultSet(versioned.auth_results))
+    }
+}
+
+impl AuthResultSet {
+    pub fn new() -> Self {
+        AuthResultSet::default()
+    }
+
+    /// Add or replace the result of a session
+    pub fn insert(&mut self, session_id: impl Into<String>, result: GuestAuthResult) {
+        self.0.insert(session_id.into(), result);
+    }
+
+    pub fn get(&self, session_id: &str) -> Option<&GuestAuthResult> {
+        self.0.get(session_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Session ids and their results, in session id order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &GuestAuthResult)> {
+        self.0
+            .iter()
+            .map(|(session_id, result)| (session_id.as_str(), result))
+    }
+
+    /// Add the results of another set, such as those of another room.
+    /// Results of sessions in both sets are taken from `other`.
+    pub fn merge(&mut self, other: AuthResultSet) {
+        self.0.extend(other.0);
+    }
+
+    /// Decrypt the results of all sessions that completed authentication,
+    /// in session id order
+    #[cfg(feature = "platform_token")]
+    pub fn credentials(&self, config: &Config) -> Result<Vec<Credentials>, Error> {
+        crate::credentials::collect_session_credentials(
+            self.iter()
+                .map(|(session_id, result)| (Some(session_id), result)),
+            config,
+        )
+    }
+
+    /// Versioned JSON representation, stable across releases of this crate
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+impl FromIterator<(String, GuestAuthResult)> for AuthResultSet {
+    fn from_iter<I: IntoIterator<Item = (String, GuestAuthResult)>>(iter: I) -> Self {
+        AuthResultSet(iter.into_iter().collect())
+    }
+}
+
+#[cfg(feature = "session_db")]
+impl AuthResultSet {
+    /// Results of the given sessions, marked stale where they are older
+    /// than the room allows
+    pub fn from_sessions(
+        sessions: Vec<crate::session::Session>,
+        retention: &crate::session::RetentionConfig,
+    ) -> Self {
+        sessions
+            .into_iter()
+            .map(|session| {
+                let stale = session.is_stale(retention);
+                (
+                    session.guest_token.id.clone(),
+                    GuestAuthResult::from(session).with_stale(stale),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Value left out of Debug output, so that it does not end up in logs,
+/// error messages or panics. Serializes as the value itself.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Redacted<T>(T);
+
+impl<T> Redacted<T> {
+    pub fn new(value: T) -> Self {
+        Redacted(value)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Redacted<T> {
+    fn from(value: T) -> Self {
+        Redacted(value)
+    }
+}
+
+impl<T> Deref for Redacted<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Redacted<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Zeroize> Zeroize for Redacted<T> {
+    fn zeroize(&mut self) {
+        self.0.zeroize()
+    }
+}
+
+impl<T> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+/// Decrypted attributes of a guest, as rendered for hosts
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct Credentials {
     pub purpose: Option<String>,
     pub name: Option<String>,
-    pub attributes: HashMap<String, String>,
+    /// Attribute values by name. Left out of Debug output.
+    pub attributes: Redacted<HashMap<String, String>>,
+    /// Names of received attributes not allowed for the purpose, which were left out
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unexpected_attributes: Vec<String>,
+    /// Whether the guest authenticated longer ago than the room allows
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+    /// Whether the auth result expired, in which case no attributes are shown
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub expired: bool,
+    /// Link for hosts to have the guest verify again, if the auth result expired
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reverify_url: Option<String>,
+}
+
+impl Credentials {
+    pub fn new(attributes: HashMap<String, String>) -> Self {
+        Credentials {
+            attributes: attributes.into(),
+            ..Credentials::default()
+        }
+    }
+
+    pub fn with_purpose(mut self, purpose: impl Into<String>) -> Self {
+        self.purpose = Some(purpose.into());
+        self
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// The purpose and name of the guest these credentials were decrypted
+    /// from, with the given attributes
+    pub fn for_guest(
+        guest_auth_result: &GuestAuthResult,
+        attributes: HashMap<String, String>,
+    ) -> Self {
+        Credentials {
+            purpose: guest_auth_result.purpose.clone(),
+            name: guest_auth_result.name.clone(),
+            attributes: attributes.into(),
+            unexpected_attributes: vec![],
+            stale: guest_auth_result.stale,
+            expired: false,
+            reverify_url: None,
+        }
+    }
+
+    /// The purpose and name of a guest whose auth result expired, without
+    /// attributes
+    pub fn expired(guest_auth_result: &GuestAuthResult, reverify_url: Option<String>) -> Self {
+        Credentials {
+            expired: true,
+            reverify_url,
+            ..Credentials::for_guest(guest_auth_result, HashMap::new())
+        }
+    }
+}
+
+/// Credentials with their attributes sorted by name, as passed to templates
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SortedCredentials {
+    pub purpose: Option<String>,
+    pub name: Option<String>,
+    pub attributes: Redacted<Vec<(String, String)>>,
+    pub unexpected_attributes: Vec<String>,
+    pub stale: bool,
+    pub expired: bool,
+    pub reverify_url: Option<String>,
+}
+
+/// Overwrites the attribute values, for when the credentials have been rendered
+impl Zeroize for Credentials {
+    fn zeroize(&mut self) {
+        self.attributes.values_mut().for_each(String::zeroize);
+        self.attributes.clear();
+    }
+}
+
+/// Overwrites the attributes, for when the credentials have been rendered
+impl Zeroize for SortedCredentials {
+    fn zeroize(&mut self) {
+        self.attributes.zeroize();
+    }
+}
+
+impl From<Credentials> for SortedCredentials {
+    fn from(credentials: Credentials) -> Self {
+        let mut attributes = credentials
+            .attributes
+            .into_inner()
+            .into_iter()
+            .collect::<Vec<(String, String)>>();
+
+        attributes.sort_by(|x, y| x.0.cmp(&y.0));
+
+        SortedCredentials {
+            purpose: credentials.purpose,
+            name: credentials.name,
+            attributes: attributes.into(),
+            unexpected_attributes: credentials.unexpected_attributes,
+            stale: credentials.stale,
+            expired: credentials.expired,
+            reverify_url: credentials.reverify_url,
+        }
+    }
 }
 
 #[cfg(feature = "platform_token")]
@@ -39,10 +396,11 @@ pub use platform_token::*;
 
 #[cfg(feature = "platform_token")]
 pub mod platform_token {
-    use crate::jwt::JwtError;
+    use crate::{config::Config, error::Error, jwt::JwtError};
     use core::str;
-    use josekit::jws::JwsVerifier;
+    use josekit::{jws::JwsVerifier, jwt::JwtPayload};
     use serde::{de::DeserializeOwned, Deserialize, Serialize};
+    use std::time::{SystemTime, UNIX_EPOCH};
     use strum_macros::{EnumString, ToString};
 
     #[derive(Deserialize, Debug, Serialize, ToString, Clone, EnumString)]
@@ -54,21 +412,49 @@ pub mod platform_token {
         Guest,
     }
 
-    #[derive(Deserialize, Debug)]
+    #[derive(Deserialize, Serialize, Debug)]
+    #[non_exhaustive]
     pub struct HostToken {
         pub id: String,
         pub domain: SessionDomain,
         #[serde(rename = "roomId")]
         pub room_id: String,
         pub instance: String,
+        /// `iat` claim of the JWT the token was read from, in seconds since the epoch
+        #[serde(skip)]
+        pub issued_at: Option<u64>,
+        /// `exp` claim of the JWT the token was read from, in seconds since the epoch
+        #[serde(skip)]
+        pub expires_at: Option<u64>,
+    }
+
+    impl HostToken {
+        /// Token of a user hosting the given room
+        pub fn new(
+            id: impl Into<String>,
+            room_id: impl Into<String>,
+            instance: impl Into<String>,
+        ) -> Self {
+            HostToken {
+                id: id.into(),
+                domain: SessionDomain::User,
+                room_id: room_id.into(),
+                instance: instance.into(),
+                issued_at: None,
+                expires_at: None,
+            }
+        }
     }
 
     #[derive(Deserialize, Serialize, Debug, Clone)]
+    #[non_exhaustive]
     pub struct GuestToken {
         pub id: String,
         pub domain: SessionDomain,
         #[serde(rename = "redirectUrl")]
         pub redirect_url: String,
+        /// Display name of the guest, sanitized when the token is parsed
+        #[serde(deserialize_with = "crate::util::deserialize_name")]
         pub name: String,
         #[serde(rename = "roomId")]
         pub room_id: String,
@@ -79,15 +465,284 @@ pub mod platform_token {
     pub trait FromPlatformJwt: Sized + DeserializeOwned {
         fn from_platform_jwt(jwt: &str, validator: &dyn JwsVerifier) -> Result<Self, JwtError> {
             let (payload, _) = josekit::jwt::decode_with_verifier(jwt, validator)?;
+            check_expiry(&payload)?;
+            let claim = payload
+                .claim("payload")
+                .ok_or(JwtError::InvalidStructure("payload"))?;
+            let token: Self = serde_json::from_value(claim.clone())?;
+            Ok(token.with_jwt_claims(&payload))
+        }
+
+        /// Take what is needed from the claims of the JWT itself, besides its
+        /// `payload` claim. Nothing by default.
+        fn with_jwt_claims(self, _payload: &JwtPayload) -> Self {
+            self
+        }
+
+        /// Verify a token with the validator for the instance it claims to belong to,
+        /// for plugins serving multiple communication platform instances
+        fn from_instance_platform_jwt<'a>(
+            jwt: &str,
+            validator_for: impl FnOnce(&str) -> &'a dyn JwsVerifier,
+        ) -> Result<Self, JwtError> {
+            let instance = unverified_instance(jwt)?;
+            let (payload, _) = josekit::jwt::decode_with_verifier(jwt, validator_for(&instance))?;
+            check_expiry(&payload)?;
             let claim = payload
                 .claim("payload")
                 .ok_or(JwtError::InvalidStructure("payload"))?;
-            let payload = serde_json::from_value(claim.clone())?;
-            Ok(payload)
+            if claim.get("instance").and_then(|i| i.as_str()) != Some(&instance) {
+                return Err(JwtError::InvalidStructure("instance"));
+            }
+            let token: Self = serde_json::from_value(claim.clone())?;
+            Ok(token.with_jwt_claims(&payload))
+        }
+    }
+
+    /// Platform tokens need not expire, but those that do are rejected afterwards
+    fn check_expiry(payload: &JwtPayload) -> Result<(), JwtError> {
+        match payload.expires_at() {
+            Some(expires_at) if expires_at <= SystemTime::now() => Err(JwtError::Expired),
+            _ => Ok(()),
+        }
+    }
+
+    /// Read the instance a platform token claims to belong to, without verifying the token
+    fn unverified_instance(jwt: &str) -> Result<String, JwtError> {
+        let payload = jwt
+            .split('.')
+            .nth(1)
+            .and_then(|payload| base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok())
+            .ok_or(JwtError::InvalidStructure("payload"))?;
+        let payload: serde_json::Value = serde_json::from_slice(&payload)?;
+        payload["payload"]["instance"]
+            .as_str()
+            .map(String::from)
+            .ok_or(JwtError::InvalidStructure("instance"))
+    }
+
+    impl GuestToken {
+        /// Token of a guest joining the given room for a purpose, without a
+        /// display name until one is set with [`GuestToken::with_name`]
+        pub fn new(
+            id: impl Into<String>,
+            room_id: impl Into<String>,
+            instance: impl Into<String>,
+            purpose: impl Into<String>,
+            redirect_url: impl Into<String>,
+        ) -> Self {
+            GuestToken {
+                id: id.into(),
+                domain: SessionDomain::Guest,
+                redirect_url: redirect_url.into(),
+                name: String::new(),
+                room_id: room_id.into(),
+                instance: instance.into(),
+                purpose: purpose.into(),
+            }
+        }
+
+        /// Set the display name, sanitized as when the token is parsed
+        pub fn with_name(mut self, name: &str) -> Self {
+            self.name = crate::util::sanitize_name(name);
+            self
+        }
+
+        pub fn with_domain(mut self, domain: SessionDomain) -> Self {
+            self.domain = domain;
+            self
+        }
+
+        /// Check the token against the configured purposes and redirect hosts
+        pub fn validate(&self, config: &Config) -> Result<(), Error> {
+            config.validate_purpose(&self.purpose)?;
+            config.validate_redirect_url(&self.redirect_url)
         }
     }
 
     impl FromPlatformJwt for GuestToken {}
 
-    impl FromPlatformJwt for HostToken {}
+    impl FromPlatformJwt for HostToken {
+        fn with_jwt_claims(self, payload: &JwtPayload) -> Self {
+            let unix_time = |time: SystemTime| {
+                time.duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0)
+            };
+            HostToken {
+                issued_at: payload.issued_at().map(unix_time),
+                expires_at: payload.expires_at().map(unix_time),
+                ..self
+            }
+        }
+    }
+    #[cfg(all(test, feature = "auth_during_comm"))]
+    mod tests {
+        use super::*;
+        use crate::test_helpers::{
+            sign_platform_token, test_config, test_guest_token, GUEST_SECRET,
+        };
+        use proptest::prelude::*;
+
+        #[test]
+        fn test_from_instance_platform_jwt() {
+            let config = test_config();
+            let config = config.auth_during_comm_config();
+
+            let mut token = test_guest_token("room");
+            token.instance = "tenant".to_string();
+            token.name = "Henk\u{202e}  Dieter\u{7}".to_string();
+            let jwt = sign_platform_token(&token, GUEST_SECRET);
+
+            let mut requested = None;
+            let parsed = GuestToken::from_instance_platform_jwt(&jwt, |instance| {
+                requested = Some(instance.to_string());
+                config.guest_validator_for(instance)
+            })
+            .unwrap();
+            assert_eq!(requested.as_deref(), Some("tenant"));
+            assert_eq!(parsed.instance, "tenant");
+            assert_eq!(parsed.name, "Henk Dieter");
+
+            assert!(
+                GuestToken::from_instance_platform_jwt("not.a.jwt", |instance| {
+                    config.guest_validator_for(instance)
+                })
+                .is_err()
+            );
+        }
+
+        /// A JWT with a well-formed header, the given payload and an arbitrary signature
+        fn craft_jwt(payload: &[u8], signature: &str) -> String {
+            format!(
+                "{}.{}.{}",
+                base64::encode_config(r#"{"alg":"HS256","typ":"JWT"}"#, base64::URL_SAFE_NO_PAD),
+                base64::encode_config(payload, base64::URL_SAFE_NO_PAD),
+                signature
+            )
+        }
+
+        proptest! {
+            #[test]
+            fn prop_malformed_platform_jwt(jwt in "\\PC*") {
+                let config = test_config();
+                let config = config.auth_during_comm_config();
+                prop_assert!(GuestToken::from_platform_jwt(&jwt, config.guest_validator()).is_err());
+                prop_assert!(GuestToken::from_instance_platform_jwt(&jwt, |instance| {
+                    config.guest_validator_for(instance)
+                })
+                .is_err());
+            }
+
+            #[test]
+            fn prop_forged_platform_jwt(
+                payload in "\\PC*",
+                instance in "\\PC*",
+                signature in "[A-Za-z0-9_-]{0,64}",
+            ) {
+                let config = test_config();
+                let config = config.auth_during_comm_config();
+                let claims = serde_json::json!({ "payload": { "instance": instance } });
+                for jwt in [
+                    craft_jwt(payload.as_bytes(), &signature),
+                    craft_jwt(claims.to_string().as_bytes(), &signature),
+                ]
+                .iter()
+                {
+                    prop_assert!(HostToken::from_platform_jwt(jwt, config.host_validator()).is_err());
+                    prop_assert!(HostToken::from_instance_platform_jwt(jwt, |instance| {
+                        config.host_validator_for(instance)
+                    })
+                    .is_err());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_credential_types() {
+        let guest_auth_result = GuestAuthResult::new("jwe")
+            .with_purpose("report_move")
+            .with_name("Henk Dieter");
+        let json = serde_json::to_value(&guest_auth_result).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "purpose": "report_move",
+                "name": "Henk Dieter",
+                "auth_result": "jwe",
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<GuestAuthResult>(json).unwrap(),
+            guest_auth_result
+        );
+
+        let attributes: HashMap<String, String> = vec![
+            ("email".to_string(), "hd@example.com".to_string()),
+            ("age".to_string(), "42".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let credentials = Credentials::for_guest(&guest_auth_result, attributes.clone());
+        assert_eq!(
+            credentials,
+            Credentials::new(attributes)
+                .with_purpose("report_move")
+                .with_name("Henk Dieter")
+        );
+        assert!(!format!("{:?}", credentials).contains("hd@example.com"));
+        let json = serde_json::to_string(&credentials).unwrap();
+        assert!(json.contains("hd@example.com"));
+        assert!(!json.contains("unexpected_attributes"));
+        assert_eq!(
+            serde_json::from_str::<Credentials>(&json).unwrap(),
+            credentials
+        );
+
+        let mut sorted = SortedCredentials::from(credentials.clone());
+        assert_eq!(sorted.attributes[0].0, "age");
+
+        let mut credentials = credentials;
+        credentials.zeroize();
+        assert!(credentials.attributes.is_empty());
+        assert_eq!(credentials.name.as_deref(), Some("Henk Dieter"));
+        sorted.zeroize();
+        assert!(sorted.attributes.is_empty());
+    }
+
+    #[test]
+    fn test_auth_result_set() {
+        let mut set: AuthResultSet = vec![
+            ("b".to_string(), GuestAuthResult::new("jwe-b")),
+            ("a".to_string(), GuestAuthResult::default()),
+        ]
+        .into_iter()
+        .collect();
+        let mut other = AuthResultSet::new();
+        other.insert("c", GuestAuthResult::new("jwe-c"));
+        other.insert("a", GuestAuthResult::new("jwe-a"));
+        set.merge(other);
+
+        assert_eq!(set.len(), 3);
+        assert_eq!(set.get("a").unwrap().auth_result.as_deref(), Some("jwe-a"));
+        assert_eq!(
+            set.iter().map(|(id, _)| id).collect::<Vec<_>>(),
+            vec!["a", "b", "c"]
+        );
+
+        let json = set.to_json().unwrap();
+        assert!(json.starts_with(r#"{"version":1,"auth_results":{"a":"#));
+        assert_eq!(serde_json::from_str::<AuthResultSet>(&json).unwrap(), set);
+        assert!(serde_json::from_value::<AuthResultSet>(serde_json::json!({
+            "version": 2,
+            "auth_results": {},
+        }))
+        .is_err());
+    }
 }
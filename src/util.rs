@@ -1,4 +1,20 @@
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use crate::error::Error;
+use rand::{distributions::Alphanumeric, thread_rng, Rng, RngCore};
+use reqwest::Url;
+use subtle::ConstantTimeEq;
+
+/// Query parameters of a request to an axum handler, in order
+#[cfg(feature = "axum")]
+pub(crate) async fn query_pairs<B: Send>(
+    request: &mut axum::extract::RequestParts<B>,
+) -> Vec<(String, String)> {
+    use axum::extract::{FromRequest, Query};
+
+    Query::<Vec<(String, String)>>::from_request(request)
+        .await
+        .map(|Query(pairs)| pairs)
+        .unwrap_or_default()
+}
 
 /// Generate a random string for use as unique identification code
 pub fn random_string(len: usize) -> String {
@@ -8,3 +24,265 @@ pub fn random_string(len: usize) -> String {
         .map(char::from)
         .collect()
 }
+
+/// Generate a token of `bytes` random bytes, encoded as URL-safe base64 without padding
+pub fn random_token(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    thread_rng().fill_bytes(&mut buf);
+    base64::encode_config(buf, base64::URL_SAFE_NO_PAD)
+}
+
+/// Compare two strings in constant time, for use with secrets such as attribute ids
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.as_bytes().ct_eq(b.as_bytes()).into()
+}
+
+/// Maximum number of characters in a display name, such as a guest's name
+pub const MAX_NAME_LENGTH: usize = 100;
+
+/// Characters controlling text direction, which can make a name display
+/// differently from what it contains
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{200e}' | '\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Normalize a user supplied display name: control characters are removed,
+/// whitespace is collapsed and the name is cut off at [`MAX_NAME_LENGTH`]
+/// characters.
+pub fn sanitize_name(name: &str) -> String {
+    name.split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| !c.is_control() && !is_bidi_control(*c))
+                .collect::<String>()
+        })
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(MAX_NAME_LENGTH)
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+/// Deserialize a display name, sanitizing it with [`sanitize_name`]
+pub fn deserialize_name<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let name: String = serde::Deserialize::deserialize(deserializer)?;
+    Ok(sanitize_name(&name))
+}
+
+/// Escape HTML special characters, for output that is not escaped by the
+/// template engine, such as Markdown or CSV
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Append a relative path to a base url, such as the external url.
+/// Fails if the result would point outside of the base url.
+pub fn join_url(base: &str, path: &str) -> Result<String, Error> {
+    let base = Url::parse(&format!("{}/", base.trim_end_matches('/')))
+        .map_err(|_| Error::BadRequest("Invalid base URL"))?;
+    let joined = base
+        .join(path.trim_start_matches('/'))
+        .map_err(|_| Error::BadRequest("Invalid URL path"))?;
+
+    if joined.origin() != base.origin() || !joined.path().starts_with(base.path()) {
+        return Err(Error::BadRequest("URL path escapes base URL"));
+    }
+
+    Ok(joined.to_string())
+}
+
+/// Percent-encode everything but unreserved characters, for use in a path segment
+pub(crate) fn encode_path_segment(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Fill the `{name}` placeholders of a relative URL path pattern with the
+/// given values, percent-encoded, and append the result to `base_url`.
+/// Fails on unknown placeholders and on patterns that are not plain relative
+/// paths, so a pattern cannot redirect users elsewhere.
+pub fn fill_url_pattern(
+    base_url: &str,
+    pattern: &str,
+    values: &[(&str, &str)],
+) -> Result<String, Error> {
+    if pattern.starts_with("//")
+        || pattern.contains([':', '?', '#', '\\'])
+        || pattern.split('/').any(|segment| segment == "..")
+    {
+        return Err(Error::BadRequest("URL pattern is not a relative path"));
+    }
+
+    let mut path = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        path.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or(Error::BadRequest("Unclosed placeholder in URL pattern"))?
+            + start;
+        let name = &rest[start + 1..end];
+        let value = values
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+            .ok_or(Error::BadRequest("Unknown placeholder in URL pattern"))?;
+        path.push_str(&encode_path_segment(value));
+        rest = &rest[end + 1..];
+    }
+    path.push_str(rest);
+
+    join_url(base_url, &path)
+}
+
+/// Whether `host` matches one of the host patterns, either exactly or, for
+/// patterns like `*.example.com`, as a subdomain
+fn host_allowed(host: &str, allowed_hosts: &[String]) -> bool {
+    allowed_hosts.iter().any(|pattern| {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix("*.") {
+            Some(domain) => matches!(
+                host.strip_suffix(domain),
+                Some(subdomain) if subdomain.len() > 1 && subdomain.ends_with('.')
+            ),
+            None => host == pattern,
+        }
+    })
+}
+
+/// Check that users can safely be redirected to `url`: an absolute http(s)
+/// URL without credentials, on one of the allowed hosts. Any host is allowed
+/// if `allowed_hosts` is empty.
+pub fn validate_redirect_url(url: &str, allowed_hosts: &[String]) -> Result<(), Error> {
+    let url = Url::parse(url).map_err(|_| Error::BadRequest("Invalid redirect URL"))?;
+    if !matches!(url.scheme(), "https" | "http")
+        || !url.username().is_empty()
+        || url.password().is_some()
+    {
+        return Err(Error::BadRequest("Invalid redirect URL"));
+    }
+
+    let host = url
+        .host_str()
+        .ok_or(Error::BadRequest("Invalid redirect URL"))?;
+    if allowed_hosts.is_empty() || host_allowed(host, allowed_hosts) {
+        Ok(())
+    } else {
+        Err(Error::Forbidden("Redirect URL not allowed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_token() {
+        let token = random_token(32);
+        assert_eq!(token.len(), 43);
+        assert!(token
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_ne!(token, random_token(32));
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("  Henk \t Dieter\n"), "Henk Dieter");
+        assert_eq!(sanitize_name("Henk\u{0}\u{1b}[31m"), "Henk[31m");
+        assert_eq!(sanitize_name("\u{202e}retseiD"), "retseiD");
+        assert_eq!(
+            sanitize_name(&"é".repeat(200)).chars().count(),
+            MAX_NAME_LENGTH
+        );
+        assert_eq!(
+            escape_html("<b>Henk & 'Dieter'</b>"),
+            "&lt;b&gt;Henk &amp; &#x27;Dieter&#x27;&lt;/b&gt;"
+        );
+    }
+
+    #[test]
+    fn test_fill_url_pattern() {
+        let values = [("purpose", "report_move"), ("session_id", "a/b?c")];
+        assert_eq!(
+            fill_url_pattern(
+                "https://example.com/base",
+                "start/{purpose}/{session_id}",
+                &values
+            )
+            .unwrap(),
+            "https://example.com/base/start/report_move/a%2Fb%3Fc"
+        );
+        assert!(fill_url_pattern("https://example.com", "start/{unknown}", &values).is_err());
+        assert!(fill_url_pattern("https://example.com", "start/{purpose", &values).is_err());
+        assert!(fill_url_pattern("https://example.com", "//evil.com/{purpose}", &values).is_err());
+        assert!(fill_url_pattern("https://example.com", "https://evil.com", &values).is_err());
+        assert!(fill_url_pattern("https://example.com/base", "../{purpose}", &values).is_err());
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq("abc123", "abc123"));
+        assert!(!constant_time_eq("abc123", "abc124"));
+        assert!(!constant_time_eq("abc123", "abc1234"));
+        assert!(constant_time_eq("", ""));
+    }
+
+    #[test]
+    fn test_join_url() {
+        assert_eq!(
+            join_url("https://example.com", "auth/start").unwrap(),
+            "https://example.com/auth/start"
+        );
+        assert_eq!(
+            join_url("https://example.com/plugin/", "/auth/start?x=1").unwrap(),
+            "https://example.com/plugin/auth/start?x=1"
+        );
+        assert!(join_url("https://example.com/plugin", "../admin").is_err());
+        assert!(join_url("https://example.com", "https://evil.com/").is_err());
+        assert!(join_url("https://example.com", "//evil.com/").is_ok());
+        assert!(join_url("not a url", "auth").is_err());
+    }
+
+    #[test]
+    fn test_validate_redirect_url() {
+        let allowed = vec!["comm.example.com".to_string(), "*.example.org".to_string()];
+        assert!(validate_redirect_url("https://comm.example.com/room/1", &allowed).is_ok());
+        assert!(validate_redirect_url("https://COMM.example.com/", &allowed).is_ok());
+        assert!(validate_redirect_url("https://a.b.example.org/", &allowed).is_ok());
+        assert!(validate_redirect_url("https://example.org/", &allowed).is_err());
+        assert!(validate_redirect_url("https://evilexample.org/", &allowed).is_err());
+        assert!(validate_redirect_url("https://evil.com/", &allowed).is_err());
+        assert!(validate_redirect_url("https://comm.example.com@evil.com/", &allowed).is_err());
+        assert!(validate_redirect_url("https://user@comm.example.com/", &allowed).is_err());
+        assert!(validate_redirect_url("javascript:alert(1)", &allowed).is_err());
+        assert!(validate_redirect_url("/relative", &allowed).is_err());
+
+        assert!(validate_redirect_url("https://evil.com/", &[]).is_ok());
+        assert!(validate_redirect_url("javascript:alert(1)", &[]).is_err());
+    }
+}
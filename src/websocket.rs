@@ -0,0 +1,199 @@
+use crate::{
+    config::Config,
+    error::Error,
+    events::{subscribe, SessionEvent},
+    jwt::JwtError,
+    proxy::ClientAddr,
+    routes::verify_host_token,
+    util::random_token,
+};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Extension, Path,
+    },
+    response::Response,
+    routing::get,
+    Router,
+};
+use josekit::{
+    jws::{JwsHeader, HS256},
+    jwt::{self, JwtPayload},
+};
+use serde_json::json;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::broadcast::error::RecvError;
+
+/// Interval between heartbeats, each carrying a fresh reconnect token
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// Time a reconnect token can be used to resubscribe to a room
+const RECONNECT_TOKEN_VALIDITY: Duration = Duration::from_secs(3600);
+
+lazy_static! {
+    // Reconnect tokens are only meant for the process that issued them
+    static ref RECONNECT_SECRET: String = random_token(32);
+}
+
+/// Router with the WebSocket endpoint over which hosts receive updates of
+/// the credentials in their room. Connect to `/updates/<token>` with either a
+/// host token or a reconnect token received over an earlier connection.
+/// Serve it with `into_make_service_with_connect_info::<SocketAddr>()`, so
+/// clients presenting invalid host tokens can be locked out.
+pub fn router(config: Arc<Config>) -> Router {
+    Router::new()
+        .route("/updates/:token", get(updates))
+        .layer(Extension(config.trusted_proxies().clone()))
+        .layer(Extension(config))
+}
+
+async fn updates(
+    ws: WebSocketUpgrade,
+    Path(token): Path<String>,
+    client: ClientAddr,
+    Extension(config): Extension<Arc<Config>>,
+) -> Result<Response, Error> {
+    let room = match verify_reconnect_token(&token) {
+        Ok(room) => room,
+        Err(_) => {
+            let host_token = verify_host_token(&token, &config, client)?;
+            Room {
+                room_id: host_token.room_id,
+                instance: host_token.instance,
+            }
+        }
+    };
+    Ok(ws.on_upgrade(move |socket| push_updates(socket, room)))
+}
+
+/// Room a host subscribed to. Room ids are chosen by the platforms, so the
+/// instance is needed to tell rooms of different platforms apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Room {
+    room_id: String,
+    instance: String,
+}
+
+/// Push an update for every event changing the room's credentials, until
+/// either side closes the connection
+async fn push_updates(mut socket: WebSocket, room: Room) {
+    let mut events = subscribe();
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    loop {
+        let message = tokio::select! {
+            _ = heartbeat.tick() => match reconnect_token(&room) {
+                Ok(token) => json!({"type": "heartbeat", "reconnect_token": token}),
+                Err(e) => {
+                    log::warn!("Could not create reconnect token: {}", e);
+                    json!({"type": "heartbeat"})
+                }
+            },
+            event = events.recv() => match event {
+                Ok(event) if is_update_for(&event, &room) => {
+                    json!({"type": "update", "event": event})
+                }
+                Ok(_) => continue,
+                // Missed events, the host should fetch the credentials anew
+                Err(RecvError::Lagged(_)) => json!({"type": "resync"}),
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        if socket
+            .send(Message::Text(message.to_string()))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+fn is_update_for(event: &SessionEvent, room: &Room) -> bool {
+    event.room_id() == room.room_id
+        && event.instance() == room.instance
+        && event.changes_credentials()
+}
+
+/// Create a token with which a host can resubscribe to a room after losing
+/// the connection, without needing their host token again
+fn reconnect_token(room: &Room) -> Result<String, JwtError> {
+    let mut payload = JwtPayload::new();
+    payload.set_claim("room_id", Some(json!(room.room_id)))?;
+    payload.set_claim("instance", Some(json!(room.instance)))?;
+    payload.set_expires_at(&(SystemTime::now() + RECONNECT_TOKEN_VALIDITY));
+
+    let signer = HS256.signer_from_bytes(RECONNECT_SECRET.as_bytes())?;
+    Ok(jwt::encode_with_signer(
+        &payload,
+        &JwsHeader::new(),
+        &signer,
+    )?)
+}
+
+fn verify_reconnect_token(token: &str) -> Result<Room, JwtError> {
+    let verifier = HS256.verifier_from_bytes(RECONNECT_SECRET.as_bytes())?;
+    let (payload, _) = jwt::decode_with_verifier(token, &verifier)?;
+
+    match payload.expires_at() {
+        Some(expires_at) if expires_at > SystemTime::now() => {}
+        Some(_) => return Err(JwtError::Expired),
+        None => return Err(JwtError::InvalidStructure("exp")),
+    }
+    let claim = |name: &'static str| {
+        payload
+            .claim(name)
+            .and_then(|value| value.as_str())
+            .map(|value| value.to_owned())
+            .ok_or(JwtError::InvalidStructure(name))
+    };
+    Ok(Room {
+        room_id: claim("room_id")?,
+        instance: claim("instance")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reconnect_token() {
+        let room = |room_id: &str, instance: &str| Room {
+            room_id: room_id.into(),
+            instance: instance.into(),
+        };
+        let token = reconnect_token(&room("room", "test")).unwrap();
+        assert_eq!(
+            verify_reconnect_token(&token).unwrap(),
+            room("room", "test")
+        );
+        assert!(verify_reconnect_token("not a token").is_err());
+
+        let event = SessionEvent::AuthResultReceived {
+            session_id: "session".into(),
+            room_id: "room".into(),
+            instance: "test".into(),
+            attr_id: "attr".into(),
+        };
+        assert!(is_update_for(&event, &room("room", "test")));
+        assert!(!is_update_for(&event, &room("other room", "test")));
+        // The same room id at another instance is another room
+        assert!(!is_update_for(&event, &room("room", "other")));
+        assert!(!is_update_for(
+            &SessionEvent::Viewed {
+                room_id: "room".into(),
+                instance: "test".into(),
+                sessions: 1,
+            },
+            &room("room", "test")
+        ));
+    }
+}
@@ -0,0 +1,63 @@
+use id_contact_comm_common::{
+    config::Config,
+    credentials::{collect_credentials, render_credentials, RenderType},
+    error::Error,
+    test_helpers::{encrypt_auth_result, test_raw_config},
+    types::GuestAuthResult,
+};
+use std::{collections::HashMap, sync::Mutex};
+
+struct CapturingLogger(Mutex<Vec<String>>);
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger(Mutex::new(Vec::new()));
+
+#[test]
+fn test_render_logs_no_attribute_values() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let mut attributes: HashMap<String, String> = HashMap::new();
+    attributes.insert("email".to_string(), "canary@example.com".to_string());
+    attributes.insert("bsn".to_string(), "canary-999999990".to_string());
+    let guest_auth_results = vec![GuestAuthResult::new(encrypt_auth_result(attributes))
+        .with_purpose("test_purpose")
+        .with_name("Henk Dieter")];
+
+    let mut raw_config = test_raw_config();
+    let purposes: serde_yaml::Value = serde_yaml::from_str(
+        r"
+        test_purpose:
+            display_name: Test
+            attributes: [email]
+        ",
+    )
+    .unwrap();
+    raw_config.insert("purposes".into(), purposes);
+    let config: Config = serde_yaml::from_value(serde_yaml::Value::Mapping(raw_config)).unwrap();
+
+    let credentials = collect_credentials(&guest_auth_results, &config).unwrap();
+    log::debug!("Rendering {:?}", credentials);
+    for render_type in [RenderType::Json, RenderType::Html, RenderType::HtmlPage].iter() {
+        render_credentials(credentials.clone(), *render_type).unwrap();
+    }
+
+    let error = serde_json::from_str::<u32>("\"canary-999999990\"").unwrap_err();
+    log::debug!("{}", Error::from(error));
+
+    let logs = LOGGER.0.lock().unwrap();
+    // The unexpected attribute is logged by name only
+    assert!(logs.iter().any(|line| line.contains("bsn")));
+    assert!(!logs.iter().any(|line| line.contains("canary")));
+}